@@ -0,0 +1,58 @@
+/* The C interface to grepr, built with the "ffi" feature. See src/ffi.rs
+ * for how to build a library to link with. */
+
+#ifndef GREPR_H
+#define GREPR_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Flags for grepr_searcher_new */
+#define GREPR_IGNORE_CASE (1u << 0)
+#define GREPR_WORD_REGEXP (1u << 1)
+#define GREPR_LINE_REGEXP (1u << 2)
+#define GREPR_FIXED_STRINGS (1u << 3)
+#define GREPR_PERL_REGEXP (1u << 4)
+#define GREPR_INVERT_MATCH (1u << 5)
+#define GREPR_MULTILINE (1u << 6)
+
+typedef struct GreprSearcher GreprSearcher;
+typedef struct GreprMatches GreprMatches;
+
+/* One match, or a selected line without any with GREPR_INVERT_MATCH. The
+ * line isn't NUL-terminated, and stays valid until its matches are freed. */
+typedef struct GreprMatch {
+    size_t line_number;
+    size_t line_offset;
+    const uint8_t *line;
+    size_t line_len;
+    size_t start;
+    size_t end;
+} GreprMatch;
+
+/* Each function that can fail returns NULL, and if error isn't NULL sets it
+ * to a message to free with grepr_string_free. */
+GreprSearcher *grepr_searcher_new(const char *pattern, uint32_t flags, char **error);
+void grepr_searcher_free(GreprSearcher *searcher);
+
+GreprMatches *grepr_search_buffer(const GreprSearcher *searcher, const uint8_t *buf,
+                                  size_t len, char **error);
+/* Only with the "fs" feature */
+GreprMatches *grepr_search_file(const GreprSearcher *searcher, const char *path,
+                                char **error);
+
+/* Writes the next match to out, returning 0 once there are no more */
+int32_t grepr_matches_next(GreprMatches *matches, GreprMatch *out);
+void grepr_matches_free(GreprMatches *matches);
+
+void grepr_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
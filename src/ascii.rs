@@ -0,0 +1,153 @@
+//! Rewriting of patterns so that `\w`, `\d`, `\s` and `\b` only know ASCII,
+//! for --no-unicode. The `regex` crate can't turn Unicode off for a whole
+//! `&str` pattern, since `.` could then match part of a character.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A word boundary in `fancy-regex`, which has no `(?-u)` flag
+const FANCY_BOUNDARY: &str = r"(?:(?<=[[:word:]])(?![[:word:]])|(?<![[:word:]])(?=[[:word:]]))";
+const FANCY_NOT_BOUNDARY: &str = r"(?:(?<=[[:word:]])(?=[[:word:]])|(?<![[:word:]])(?![[:word:]]))";
+
+/// Rewrites the Perl classes and word boundaries of a pattern as their ASCII
+/// equivalents, leaving everything else alone. With `fancy` the pattern is
+/// for `fancy-regex`, and word boundaries are spelled out with lookaround.
+pub fn translate(pattern: &str, fancy: bool) -> String {
+    let mut out = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars().peekable();
+    // How deeply nested in brackets the next character is
+    let mut depth = 0;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let Some(escaped) = chars.next() else {
+                    out.push(c);
+                    break;
+                };
+                match escaped {
+                    'w' | 'W' | 'd' | 'D' | 's' | 'S' => {
+                        let class = posix_class(escaped);
+                        if depth > 0 {
+                            out.push_str(class);
+                        } else {
+                            out.push('[');
+                            out.push_str(class);
+                            out.push(']');
+                        }
+                    }
+                    'b' | 'B' if depth == 0 => push_boundary(&mut out, &mut chars, escaped, fancy),
+                    _ => {
+                        out.push(c);
+                        out.push(escaped);
+                    }
+                }
+            }
+            // A class such as [:alpha:] is copied whole
+            '[' if depth > 0 && chars.peek() == Some(&':') => {
+                out.push(c);
+                for c in chars.by_ref() {
+                    out.push(c);
+                    if c == ']' {
+                        break;
+                    }
+                }
+            }
+            '[' => {
+                out.push(c);
+                depth += 1;
+                // A ] straight after the opening bracket is literal
+                if let Some(c) = chars.next_if_eq(&'^') {
+                    out.push(c);
+                }
+                if let Some(c) = chars.next_if_eq(&']') {
+                    out.push(c);
+                }
+            }
+            ']' if depth > 0 => {
+                out.push(c);
+                depth -= 1;
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// The ASCII bracket class equivalent to a Perl class
+fn posix_class(escaped: char) -> &'static str {
+    match escaped {
+        'w' => "[:word:]",
+        'W' => "[:^word:]",
+        'd' => "[:digit:]",
+        'D' => "[:^digit:]",
+        's' => "[:space:]",
+        _ => "[:^space:]",
+    }
+}
+
+/// Pushes an ASCII `\b` or `\B`, along with any `{start}` style suffix
+fn push_boundary(out: &mut String, chars: &mut Peekable<Chars>, escaped: char, fancy: bool) {
+    if fancy {
+        out.push_str(if escaped == 'b' {
+            FANCY_BOUNDARY
+        } else {
+            FANCY_NOT_BOUNDARY
+        });
+        return;
+    }
+
+    out.push_str(r"(?-u:\");
+    out.push(escaped);
+    if chars.peek() == Some(&'{') {
+        for c in chars.by_ref() {
+            out.push(c);
+            if c == '}' {
+                break;
+            }
+        }
+    }
+    out.push(')');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::translate;
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(translate(r"\w+\d", false), r"[[:word:]]+[[:digit:]]");
+        assert_eq!(
+            translate(r"\S\W\D", false),
+            r"[[:^space:]][[:^word:]][[:^digit:]]"
+        );
+        assert_eq!(translate(r"[\w.-]", false), r"[[:word:].-]");
+        assert_eq!(
+            translate(r"[^\s[:alpha:]]\s", false),
+            r"[^[:space:][:alpha:]][[:space:]]"
+        );
+        assert_eq!(translate(r"[]\w]", false), r"[][:word:]]");
+        assert_eq!(translate(r"\bfoo\B", false), r"(?-u:\b)foo(?-u:\B)");
+        assert_eq!(
+            translate(r"\b{start-half}foo\b{end-half}", false),
+            r"(?-u:\b{start-half})foo(?-u:\b{end-half})"
+        );
+        // Other escapes, and escaped backslashes, are left alone
+        assert_eq!(translate(r"\\w\.\x41\p{L}", false), r"\\w\.\x41\p{L}");
+        assert_eq!(translate("é.", false), "é.");
+    }
+
+    #[test]
+    fn test_translate_boundaries() {
+        let re = regex::Regex::new(&translate(r"\bfoo\b", false)).unwrap();
+        assert!(re.is_match("éfoo"));
+        assert!(!re.is_match("xfoo"));
+
+        let translated = translate(r"(?<!\w)foo\b", true);
+        assert_eq!(
+            translated,
+            r"(?<![[:word:]])foo(?:(?<=[[:word:]])(?![[:word:]])|(?<![[:word:]])(?=[[:word:]]))"
+        );
+    }
+}
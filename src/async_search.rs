@@ -0,0 +1,183 @@
+//! Searching tokio's readers, for services that can't block on input
+
+use std::collections::VecDeque;
+
+use futures_util::stream::{self, Stream};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+use crate::{search_text, GreprError, Line, Searcher, Selector};
+
+/// A `Searcher` for input that arrives asynchronously, which yields the
+/// wanted lines as a `Stream`
+#[derive(Debug)]
+pub struct AsyncSearcher {
+    searcher: Searcher,
+}
+
+impl AsyncSearcher {
+    pub fn new(searcher: Searcher) -> AsyncSearcher {
+        AsyncSearcher { searcher }
+    }
+
+    pub fn searcher(&self) -> &Searcher {
+        &self.searcher
+    }
+
+    /// The lines wanted from `input`, as with `Searcher::lines`, each
+    /// yielded as soon as it's known to be wanted
+    pub fn lines<'s, R>(&'s self, input: R) -> impl Stream<Item = Result<Line, GreprError>> + 's
+    where
+        R: AsyncBufRead + Unpin + 's,
+    {
+        let options = &self.searcher.options;
+        let lines = AsyncLines {
+            searcher: &self.searcher,
+            input,
+            selector: Selector::new(options, None),
+            buffer: vec![],
+            pending: VecDeque::new(),
+            finished: options.max_count == Some(0),
+        };
+        stream::unfold(lines, |mut lines| async move {
+            let line = lines.next().await?;
+            Some((line, lines))
+        })
+    }
+}
+
+/// The state of a search between the lines it yields
+struct AsyncLines<'s, R> {
+    searcher: &'s Searcher,
+    input: R,
+    selector: Selector<'s>,
+    buffer: Vec<u8>,
+    /// Lines found but not yet yielded, as a match brings its context
+    pending: VecDeque<Line>,
+    finished: bool,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncLines<'_, R> {
+    async fn next(&mut self) -> Option<Result<Line, GreprError>> {
+        let searcher = self.searcher;
+        let pattern = &*searcher.pattern;
+        let options = &searcher.options;
+        loop {
+            if let Some(line) = self.pending.pop_front() {
+                return Some(Ok(line));
+            }
+            if self.finished {
+                return None;
+            }
+            self.finished = true;
+            if searcher.is_cancelled() {
+                return Some(Err(GreprError::Cancelled));
+            }
+
+            // A multiline pattern runs over the whole input at once
+            let pending = &mut self.pending;
+            if options.multiline {
+                let mut bytes = vec![];
+                if let Err(e) = self.input.read_to_end(&mut bytes).await {
+                    return Some(Err(e.into()));
+                }
+                let res = search_text(&bytes, pattern, options, |line| {
+                    pending.push_back(line);
+                    Ok(())
+                });
+                if let Err(e) = res {
+                    return Some(Err(e));
+                }
+                continue;
+            }
+
+            // Input that can't be read is yielded as an error, and ends the
+            // search
+            let terminator = if options.null_data { b'\0' } else { b'\n' };
+            self.buffer.clear();
+            let len = match self.input.read_until(terminator, &mut self.buffer).await {
+                Ok(0) => continue,
+                Ok(len) => len,
+                Err(e) => return Some(Err(GreprError::from(e))),
+            };
+            let mut find = |content: &[u8], _| pattern.find_spans(content);
+            let more = self
+                .selector
+                .record(&self.buffer, len, &mut find, &mut |line| {
+                    pending.push_back(line);
+                    Ok(())
+                });
+            if let Ok(true) = more {
+                self.finished = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncSearcher;
+    use crate::GreprError;
+    use crate::{SearchOptions, Searcher};
+    use futures_util::StreamExt;
+    use regex::bytes::Regex;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncReadExt, BufReader, ReadBuf};
+
+    /// A reader that fails on every read
+    struct Broken;
+
+    impl AsyncRead for Broken {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Poll::Ready(Err(io::Error::other("read failed")))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lines() {
+        let options = SearchOptions {
+            after_context: 1,
+            ..Default::default()
+        };
+        let searcher = Searcher::with_matcher(Regex::new("o").unwrap(), options);
+        let text = "one\nsix\nten\ntwo\n";
+        let expected = searcher.find_lines(text.as_bytes()).unwrap();
+        let searcher = AsyncSearcher::new(searcher);
+        let lines: Vec<_> = searcher
+            .lines(text.as_bytes())
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(lines, expected);
+
+        // A multiline pattern is searched for across lines
+        let options = SearchOptions {
+            multiline: true,
+            ..Default::default()
+        };
+        let searcher = Searcher::with_matcher(Regex::new(r"x\nt").unwrap(), options);
+        let searcher = AsyncSearcher::new(searcher);
+        let numbers: Vec<_> = searcher
+            .lines(text.as_bytes())
+            .map(|line| line.unwrap().number)
+            .collect()
+            .await;
+        assert_eq!(numbers, [2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_read_error() {
+        let searcher = Searcher::with_matcher(Regex::new("o").unwrap(), Default::default());
+        let searcher = AsyncSearcher::new(searcher);
+        let input = BufReader::new(&b"one\ntwo\n"[..]).chain(Broken);
+        let lines: Vec<_> = searcher.lines(BufReader::new(input)).collect().await;
+        assert_eq!(lines.len(), 3);
+        assert!(lines[..2].iter().all(Result::is_ok));
+        assert!(matches!(&lines[2], Err(GreprError::Stream(e)) if e.to_string() == "read failed"));
+    }
+}
@@ -0,0 +1,245 @@
+//! The memory a run may hold at once, set by --max-memory, which whatever
+//! can't get room in is cut short to stay within
+
+use std::cell::Cell;
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The bytes shared out among everything a run holds, across every file
+/// searched at once
+#[derive(Debug)]
+pub(crate) struct Budget {
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl Budget {
+    pub(crate) fn new(limit: u64) -> Budget {
+        Budget {
+            limit,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Takes `bytes` of what's left, if there's that much
+    pub(crate) fn reserve(&self, bytes: usize) -> bool {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes as u64)
+                    .filter(|&used| used <= self.limit)
+            })
+            .is_ok()
+    }
+
+    /// Takes `bytes` to hold on to past the line at hand, as context,
+    /// --unique's lines and output waiting to be printed are. Those only
+    /// get half the limit, so the lines being searched always have room.
+    pub(crate) fn hold(&self, bytes: usize) -> bool {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes as u64)
+                    .filter(|&used| used <= self.limit / 2)
+            })
+            .is_ok()
+    }
+
+    /// Takes as much of `bytes` as is left, returning how much that was
+    pub(crate) fn reserve_up_to(&self, bytes: usize) -> usize {
+        let mut got = 0;
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                got = self.limit.saturating_sub(used).min(bytes as u64);
+                Some(used + got)
+            });
+        got as usize
+    }
+
+    /// Gives back bytes that were taken
+    pub(crate) fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// One input's draw on a `Budget`, which keeps count of what it cut short
+#[derive(Debug)]
+pub(crate) struct Allowance<'a> {
+    pub(crate) budget: &'a Budget,
+    truncated: Cell<usize>,
+}
+
+impl<'a> Allowance<'a> {
+    pub(crate) fn new(budget: &'a Budget) -> Allowance<'a> {
+        Allowance {
+            budget,
+            truncated: Cell::new(0),
+        }
+    }
+
+    /// How many records, or for input read whole how many times the
+    /// input, had to be cut short
+    pub(crate) fn truncated(&self) -> usize {
+        self.truncated.get()
+    }
+
+    /// Like `SearchOptions::with_record`, but holds no more of the record
+    /// than there's room for. The rest is skipped, though it still counts
+    /// towards the record's length in the input, and the terminator is
+    /// kept.
+    pub(crate) fn with_record<T: BufRead, R>(
+        &self,
+        file: &mut T,
+        terminator: u8,
+        buffer: &mut Vec<u8>,
+        f: impl FnOnce(&[u8], usize) -> R,
+    ) -> io::Result<Option<R>> {
+        // A record in the reader's buffer is still held once it's copied
+        // out as a line
+        let buf = file.fill_buf()?;
+        if let Some(end) = memchr::memchr(terminator, buf) {
+            if self.budget.reserve(end + 1) {
+                let res = f(&buf[..=end], end + 1);
+                file.consume(end + 1);
+                self.budget.release(end + 1);
+                return Ok(Some(res));
+            }
+        }
+
+        buffer.clear();
+        let mut len = 0;
+        let mut ended = false;
+        while !ended {
+            let buf = file.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            let chunk = match memchr::memchr(terminator, buf) {
+                Some(end) => {
+                    ended = true;
+                    &buf[..=end]
+                }
+                None => buf,
+            };
+            // Once any of the record is skipped, so is the rest
+            let kept = if buffer.len() == len {
+                self.budget.reserve_up_to(chunk.len())
+            } else {
+                0
+            };
+            buffer.extend_from_slice(&chunk[..kept]);
+            let n = chunk.len();
+            len += n;
+            file.consume(n);
+        }
+        if len == 0 {
+            return Ok(None);
+        }
+
+        let held = buffer.len();
+        if held < len {
+            self.truncated.set(self.truncated.get() + 1);
+            if ended {
+                buffer.push(terminator);
+            }
+        }
+        let res = f(buffer, len);
+        self.budget.release(held);
+        Ok(Some(res))
+    }
+
+    /// Reads the rest of `file` into `bytes` as far as there's room for it.
+    /// What's read stays taken until the caller gives it back.
+    pub(crate) fn read_to_end<T: BufRead>(
+        &self,
+        file: &mut T,
+        bytes: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        loop {
+            let buf = file.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(());
+            }
+            let kept = self.budget.reserve_up_to(buf.len());
+            bytes.extend_from_slice(&buf[..kept]);
+            let whole = kept == buf.len();
+            file.consume(kept);
+            if !whole {
+                self.truncated.set(self.truncated.get() + 1);
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Allowance, Budget};
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn test_reserve() {
+        let budget = Budget::new(10);
+        assert!(budget.reserve(6));
+        assert!(!budget.reserve(6));
+        assert_eq!(budget.reserve_up_to(6), 4);
+        assert_eq!(budget.reserve_up_to(6), 0);
+        budget.release(10);
+        assert!(budget.reserve(10));
+
+        // What's held leaves half for lines
+        budget.release(10);
+        assert!(budget.hold(5));
+        assert!(!budget.hold(1));
+        assert!(budget.reserve(5));
+    }
+
+    #[test]
+    fn test_with_record() {
+        // Whole lines or not, in the reader's buffer or not
+        for capacity in [2, 64] {
+            let budget = Budget::new(8);
+            let allowance = Allowance::new(&budget);
+            let mut file = BufReader::with_capacity(capacity, &b"a fox\nthe longest fox\nfox"[..]);
+            let mut buffer = vec![];
+            let mut records = vec![];
+            while let Some(()) = allowance
+                .with_record(&mut file, b'\n', &mut buffer, |record, len| {
+                    records.push((record.to_vec(), len))
+                })
+                .unwrap()
+            {}
+            assert_eq!(
+                records,
+                [
+                    (b"a fox\n".to_vec(), 6),
+                    (b"the long\n".to_vec(), 16),
+                    (b"fox".to_vec(), 3)
+                ]
+            );
+            assert_eq!(allowance.truncated(), 1);
+            // Everything taken is given back
+            assert!(budget.reserve(8));
+            assert!(file.fill_buf().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_read_to_end() {
+        let budget = Budget::new(8);
+        let allowance = Allowance::new(&budget);
+        let mut bytes = vec![];
+        allowance
+            .read_to_end(
+                &mut BufReader::with_capacity(3, &b"a fox\nfox\n"[..]),
+                &mut bytes,
+            )
+            .unwrap();
+        assert_eq!(bytes, b"a fox\nfo");
+        assert_eq!(allowance.truncated(), 1);
+        assert_eq!(budget.reserve_up_to(1), 0);
+    }
+}
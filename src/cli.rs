@@ -0,0 +1,1421 @@
+//! The `grepr` command line: its options, read into the `Config` a run
+//! searches with
+
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::io::{self, BufRead, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::vec;
+
+use clap::{App, AppSettings, Arg};
+use encoding_rs::Encoding;
+use tracing::debug;
+
+use crate::run::{
+    open, BinaryFiles, Colors, Decompressor, HyperlinkFormat, Meter, Template, Transcoder, Types,
+};
+use crate::{
+    build_globs, build_pattern, has_uppercase, Config, Decoder, GreprError, Progress, RealFs,
+    RegexOptions, SearchOptions, SortBy, Syntax, WalkOptions,
+};
+
+type MyResult<T> = Result<T, GreprError>;
+
+const PATTERN: &str = "pattern";
+const FILE: &str = "file";
+const FILES_FROM: &str = "files-from";
+const FILES0_FROM: &str = "files0-from";
+const RECURSIVE: &str = "recursive";
+const DIRECTORIES: &str = "directories";
+const GREP_COMPAT: &str = "grep-compat";
+const DEREFERENCE_RECURSIVE: &str = "dereference-recursive";
+const FOLLOW: &str = "follow";
+const ONE_FILE_SYSTEM: &str = "one-file-system";
+const HIDDEN: &str = "hidden";
+const NO_IGNORE: &str = "no-ignore";
+const NO_IGNORE_VCS: &str = "no-ignore-vcs";
+const NO_IGNORE_DOT: &str = "no-ignore-dot";
+const MAX_DEPTH: &str = "max-depth";
+const MAX_FILESIZE: &str = "max-filesize";
+const SORT: &str = "sort";
+const SORTR: &str = "sortr";
+const INVERT_MATCH: &str = "invert-match";
+const COUNT: &str = "count";
+const COUNT_MATCHES: &str = "count-matches";
+const TOTAL: &str = "total";
+const INSENSITIVE: &str = "insensitive";
+const UNICODE: &str = "unicode";
+const NO_UNICODE: &str = "no-unicode";
+const REGEX_SIZE_LIMIT: &str = "regex-size-limit";
+const DFA_SIZE_LIMIT: &str = "dfa-size-limit";
+const CASE_SENSITIVE: &str = "case-sensitive";
+const SMART_CASE: &str = "smart-case";
+const LINE_NUMBER: &str = "line-number";
+const NO_LINE_NUMBER: &str = "no-line-number";
+const AFTER_CONTEXT: &str = "after-context";
+const BEFORE_CONTEXT: &str = "before-context";
+const CONTEXT: &str = "context";
+const GROUP_SEPARATOR: &str = "group-separator";
+const NO_GROUP_SEPARATOR: &str = "no-group-separator";
+const PASSTHRU: &str = "passthru";
+const REPLACE: &str = "replace";
+const GROUP: &str = "group";
+const FILES_WITH_MATCHES: &str = "files-with-matches";
+const FILES_WITHOUT_MATCH: &str = "files-without-match";
+const LIST_FILES: &str = "files";
+const ONLY_MATCHING: &str = "only-matching";
+const UNIQUE: &str = "unique";
+const UNIQUE_COUNT: &str = "unique-count";
+const WORD_REGEXP: &str = "word-regexp";
+const LINE_REGEXP: &str = "line-regexp";
+const FIXED_STRINGS: &str = "fixed-strings";
+const BASIC_REGEXP: &str = "basic-regexp";
+const EXTENDED_REGEXP: &str = "extended-regexp";
+const PERL_REGEXP: &str = "perl-regexp";
+const ENGINE: &str = "engine";
+const MULTILINE: &str = "multiline";
+const CRLF: &str = "crlf";
+const REGEXP: &str = "regexp";
+const PATTERN_FILE: &str = "pattern-file";
+const MAX_COUNT: &str = "max-count";
+const QUIET: &str = "quiet";
+const NO_MESSAGES: &str = "no-messages";
+const INCLUDE: &str = "include";
+const EXCLUDE: &str = "exclude";
+const EXCLUDE_DIR: &str = "exclude-dir";
+const EXCLUDE_FROM: &str = "exclude-from";
+const TYPE: &str = "type";
+const TYPE_NOT: &str = "type-not";
+const TYPE_ADD: &str = "type-add";
+const TYPE_LIST: &str = "type-list";
+const COLOR: &str = "color";
+const WITH_FILENAME: &str = "with-filename";
+const NO_FILENAME: &str = "no-filename";
+const NULL: &str = "null";
+const FIELD_MATCH_SEPARATOR: &str = "field-match-separator";
+const PATH_SEPARATOR: &str = "path-separator";
+const HYPERLINK_FORMAT: &str = "hyperlink-format";
+const LABEL: &str = "label";
+const NULL_DATA: &str = "null-data";
+const TEXT: &str = "text";
+const BINARY_FILES: &str = "binary-files";
+const ENCODING: &str = "encoding";
+const MMAP: &str = "mmap";
+const MAX_MEMORY: &str = "max-memory";
+const SEARCH_ZIP: &str = "search-zip";
+const PRE: &str = "pre";
+const PRE_GLOB: &str = "pre-glob";
+const BYTE_OFFSET: &str = "byte-offset";
+const COLUMN: &str = "column";
+const VIMGREP: &str = "vimgrep";
+const FORMAT: &str = "format";
+const MAX_COLUMNS: &str = "max-columns";
+const MAX_COLUMNS_PREVIEW: &str = "max-columns-preview";
+const TRIM: &str = "trim";
+const INITIAL_TAB: &str = "initial-tab";
+const HEADING: &str = "heading";
+const NO_HEADING: &str = "no-heading";
+const PRETTY: &str = "pretty";
+const STATS: &str = "stats";
+const THREADS: &str = "threads";
+const LINE_BUFFERED: &str = "line-buffered";
+const PAGER: &str = "pager";
+const NO_PAGER: &str = "no-pager";
+const BLOCK_BUFFERED: &str = "block-buffered";
+const JSON: &str = "json";
+const NO_CONFIG: &str = "no-config";
+const PROGRESS: &str = "progress";
+const DEBUG: &str = "debug";
+const TRACE: &str = "trace";
+
+pub fn get_args() -> MyResult<Config> {
+    // Defaults come from the config file and then GREPR_OPTIONS, so that
+    // each can be overridden by what follows it
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let mut defaults = vec![];
+    if !args.iter().any(|arg| arg == "--no-config") {
+        defaults = config_args()?;
+    }
+    if let Some(options) = std::env::var_os("GREPR_OPTIONS") {
+        defaults.extend(split_options(&options.to_string_lossy())?);
+    }
+    let defaults_len = defaults.len();
+    args.splice(1..1, defaults.into_iter().map(OsString::from));
+
+    let matches = App::new("grepr")
+        .version("0.1.0")
+        .author("Myron Lioz <liozmyron@gmail.com>")
+        .about("Rust grep")
+        // Options from the config file can be given again to override them
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .arg(
+            Arg::with_name(PATTERN)
+                .value_name("PATTERN")
+                .help("Search pattern")
+                .required_unless_one(&[REGEXP, PATTERN_FILE, LIST_FILES, TYPE_LIST]),
+        )
+        .arg(
+            Arg::with_name(FILE)
+                .value_name("FILE")
+                .help("Input file(s), or the current directory on a terminal and otherwise stdin")
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name(FILES_FROM)
+                .value_name("LIST")
+                .help("Search the files named in LIST, one per line, or stdin for -")
+                .long("files-from")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name(FILES0_FROM)
+                .value_name("LIST")
+                .help("Search the files named in LIST, each ended by NUL, or stdin for -")
+                .long("files0-from")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name(COUNT)
+                .help("Count occurences.")
+                .short("c")
+                .long("count")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(COUNT_MATCHES)
+                .help("Count individual matches rather than matching lines")
+                .long("count-matches")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(TOTAL)
+                .help("With --count, print the total over all files")
+                .long("total")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(STATS)
+                .help("Print statistics about the search when done")
+                .long("stats")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(PROGRESS)
+                .help("Show how many files have been searched on stderr")
+                .long("progress")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(JSON)
+                .help("Print results as JSON Lines, in ripgrep's format")
+                .long("json")
+                .conflicts_with_all(&[
+                    COUNT,
+                    COUNT_MATCHES,
+                    FILES_WITH_MATCHES,
+                    FILES_WITHOUT_MATCH,
+                    LIST_FILES,
+                    ONLY_MATCHING,
+                    VIMGREP,
+                    REPLACE,
+                    QUIET,
+                ])
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(FILES_WITH_MATCHES)
+                .help("Print only names of files with matches")
+                .short("l")
+                .long("files-with-matches")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(FILES_WITHOUT_MATCH)
+                .help("Print only names of files without matches")
+                .short("L")
+                .long("files-without-match")
+                .conflicts_with(FILES_WITH_MATCHES)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(LIST_FILES)
+                .help("Print the files that would be searched, without searching")
+                .long("files")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(INSENSITIVE)
+                .help("Case-insensitive")
+                .short("i")
+                .long("insensitive")
+                .overrides_with(CASE_SENSITIVE)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(CASE_SENSITIVE)
+                .help("Case-sensitive, even with --smart-case")
+                .long("case-sensitive")
+                .overrides_with(INSENSITIVE)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(SMART_CASE)
+                .help("Case-insensitive unless the pattern contains an uppercase letter")
+                .short("S")
+                .long("smart-case")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(UNICODE)
+                .help(r"Let \w, \d, \s and \b match any Unicode character (default)")
+                .long("unicode")
+                .overrides_with(NO_UNICODE)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(NO_UNICODE)
+                .help(r"Let \w, \d, \s and \b match only ASCII, which is faster")
+                .long("no-unicode")
+                .overrides_with(UNICODE)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(REGEX_SIZE_LIMIT)
+                .value_name("NUM")
+                .help("Allow a compiled regex of up to NUM bytes, or with a suffix K, M or G")
+                .long("regex-size-limit"),
+        )
+        .arg(
+            Arg::with_name(DFA_SIZE_LIMIT)
+                .value_name("NUM")
+                .help("Cap the regex DFA's cache at NUM bytes, or with a suffix K, M or G")
+                .long("dfa-size-limit"),
+        )
+        .arg(
+            Arg::with_name(INVERT_MATCH)
+                .help("Invert match")
+                .short("v")
+                .long("invert-match")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(LINE_NUMBER)
+                .help("Prefix each line with its line number (default on a terminal)")
+                .short("n")
+                .long("line-number")
+                .overrides_with(NO_LINE_NUMBER)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(NO_LINE_NUMBER)
+                .help("Don't prefix lines with their line numbers")
+                .short("N")
+                .long("no-line-number")
+                .overrides_with(LINE_NUMBER)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(BYTE_OFFSET)
+                .help("Prefix each line with its byte offset")
+                .short("b")
+                .long("byte-offset")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(COLUMN)
+                .help("Prefix each line with the column of its first match")
+                .long("column")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(VIMGREP)
+                .help("Print file:line:column:text once for every match")
+                .long("vimgrep")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(FORMAT)
+                .value_name("TEMPLATE")
+                .help("Print every match as TEMPLATE, filling in {path}, {line}, {column}, {text}, {match} and groups such as {1}")
+                .long("format"),
+        )
+        .arg(
+            Arg::with_name(MAX_COLUMNS)
+                .value_name("NUM")
+                .help("Omit lines longer than NUM bytes from the output")
+                .short("M")
+                .long("max-columns"),
+        )
+        .arg(
+            Arg::with_name(MAX_COLUMNS_PREVIEW)
+                .help("Print the first NUM bytes of lines longer than --max-columns")
+                .long("max-columns-preview")
+                .requires(MAX_COLUMNS)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(TRIM)
+                .help("Strip leading whitespace from each line printed")
+                .long("trim")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(INITIAL_TAB)
+                .help("Put a tab between the file name, line number and so on and the text")
+                .short("T")
+                .long("initial-tab")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(HEADING)
+                .help("Print file names above their lines (default on a terminal)")
+                .long("heading")
+                .overrides_with(NO_HEADING)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(NO_HEADING)
+                .help("Print the file name on every line")
+                .long("no-heading")
+                .overrides_with(HEADING)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(PRETTY)
+                .help("Color, headings and line numbers, even when not on a terminal")
+                .short("p")
+                .long("pretty")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(AFTER_CONTEXT)
+                .value_name("NUM")
+                .help("Print NUM lines of trailing context")
+                .short("A")
+                .long("after-context"),
+        )
+        .arg(
+            Arg::with_name(BEFORE_CONTEXT)
+                .value_name("NUM")
+                .help("Print NUM lines of leading context")
+                .short("B")
+                .long("before-context"),
+        )
+        .arg(
+            Arg::with_name(CONTEXT)
+                .value_name("NUM")
+                .help("Print NUM lines of output context")
+                .short("C")
+                .long("context"),
+        )
+        .arg(
+            Arg::with_name(GROUP_SEPARATOR)
+                .value_name("SEP")
+                .help("Print SEP between groups of context lines instead of \"--\"")
+                .long("group-separator")
+                .overrides_with(NO_GROUP_SEPARATOR),
+        )
+        .arg(
+            Arg::with_name(NO_GROUP_SEPARATOR)
+                .help("Print nothing between groups of context lines")
+                .long("no-group-separator")
+                .overrides_with(GROUP_SEPARATOR)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(REPLACE)
+                .value_name("TEMPLATE")
+                .help("Print TEMPLATE in place of each match, expanding $1 or ${name}")
+                .long("replace"),
+        )
+        .arg(
+            Arg::with_name(GROUP)
+                .value_name("GROUP")
+                .help("With -o, print only the capture group GROUP, by number or name")
+                .long("group")
+                .requires(ONLY_MATCHING)
+                .conflicts_with(REPLACE),
+        )
+        .arg(
+            Arg::with_name(PASSTHRU)
+                .help("Print every line, highlighting the matches")
+                .long("passthru")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(ONLY_MATCHING)
+                .help("Print only the matched parts of a line")
+                .short("o")
+                .long("only-matching")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(UNIQUE)
+                .help("Print each distinct line of a file only once")
+                .long("unique")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(UNIQUE_COUNT)
+                .help("Like --unique, with how many times each line occurred")
+                .long("unique-count")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(WORD_REGEXP)
+                .help("Match only whole words")
+                .short("w")
+                .long("word-regexp")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(LINE_REGEXP)
+                .help("Match only whole lines")
+                .short("x")
+                .long("line-regexp")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(REGEXP)
+                .value_name("PATTERN")
+                .help("Search pattern, may be given more than once")
+                .short("e")
+                .long("regexp")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name(PATTERN_FILE)
+                .value_name("FILE")
+                .help("Read patterns from FILE, one per line")
+                .short("f")
+                .long("file")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name(FIXED_STRINGS)
+                .help("Interpret the pattern as a literal string")
+                .short("F")
+                .long("fixed-strings")
+                .conflicts_with_all(&[BASIC_REGEXP, EXTENDED_REGEXP, PERL_REGEXP])
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(BASIC_REGEXP)
+                .help("Interpret the pattern as a POSIX basic regular expression")
+                .short("G")
+                .long("basic-regexp")
+                .conflicts_with_all(&[EXTENDED_REGEXP, PERL_REGEXP])
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(EXTENDED_REGEXP)
+                .help("Interpret the pattern as a POSIX extended regular expression")
+                .short("E")
+                .long("extended-regexp")
+                .conflicts_with(PERL_REGEXP)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(PERL_REGEXP)
+                .help("Interpret the pattern with lookaround and backreferences")
+                .short("P")
+                .long("perl-regexp")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(ENGINE)
+                .value_name("ENGINE")
+                .help("Run regexes with the default engine, the fancy one used by -P, or the fancy one only for patterns that need it")
+                .long("engine")
+                .possible_values(&["auto", "default", "fancy"]),
+        )
+        .arg(
+            Arg::with_name(MULTILINE)
+                .help("Allow matches to span multiple lines")
+                .short("U")
+                .long("multiline")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(CRLF)
+                .help("Treat CRLF as a line terminator, and leave the CR out of output")
+                .long("crlf")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(MAX_COUNT)
+                .value_name("NUM")
+                .help("Stop reading a file after NUM matching lines")
+                .short("m")
+                .long("max-count"),
+        )
+        .arg(
+            Arg::with_name(QUIET)
+                .help("Print nothing, stop at the first match")
+                .short("q")
+                .long("quiet")
+                .alias("silent")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(NO_MESSAGES)
+                .help("Suppress error messages about unreadable files")
+                .short("s")
+                .long("no-messages")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(WITH_FILENAME)
+                .help("Print the file name for each match")
+                .short("H")
+                .long("with-filename")
+                .overrides_with(NO_FILENAME)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(NO_FILENAME)
+                .help("Never print file names with matches")
+                .short("h")
+                .long("no-filename")
+                .overrides_with(WITH_FILENAME)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(LABEL)
+                .value_name("NAME")
+                .help("Show standard input as NAME where a file name would go")
+                .long("label"),
+        )
+        .arg(
+            Arg::with_name(NULL)
+                .help("Follow file names with a NUL byte instead of ':' or newline")
+                .short("Z")
+                .long("null")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(FIELD_MATCH_SEPARATOR)
+                .value_name("SEP")
+                .help("Print SEP instead of ':' after the file name and numbers of matching lines")
+                .long("field-match-separator"),
+        )
+        .arg(
+            Arg::with_name(PATH_SEPARATOR)
+                .value_name("SEP")
+                .help("Print SEP between the components of file names")
+                .long("path-separator"),
+        )
+        .arg(
+            Arg::with_name(HYPERLINK_FORMAT)
+                .value_name("FORMAT")
+                .help("Link file names to a URL such as vscode://file{path}:{line}:{column} in color output")
+                .long("hyperlink-format"),
+        )
+        .arg(
+            Arg::with_name(NULL_DATA)
+                .help("Input and output records are terminated by NUL, not newline")
+                .short("z")
+                .long("null-data")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(TEXT)
+                .help("Search binary files as if they were text")
+                .short("a")
+                .long("text")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(BINARY_FILES)
+                .value_name("TYPE")
+                .help("How to treat binary files")
+                .long("binary-files")
+                .possible_values(&["binary", "text", "without-match"]),
+        )
+        .arg(
+            Arg::with_name(THREADS)
+                .value_name("NUM")
+                .help("Search NUM files at once (default: one per CPU)")
+                .short("j")
+                .long("threads"),
+        )
+        .arg(
+            Arg::with_name(LINE_BUFFERED)
+                .help("Write each line out at once (default on a terminal)")
+                .long("line-buffered")
+                .overrides_with(BLOCK_BUFFERED)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(BLOCK_BUFFERED)
+                .help("Write output in large blocks (default when not on a terminal)")
+                .long("block-buffered")
+                .overrides_with(LINE_BUFFERED)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(PAGER)
+                .help("Page output on a terminal through $PAGER, or less")
+                .long("pager")
+                .overrides_with(NO_PAGER)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(NO_PAGER)
+                .help("Write output straight to the terminal (default)")
+                .long("no-pager")
+                .overrides_with(PAGER)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(MMAP)
+                .help("Search large files by mapping them into memory")
+                .long("mmap")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(MAX_MEMORY)
+                .value_name("NUM")
+                .help("Hold no more than NUM bytes in memory at once, or with a suffix K, M or G, cutting long lines short")
+                .long("max-memory"),
+        )
+        .arg(
+            Arg::with_name(SEARCH_ZIP)
+                .help("Search inside gzip, bzip2, xz and zstd compressed files")
+                .long("search-zip")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(PRE)
+                .value_name("CMD")
+                .help("Search the output of CMD run on each file, given its path")
+                .long("pre"),
+        )
+        .arg(
+            Arg::with_name(PRE_GLOB)
+                .value_name("GLOB")
+                .help("Only run the --pre command on files whose name matches GLOB")
+                .long("pre-glob")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name(ENCODING)
+                .value_name("LABEL")
+                .help("Transcode files from LABEL, such as utf-16le or latin1")
+                .long("encoding"),
+        )
+        .arg(
+            Arg::with_name(COLOR)
+                .value_name("WHEN")
+                .help("Highlight matches, file names and line numbers (default on a terminal)")
+                .long("color")
+                .alias("colour")
+                .possible_values(&["auto", "always", "never"])
+                .min_values(0)
+                .require_equals(true),
+        )
+        .arg(
+            Arg::with_name(RECURSIVE)
+                .help("Recursive search")
+                .short("r")
+                .long("recursive")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(DIRECTORIES)
+                .value_name("ACTION")
+                .help("Read, skip or recurse into directories named as files")
+                .short("d")
+                .long("directories")
+                .possible_values(&["read", "skip", "recurse"]),
+        )
+        .arg(
+            Arg::with_name(GREP_COMPAT)
+                .help("Read stdin without any files, and directories only with -r, as grep does")
+                .long("grep-compat")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(DEREFERENCE_RECURSIVE)
+                .help("Recursive search, following symbolic links")
+                .short("R")
+                .long("dereference-recursive")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(HIDDEN)
+                .help("Search hidden files and directories while recursing")
+                .long("hidden")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(NO_IGNORE)
+                .help("Don't skip the paths listed in ignore files while recursing")
+                .long("no-ignore")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(NO_IGNORE_VCS)
+                .help("Don't skip the paths listed in .gitignore files")
+                .long("no-ignore-vcs")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(NO_IGNORE_DOT)
+                .help("Don't skip the paths listed in .ignore files")
+                .long("no-ignore-dot")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(FOLLOW)
+                .help("Follow symbolic links while recursing")
+                .long("follow")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(ONE_FILE_SYSTEM)
+                .help("Don't descend into directories on other file systems (Unix only; ignored on Windows)")
+                .long("one-file-system")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(MAX_DEPTH)
+                .value_name("NUM")
+                .help("Descend at most NUM directories below each path")
+                .long("max-depth"),
+        )
+        .arg(
+            Arg::with_name(MAX_FILESIZE)
+                .value_name("NUM")
+                .help("Skip files larger than NUM bytes, or with a suffix K, M or G")
+                .long("max-filesize"),
+        )
+        .arg(
+            Arg::with_name(SORT)
+                .value_name("BY")
+                .help("Search files in order of their path, modified or created time, or size")
+                .long("sort")
+                .possible_values(&["path", "modified", "created", "size"]),
+        )
+        .arg(
+            Arg::with_name(SORTR)
+                .value_name("BY")
+                .help("Like --sort, in reverse")
+                .long("sortr")
+                .possible_values(&["path", "modified", "created", "size"])
+                .conflicts_with(SORT),
+        )
+        .arg(
+            Arg::with_name(INCLUDE)
+                .value_name("GLOB")
+                .help("Search only files whose name matches GLOB")
+                .long("include")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name(EXCLUDE)
+                .value_name("GLOB")
+                .help("Skip files whose name matches GLOB")
+                .long("exclude")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name(TYPE)
+                .value_name("TYPE")
+                .help("Search only files of TYPE, such as rust or py")
+                .short("t")
+                .long("type")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name(TYPE_NOT)
+                .value_name("TYPE")
+                .help("Skip files of TYPE")
+                .long("type-not")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name(TYPE_ADD)
+                .value_name("NAME:GLOB")
+                .help("Add GLOB to the file type NAME, defining it if need be")
+                .long("type-add")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name(TYPE_LIST)
+                .help("Print the file types and their globs, without searching")
+                .long("type-list")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(EXCLUDE_FROM)
+                .value_name("FILE")
+                .help("Skip files whose name matches any glob in FILE")
+                .long("exclude-from")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name(EXCLUDE_DIR)
+                .value_name("GLOB")
+                .help("Skip directories whose name matches GLOB")
+                .long("exclude-dir")
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name(NO_CONFIG)
+                .help("Don't read the config file")
+                .long("no-config")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(DEBUG)
+                .help("Explain which files are searched or skipped and why, on stderr")
+                .long("debug")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name(TRACE)
+                .help("Like --debug, with even more detail")
+                .long("trace")
+                .takes_value(false),
+        )
+        .get_matches_from_safe(args)
+        // Help and version aren't errors, and go to stdout
+        .map_err(|e| {
+            if e.use_stderr() {
+                GreprError::from(e.message.trim_end())
+            } else {
+                e.exit()
+            }
+        })?;
+
+    let level = if matches.is_present(TRACE) {
+        Some(tracing::Level::TRACE)
+    } else if matches.is_present(DEBUG) {
+        Some(tracing::Level::DEBUG)
+    } else {
+        None
+    };
+    if let Some(level) = level {
+        // Fails only if a subscriber is already set, which is just as good
+        let _ = tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_writer(io::stderr)
+            .with_ansi(io::stderr().is_terminal())
+            .without_time()
+            .with_target(false)
+            .try_init();
+        debug!(
+            "{} arguments from the config file and GREPR_OPTIONS",
+            defaults_len
+        );
+    }
+
+    let mut patterns = matches.values_of_lossy(REGEXP).unwrap_or_default();
+    for filename in matches.values_of(PATTERN_FILE).into_iter().flatten() {
+        patterns.extend(read_patterns(filename)?);
+    }
+
+    let mut files: Vec<PathBuf> = if matches.is_present(REGEXP)
+        || matches.is_present(PATTERN_FILE)
+        || matches.is_present(LIST_FILES)
+        || matches.is_present(TYPE_LIST)
+    {
+        // With -e, -f, --files or --type-list every positional argument is a file
+        let files = matches.values_of_os(PATTERN).into_iter().flatten();
+        files
+            .chain(matches.values_of_os(FILE).into_iter().flatten())
+            .map(PathBuf::from)
+            .collect()
+    } else {
+        patterns.extend(matches.values_of_lossy(PATTERN).unwrap());
+        let files = matches.values_of_os(FILE).into_iter().flatten();
+        files.map(PathBuf::from).collect()
+    };
+
+    let files_from = matches.is_present(FILES_FROM) || matches.is_present(FILES0_FROM);
+    for list in matches.values_of_os(FILES_FROM).into_iter().flatten() {
+        files.extend(read_file_list(Path::new(list), b'\n')?);
+    }
+    for list in matches.values_of_os(FILES0_FROM).into_iter().flatten() {
+        files.extend(read_file_list(Path::new(list), b'\0')?);
+    }
+
+    // Without any files an interactive run searches the current directory,
+    // where reading the terminal would only wait for input. An empty list
+    // of files means nothing to search.
+    let grep_compat = matches.is_present(GREP_COMPAT);
+    let implicit_cwd = files.is_empty() && !grep_compat && io::stdin().is_terminal();
+    if files.is_empty() && !files_from {
+        files.push(PathBuf::from(if implicit_cwd { "." } else { "-" }));
+    }
+
+    let syntax = if matches.is_present(FIXED_STRINGS) {
+        Syntax::Fixed
+    } else if matches.is_present(BASIC_REGEXP) {
+        Syntax::Basic
+    } else if matches.is_present(EXTENDED_REGEXP) {
+        Syntax::Extended
+    } else if matches.is_present(PERL_REGEXP) || matches.value_of(ENGINE) == Some("fancy") {
+        Syntax::Perl
+    } else {
+        Syntax::Regex
+    };
+
+    // An explicit -i or --case-sensitive beats --smart-case
+    let insensitive = if matches.is_present(INSENSITIVE) {
+        true
+    } else if matches.is_present(CASE_SENSITIVE) {
+        false
+    } else {
+        matches.is_present(SMART_CASE) && !patterns.iter().any(|p| has_uppercase(p, syntax))
+    };
+
+    let regex = RegexOptions {
+        insensitive,
+        word_regexp: matches.is_present(WORD_REGEXP),
+        line_regexp: matches.is_present(LINE_REGEXP),
+        multiline: matches.is_present(MULTILINE),
+        crlf: matches.is_present(CRLF),
+        ascii: matches.is_present(NO_UNICODE),
+        size_limit: parse_limit(matches.value_of(REGEX_SIZE_LIMIT))?,
+        dfa_size_limit: parse_limit(matches.value_of(DFA_SIZE_LIMIT))?,
+    };
+    // With --engine auto, patterns the regex engine rejects, such as ones
+    // with lookaround or backreferences, are tried with the fancy one
+    let auto = syntax == Syntax::Regex && matches.value_of(ENGINE) == Some("auto");
+    let pattern = match build_pattern(&patterns, &regex, syntax) {
+        Err(e @ GreprError::InvalidPattern(_)) if auto => {
+            let pattern = build_pattern(&patterns, &regex, Syntax::Perl).map_err(|_| e)?;
+            debug!("falling back to the fancy engine");
+            pattern
+        }
+        // The library doesn't know the option that raises the limit
+        Err(e @ GreprError::PatternTooBig { .. }) => {
+            return Err(format!("{}; raise --regex-size-limit", e).into())
+        }
+        res => res?,
+    };
+
+    let context = parse_num(matches.value_of(CONTEXT), "context length")?.unwrap_or(0);
+    let before_context =
+        parse_num(matches.value_of(BEFORE_CONTEXT), "context length")?.unwrap_or(context);
+    let after_context =
+        parse_num(matches.value_of(AFTER_CONTEXT), "context length")?.unwrap_or(context);
+
+    let vimgrep = matches.is_present(VIMGREP);
+    let json = matches.is_present(JSON);
+    let format = matches.value_of(FORMAT).map(Template::parse).transpose()?;
+    let formatted = format.is_some();
+
+    // Color, headings and line numbers go together: on for --pretty or a
+    // terminal and off when piped, unless set one by one
+    let pretty = matches.is_present(PRETTY);
+    let terminal = io::stdout().is_terminal();
+    let color = match matches.value_of(COLOR) {
+        Some("never") => false,
+        Some("always") => true,
+        // NO_COLOR only affects the automatic choice
+        _ => pretty || (terminal && std::env::var_os("NO_COLOR").is_none()),
+    };
+    let heading =
+        !matches.is_present(NO_HEADING) && (matches.is_present(HEADING) || pretty || terminal);
+    let line_number = !matches.is_present(NO_LINE_NUMBER)
+        && (matches.is_present(LINE_NUMBER) || pretty || terminal);
+    let hyperlink = matches
+        .value_of(HYPERLINK_FORMAT)
+        .map(HyperlinkFormat::new)
+        .transpose()?
+        .flatten();
+
+    let mut exclude_from = vec![];
+    for filename in matches.values_of(EXCLUDE_FROM).into_iter().flatten() {
+        exclude_from.extend(read_globs(filename)?);
+    }
+
+    let mut types = Types::new();
+    for def in matches.values_of(TYPE_ADD).into_iter().flatten() {
+        types.add(def)?;
+    }
+
+    // Printing a single group is replacing the match with a reference to it
+    let replace = match matches.value_of(GROUP) {
+        Some(group)
+            if group.is_empty()
+                || !group.chars().all(|c| c == '_' || c.is_ascii_alphanumeric()) =>
+        {
+            return Err(From::from(format!("Invalid group \"{}\"", group)))
+        }
+        Some(group) if !pattern.has_group(group) => {
+            return Err(From::from(format!("No group \"{}\" in the pattern", group)))
+        }
+        Some(group) => Some(format!("${{{}}}", group)),
+        None => matches.value_of_lossy(REPLACE).map(|r| r.into_owned()),
+    };
+
+    Ok(Config {
+        pattern,
+        files,
+        walk: WalkOptions {
+            recursive: matches.is_present(RECURSIVE)
+                || matches.is_present(DEREFERENCE_RECURSIVE)
+                || implicit_cwd
+                || match matches.value_of(DIRECTORIES) {
+                    Some(action) => action == "recurse",
+                    None => !grep_compat,
+                },
+            skip_dirs: matches.value_of(DIRECTORIES) == Some("skip"),
+            implicit_cwd,
+            follow_links: matches.is_present(FOLLOW) || matches.is_present(DEREFERENCE_RECURSIVE),
+            one_file_system: matches.is_present(ONE_FILE_SYSTEM),
+            hidden: matches.is_present(HIDDEN),
+            no_ignore_vcs: matches.is_present(NO_IGNORE) || matches.is_present(NO_IGNORE_VCS),
+            no_ignore_dot: matches.is_present(NO_IGNORE) || matches.is_present(NO_IGNORE_DOT),
+            max_depth: parse_num(matches.value_of(MAX_DEPTH), "max depth")?,
+            max_filesize: matches
+                .value_of(MAX_FILESIZE)
+                .map(|v| parse_size(v, "file size"))
+                .transpose()?,
+            include: build_globs(matches.values_of(INCLUDE).into_iter().flatten())?,
+            exclude: build_globs(
+                matches
+                    .values_of(EXCLUDE)
+                    .into_iter()
+                    .flatten()
+                    .chain(exclude_from.iter().map(String::as_str)),
+            )?,
+            exclude_dir: build_globs(matches.values_of(EXCLUDE_DIR).into_iter().flatten())?,
+            types: build_globs(types.globs(matches.values_of(TYPE).into_iter().flatten())?)?,
+            types_not: build_globs(
+                types.globs(matches.values_of(TYPE_NOT).into_iter().flatten())?,
+            )?,
+            sort: match matches.value_of(SORT).or(matches.value_of(SORTR)) {
+                Some("path") => Some(SortBy::Path),
+                Some("modified") => Some(SortBy::Modified),
+                Some("created") => Some(SortBy::Created),
+                Some("size") => Some(SortBy::Size),
+                _ => None,
+            },
+            sort_reverse: matches.is_present(SORTR),
+            cancel: None,
+        },
+        search: SearchOptions {
+            invert_match: matches.is_present(INVERT_MATCH),
+            before_context,
+            after_context,
+            max_count: parse_num(matches.value_of(MAX_COUNT), "max count")?,
+            null_data: matches.is_present(NULL_DATA),
+            multiline: matches.is_present(MULTILINE),
+            passthru: matches.is_present(PASSTHRU),
+            crlf: matches.is_present(CRLF),
+        },
+        count: matches.is_present(COUNT) || matches.is_present(COUNT_MATCHES),
+        count_matches: matches.is_present(COUNT_MATCHES),
+        total: matches.is_present(TOTAL),
+        stats: matches.is_present(STATS),
+        json,
+        line_number: vimgrep || line_number,
+        byte_offset: matches.is_present(BYTE_OFFSET),
+        column: vimgrep || matches.is_present(COLUMN),
+        vimgrep,
+        format,
+        max_columns: parse_num(matches.value_of(MAX_COLUMNS), "column limit")?,
+        max_columns_preview: matches.is_present(MAX_COLUMNS_PREVIEW),
+        trim: matches.is_present(TRIM),
+        initial_tab: matches.is_present(INITIAL_TAB),
+        files_with_matches: matches.is_present(FILES_WITH_MATCHES),
+        list_files: matches.is_present(LIST_FILES),
+        type_list: matches.is_present(TYPE_LIST).then_some(types),
+        replace,
+        files_without_match: matches.is_present(FILES_WITHOUT_MATCH),
+        only_matching: matches.is_present(ONLY_MATCHING),
+        unique: matches.is_present(UNIQUE) || matches.is_present(UNIQUE_COUNT),
+        unique_count: matches.is_present(UNIQUE_COUNT),
+        quiet: matches.is_present(QUIET),
+        no_messages: matches.is_present(NO_MESSAGES),
+        colors: if color && !json {
+            build_colors()
+        } else {
+            Colors::default()
+        },
+        heading: heading && !vimgrep && !json && !formatted,
+        group_separator: match matches.value_of_lossy(GROUP_SEPARATOR) {
+            _ if matches.is_present(NO_GROUP_SEPARATOR) => None,
+            Some(sep) => Some(sep.into_owned()),
+            None => Some("--".to_string()),
+        },
+        with_filename: if vimgrep || matches.is_present(WITH_FILENAME) {
+            Some(true)
+        } else if matches.is_present(NO_FILENAME) {
+            Some(false)
+        } else {
+            None
+        },
+        null: matches.is_present(NULL),
+        field_match_separator: matches
+            .value_of_lossy(FIELD_MATCH_SEPARATOR)
+            .map_or_else(|| ":".to_string(), |sep| sep.into_owned()),
+        path_separator: matches
+            .value_of_lossy(PATH_SEPARATOR)
+            .map(|sep| sep.into_owned()),
+        hyperlink: hyperlink.filter(|_| color && !json),
+        label: matches.value_of_lossy(LABEL).map(|l| l.into_owned()),
+        binary_files: match matches.value_of(BINARY_FILES) {
+            _ if matches.is_present(TEXT) => BinaryFiles::Text,
+            Some("text") => BinaryFiles::Text,
+            Some("without-match") => BinaryFiles::WithoutMatch,
+            _ => BinaryFiles::Binary,
+        },
+        // Files are decompressed before they're transcoded
+        decoders: {
+            let mut decoders: Vec<Arc<dyn Decoder>> = vec![];
+            if matches.is_present(SEARCH_ZIP) {
+                decoders.push(Arc::new(Decompressor));
+            }
+            let encoding = match matches.value_of(ENCODING) {
+                None => None,
+                Some(label) => Some(
+                    Encoding::for_label(label.as_bytes())
+                        .ok_or_else(|| format!("Invalid encoding \"{}\"", label))?,
+                ),
+            };
+            decoders.push(Arc::new(Transcoder(encoding)));
+            decoders
+        },
+        // As with ripgrep, 0 means the default
+        threads: match parse_num(matches.value_of(THREADS), "thread count")? {
+            Some(threads) if threads > 0 => threads,
+            _ => thread::available_parallelism().map_or(1, |n| n.get()),
+        },
+        mmap: matches.is_present(MMAP),
+        max_memory: matches
+            .value_of(MAX_MEMORY)
+            .map(|v| parse_size(v, "memory limit"))
+            .transpose()?,
+        pre: matches.value_of_lossy(PRE).map(|p| p.into_owned()),
+        pre_glob: build_globs(matches.values_of(PRE_GLOB).into_iter().flatten())?,
+        line_buffered: !matches.is_present(BLOCK_BUFFERED)
+            && (matches.is_present(LINE_BUFFERED) || io::stdout().is_terminal()),
+        pager: matches.is_present(PAGER),
+        progress: matches
+            .is_present(PROGRESS)
+            .then(|| Arc::new(Meter::default()) as Arc<dyn Progress>),
+        vfs: None,
+    })
+}
+
+/// Default arguments from the file named by `GREPR_CONFIG_PATH`, or else
+/// `grepr/config` in the user's config directory if it exists
+fn config_args() -> MyResult<Vec<String>> {
+    let path = match std::env::var_os("GREPR_CONFIG_PATH") {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => {
+            let config_home = std::env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| {
+                    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+                });
+            match config_home.map(|dir| dir.join("grepr").join("config")) {
+                Some(path) if path.exists() => path,
+                _ => return Ok(vec![]),
+            }
+        }
+    };
+
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(parse_config(&text))
+}
+
+/// The arguments in a config file, one per line. Blank lines and lines
+/// starting with `#` are skipped.
+fn parse_config(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Splits the value of `GREPR_OPTIONS` into arguments at whitespace, as a
+/// shell would. Quotes and backslashes keep whitespace within an argument.
+fn split_options(options: &str) -> MyResult<Vec<String>> {
+    let mut res = vec![];
+    let mut arg: Option<String> = None;
+    let mut chars = options.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => res.extend(arg.take()),
+            '\'' => {
+                let quoted = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => quoted.push(c),
+                        None => return Err(From::from("Unterminated quote in GREPR_OPTIONS")),
+                    }
+                }
+            }
+            '"' => {
+                let quoted = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => quoted.extend(chars.next()),
+                        Some(c) => quoted.push(c),
+                        None => return Err(From::from("Unterminated quote in GREPR_OPTIONS")),
+                    }
+                }
+            }
+            '\\' => arg.get_or_insert_with(String::new).extend(chars.next()),
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    res.extend(arg);
+    Ok(res)
+}
+
+fn read_patterns(filename: &str) -> MyResult<Vec<String>> {
+    let file = open(&RealFs, Path::new(filename)).map_err(|e| format!("{}: {}", filename, e))?;
+    file.lines()
+        .collect::<io::Result<_>>()
+        .map_err(|e| From::from(format!("{}: {}", filename, e)))
+}
+
+/// Reads the paths in `list`, or stdin for -, each ended by `terminator`.
+/// Empty entries are passed over.
+fn read_file_list(list: &Path, terminator: u8) -> MyResult<Vec<PathBuf>> {
+    let warning = |e: &dyn Display| format!("{}: {}", list.display(), e);
+    let mut file = open(&RealFs, list).map_err(|e| warning(&e))?;
+    let mut paths = vec![];
+    loop {
+        let mut entry = vec![];
+        file.read_until(terminator, &mut entry)
+            .map_err(|e| warning(&e))?;
+        if entry.is_empty() {
+            return Ok(paths);
+        }
+        if entry.last() == Some(&terminator) {
+            entry.pop();
+        }
+        if !entry.is_empty() {
+            paths.push(path_from_bytes(entry));
+        }
+    }
+}
+
+/// A path from the bytes naming it, which are kept as they are on Unix
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    #[cfg(unix)]
+    let path = <OsString as std::os::unix::ffi::OsStringExt>::from_vec(bytes);
+    #[cfg(not(unix))]
+    let path = String::from_utf8_lossy(&bytes).into_owned();
+    PathBuf::from(path)
+}
+
+/// Reads a file of globs, one per line. Blank lines and lines starting
+/// with `#` are skipped.
+fn read_globs(filename: &str) -> MyResult<Vec<String>> {
+    let lines = read_patterns(filename)?;
+    Ok(lines
+        .into_iter()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .collect())
+}
+
+fn build_colors() -> Colors {
+    let mut colors = Colors::new();
+    if let Ok(spec) = std::env::var("GREP_COLORS") {
+        colors.apply_spec(&spec);
+    }
+    colors
+}
+
+/// Parses a size such as `512`, `64K` or `10M`, in powers of 1024
+fn parse_size(val: &str, what: &str) -> MyResult<u64> {
+    let (digits, shift) = match val.char_indices().last() {
+        Some((i, 'K')) => (&val[..i], 10),
+        Some((i, 'M')) => (&val[..i], 20),
+        Some((i, 'G')) => (&val[..i], 30),
+        _ => (val, 0),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| From::from(format!("Invalid {} \"{}\"", what, val)))
+}
+
+fn parse_limit(val: Option<&str>) -> MyResult<Option<usize>> {
+    val.map(|v| {
+        let size = parse_size(v, "size limit")?;
+        Ok(usize::try_from(size).unwrap_or(usize::MAX))
+    })
+    .transpose()
+}
+
+fn parse_num(val: Option<&str>, what: &str) -> MyResult<Option<usize>> {
+    match val {
+        None => Ok(None),
+        Some(v) => v
+            .parse()
+            .map(Some)
+            .map_err(|_| From::from(format!("Invalid {} \"{}\"", what, v))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_config, parse_size, split_options};
+
+    #[test]
+    fn test_parse_config() {
+        let text = "# Defaults\n--hidden\n\n  --exclude-dir  \n.git\n--color=auto\n";
+        assert_eq!(
+            parse_config(text),
+            ["--hidden", "--exclude-dir", ".git", "--color=auto"]
+        );
+        assert!(parse_config("").is_empty());
+    }
+
+    #[test]
+    fn test_split_options() {
+        assert_eq!(
+            split_options("  -i --exclude-dir .git  ").unwrap(),
+            ["-i", "--exclude-dir", ".git"]
+        );
+        assert_eq!(
+            split_options(r#"-e 'two words' --replace="a \"b\"" c\ d"#).unwrap(),
+            ["-e", "two words", r#"--replace=a "b""#, "c d"]
+        );
+        assert_eq!(split_options("-e ''").unwrap(), ["-e", ""]);
+        assert!(split_options("").unwrap().is_empty());
+        assert!(split_options("-e 'open").is_err());
+        assert!(split_options(r#"-e "open"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512", "file size").unwrap(), 512);
+        assert_eq!(parse_size("64K", "file size").unwrap(), 64 * 1024);
+        assert_eq!(parse_size("10M", "file size").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_size("2G", "file size").unwrap(), 2 << 30);
+        for bad in ["", "K", "1.5M", "10k", "-1", "99999999999999G"] {
+            assert!(parse_size(bad, "file size").is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! The errors a search can end with, as one enum to match on
+
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+/// Everything that can go wrong in the library. Each message is complete
+/// as it stands, naming the pattern or path at fault.
+#[derive(Debug, Error)]
+pub enum GreprError {
+    /// A pattern the engine rejected, named as it was given
+    #[error("Invalid pattern \"{0}\"")]
+    InvalidPattern(String),
+
+    /// A pattern that compiles to more than the size limit allows
+    #[error("Pattern \"{pattern}\" is too big to compile within {limit} bytes")]
+    PatternTooBig { pattern: String, limit: usize },
+
+    /// Too many fixed strings to search for together
+    #[error(transparent)]
+    Literals(#[from] aho_corasick::BuildError),
+
+    /// A glob that couldn't be compiled
+    #[error("Invalid glob \"{0}\"")]
+    InvalidGlob(String),
+
+    /// Options that can't be used together, or aren't built in
+    #[error("{0}")]
+    Unsupported(&'static str),
+
+    /// A directory given to search without recursion
+    #[error("{} is a directory", .0.display())]
+    IsADirectory(PathBuf),
+
+    /// A path that couldn't be read
+    #[error("{}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+
+    /// A symbolic link followed back to a directory the walk was already in
+    #[error("File system loop found: {} points to an ancestor {}", path.display(), ancestor.display())]
+    Loop { path: PathBuf, ancestor: PathBuf },
+
+    /// Input or output that failed with no path to name, such as a sink's
+    #[error(transparent)]
+    Stream(#[from] io::Error),
+
+    /// A search or walk stopped early by its cancel flag
+    #[error("Search cancelled")]
+    Cancelled,
+
+    /// An error of a sink's or callback's own, which ends the search
+    #[error("{0}")]
+    Other(Box<dyn Error + Send + Sync>),
+}
+
+/// A message of the caller's own, as an `Other` error
+impl From<String> for GreprError {
+    fn from(msg: String) -> Self {
+        GreprError::Other(msg.into())
+    }
+}
+
+impl From<&str> for GreprError {
+    fn from(msg: &str) -> Self {
+        GreprError::Other(msg.into())
+    }
+}
+
+impl GreprError {
+    /// The I/O error underneath, if there is one
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            GreprError::Io { source, .. } => Some(source),
+            GreprError::Stream(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// The path at fault, if the error names one
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            GreprError::IsADirectory(path)
+            | GreprError::Io { path, .. }
+            | GreprError::Loop { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GreprError;
+    use std::io;
+
+    #[test]
+    fn test_io_error() {
+        let e = GreprError::Io {
+            path: "a.txt".into(),
+            source: io::Error::new(io::ErrorKind::NotFound, "missing"),
+        };
+        assert_eq!(e.to_string(), "a.txt: missing");
+        assert_eq!(e.path(), Some(std::path::Path::new("a.txt")));
+        assert_eq!(
+            e.io_error().map(io::Error::kind),
+            Some(io::ErrorKind::NotFound)
+        );
+
+        let e = GreprError::from(io::Error::from(io::ErrorKind::BrokenPipe));
+        assert_eq!(
+            e.io_error().map(io::Error::kind),
+            Some(io::ErrorKind::BrokenPipe)
+        );
+        assert!(GreprError::InvalidPattern("(".to_string())
+            .io_error()
+            .is_none());
+        assert_eq!(GreprError::from("no good").to_string(), "no good");
+    }
+}
@@ -0,0 +1,350 @@
+//! A C interface to the library, with the "ffi" feature, declared for C in
+//! include/grepr.h. Build it as a library C can link with:
+//!
+//! ```text
+//! cargo rustc --release --lib --no-default-features --features ffi,fs --crate-type cdylib
+//! ```
+//!
+//! Every object handed out is freed by the function for its kind. An error
+//! comes back as a null pointer along with a message, if asked for one,
+//! which is freed with `grepr_string_free`.
+
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+use crate::{GreprError, Line, RegexOptions, SearchOptions, Searcher, Syntax};
+
+/// Case is ignored
+pub const GREPR_IGNORE_CASE: u32 = 1;
+/// Matches must be whole words
+pub const GREPR_WORD_REGEXP: u32 = 1 << 1;
+/// Matches must be whole lines
+pub const GREPR_LINE_REGEXP: u32 = 1 << 2;
+/// The pattern is a literal string
+pub const GREPR_FIXED_STRINGS: u32 = 1 << 3;
+/// The pattern is for the backtracking engine, with the "perl" feature
+pub const GREPR_PERL_REGEXP: u32 = 1 << 4;
+/// Lines that don't match are selected instead
+pub const GREPR_INVERT_MATCH: u32 = 1 << 5;
+/// The pattern may match across lines
+pub const GREPR_MULTILINE: u32 = 1 << 6;
+
+/// A compiled pattern, made by `grepr_searcher_new`
+pub struct GreprSearcher(Searcher);
+
+/// The matches found by a search, to go through with `grepr_matches_next`
+pub struct GreprMatches {
+    lines: Vec<Line>,
+    /// The line and span to hand out next
+    line: usize,
+    span: usize,
+}
+
+/// One match, or a selected line without any with `GREPR_INVERT_MATCH`.
+/// The line stays valid until its matches are freed.
+#[repr(C)]
+pub struct GreprMatch {
+    /// The 1-based line number
+    pub line_number: usize,
+    /// The byte offset of the line within the input
+    pub line_offset: usize,
+    /// The line, terminator included, as it was in the input and not
+    /// NUL-terminated
+    pub line: *const u8,
+    pub line_len: usize,
+    /// The byte range of the match within the line; empty for a line
+    /// without a match
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Compiles `pattern`, a NUL-terminated UTF-8 string, with any of the
+/// `GREPR_` flags. Returns null on error.
+///
+/// # Safety
+///
+/// `pattern` must be a valid C string, and `error` null or valid to write.
+#[no_mangle]
+pub unsafe extern "C" fn grepr_searcher_new(
+    pattern: *const c_char,
+    flags: u32,
+    error: *mut *mut c_char,
+) -> *mut GreprSearcher {
+    let pattern = match CStr::from_ptr(pattern).to_str() {
+        Ok(pattern) => pattern.to_string(),
+        Err(_) => {
+            set_error(error, "Pattern is not UTF-8");
+            return ptr::null_mut();
+        }
+    };
+    let has = |flag| flags & flag != 0;
+    let syntax = match (has(GREPR_FIXED_STRINGS), has(GREPR_PERL_REGEXP)) {
+        (true, true) => {
+            set_error(error, "Fixed strings and Perl patterns can't be combined");
+            return ptr::null_mut();
+        }
+        (true, false) => Syntax::Fixed,
+        (false, true) => Syntax::Perl,
+        (false, false) => Syntax::Regex,
+    };
+    let regex = RegexOptions {
+        insensitive: has(GREPR_IGNORE_CASE),
+        word_regexp: has(GREPR_WORD_REGEXP),
+        line_regexp: has(GREPR_LINE_REGEXP),
+        multiline: has(GREPR_MULTILINE),
+        ..Default::default()
+    };
+    let options = SearchOptions {
+        invert_match: has(GREPR_INVERT_MATCH),
+        multiline: has(GREPR_MULTILINE),
+        ..Default::default()
+    };
+    match Searcher::new(&[pattern], syntax, &regex, options) {
+        Ok(searcher) => Box::into_raw(Box::new(GreprSearcher(searcher))),
+        Err(e) => {
+            set_error(error, &e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+///
+/// `searcher` must be null or have come from `grepr_searcher_new`, and not
+/// be used again.
+#[no_mangle]
+pub unsafe extern "C" fn grepr_searcher_free(searcher: *mut GreprSearcher) {
+    if !searcher.is_null() {
+        drop(Box::from_raw(searcher));
+    }
+}
+
+/// Searches the `len` bytes at `buf`, which needn't be UTF-8. Returns null
+/// on error.
+///
+/// # Safety
+///
+/// `searcher` must be valid, `buf` valid to read for `len` bytes, and
+/// `error` null or valid to write.
+#[no_mangle]
+pub unsafe extern "C" fn grepr_search_buffer(
+    searcher: *const GreprSearcher,
+    buf: *const u8,
+    len: usize,
+    error: *mut *mut c_char,
+) -> *mut GreprMatches {
+    let bytes = if len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(buf, len)
+    };
+    let mut lines = vec![];
+    let res = (*searcher).0.search_slice("", bytes, &mut lines);
+    matches(res.map(|_| lines), error)
+}
+
+/// Searches the file at `path`, a NUL-terminated string. Returns null on
+/// error, as for a file that can't be read.
+///
+/// # Safety
+///
+/// `searcher` must be valid, `path` a valid C string, and `error` null or
+/// valid to write.
+#[cfg(feature = "fs")]
+#[no_mangle]
+pub unsafe extern "C" fn grepr_search_file(
+    searcher: *const GreprSearcher,
+    path: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut GreprMatches {
+    #[cfg(unix)]
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CStr::from_ptr(path);
+    // Paths are bytes on Unix, whether or not they're UTF-8
+    #[cfg(unix)]
+    let path = std::path::PathBuf::from(std::ffi::OsStr::from_bytes(path.to_bytes()));
+    #[cfg(not(unix))]
+    let path = std::path::PathBuf::from(path.to_string_lossy().into_owned());
+
+    let res = std::fs::File::open(&path)
+        .map_err(|source| GreprError::Io {
+            path: path.clone(),
+            source,
+        })
+        .and_then(|file| (*searcher).0.find_lines(std::io::BufReader::new(file)));
+    matches(res, error)
+}
+
+/// The next match, written to `out`. Returns 0 once there are no more.
+///
+/// # Safety
+///
+/// `matches` and `out` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn grepr_matches_next(
+    matches: *mut GreprMatches,
+    out: *mut GreprMatch,
+) -> i32 {
+    let matches = &mut *matches;
+    let Some(line) = matches.lines.get(matches.line) else {
+        return 0;
+    };
+    let (start, end) = line.spans.get(matches.span).copied().unwrap_or((0, 0));
+    *out = GreprMatch {
+        line_number: line.number,
+        line_offset: line.offset,
+        line: line.text.as_ptr(),
+        line_len: line.text.len(),
+        start,
+        end,
+    };
+    matches.span += 1;
+    if matches.span >= line.spans.len() {
+        matches.line += 1;
+        matches.span = 0;
+    }
+    1
+}
+
+/// # Safety
+///
+/// `matches` must be null or have come from a search, and not be used
+/// again.
+#[no_mangle]
+pub unsafe extern "C" fn grepr_matches_free(matches: *mut GreprMatches) {
+    if !matches.is_null() {
+        drop(Box::from_raw(matches));
+    }
+}
+
+/// Frees an error message
+///
+/// # Safety
+///
+/// `s` must be null or have come from this library, and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn grepr_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Hands out the selected lines of a search, or reports its error
+unsafe fn matches(
+    res: Result<Vec<Line>, GreprError>,
+    error: *mut *mut c_char,
+) -> *mut GreprMatches {
+    match res {
+        Ok(lines) => {
+            let lines = lines.into_iter().filter(|line| line.is_match).collect();
+            Box::into_raw(Box::new(GreprMatches {
+                lines,
+                line: 0,
+                span: 0,
+            }))
+        }
+        Err(e) => {
+            set_error(error, &e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+unsafe fn set_error(error: *mut *mut c_char, msg: &str) {
+    if !error.is_null() {
+        // A message can't hold a NUL, so one would end it early
+        let msg = msg.split('\0').next().unwrap_or_default();
+        *error = CString::new(msg).unwrap_or_default().into_raw();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "fs")]
+    use super::grepr_search_file;
+    use super::{
+        grepr_matches_free, grepr_matches_next, grepr_search_buffer, grepr_searcher_free,
+        grepr_searcher_new, grepr_string_free, GreprMatch, GreprMatches, GREPR_IGNORE_CASE,
+        GREPR_INVERT_MATCH,
+    };
+    use std::ffi::CStr;
+    use std::ptr;
+
+    /// Every match as its line number and matched text
+    unsafe fn collect(matches: *mut GreprMatches) -> Vec<(usize, String)> {
+        let mut res = vec![];
+        let mut m = GreprMatch {
+            line_number: 0,
+            line_offset: 0,
+            line: ptr::null(),
+            line_len: 0,
+            start: 0,
+            end: 0,
+        };
+        while grepr_matches_next(matches, &mut m) == 1 {
+            let line = std::slice::from_raw_parts(m.line, m.line_len);
+            let text = String::from_utf8_lossy(&line[m.start..m.end]).into_owned();
+            res.push((m.line_number, text));
+        }
+        grepr_matches_free(matches);
+        res
+    }
+
+    #[test]
+    fn test_search_buffer() {
+        unsafe {
+            let searcher = grepr_searcher_new(c"f.x".as_ptr(), GREPR_IGNORE_CASE, ptr::null_mut());
+            assert!(!searcher.is_null());
+            let text = b"a fox\nno\nFix fax\n";
+            let matches = grepr_search_buffer(searcher, text.as_ptr(), text.len(), ptr::null_mut());
+            assert_eq!(
+                collect(matches),
+                [
+                    (1, "fox".to_string()),
+                    (3, "Fix".to_string()),
+                    (3, "fax".to_string())
+                ]
+            );
+
+            // An inverted search hands out lines without matches
+            grepr_searcher_free(searcher);
+            let searcher = grepr_searcher_new(c"f.x".as_ptr(), GREPR_INVERT_MATCH, ptr::null_mut());
+            let matches = grepr_search_buffer(searcher, text.as_ptr(), text.len(), ptr::null_mut());
+            assert_eq!(collect(matches), [(2, String::new())]);
+            grepr_searcher_free(searcher);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        unsafe {
+            let mut error = ptr::null_mut();
+            let searcher = grepr_searcher_new(c"(".as_ptr(), 0, &mut error);
+            assert!(searcher.is_null());
+            assert_eq!(CStr::from_ptr(error).to_str(), Ok("Invalid pattern \"(\""));
+            grepr_string_free(error);
+
+            #[cfg(feature = "fs")]
+            {
+                let searcher = grepr_searcher_new(c"fox".as_ptr(), 0, ptr::null_mut());
+                let matches =
+                    grepr_search_file(searcher, c"tests/inputs/fox.txt".as_ptr(), ptr::null_mut());
+                assert_eq!(collect(matches), [(1, "fox".to_string())]);
+
+                let mut error = ptr::null_mut();
+                let matches = grepr_search_file(
+                    searcher,
+                    c"tests/inputs/nonexistent.txt".as_ptr(),
+                    &mut error,
+                );
+                assert!(matches.is_null());
+                assert!(CStr::from_ptr(error)
+                    .to_string_lossy()
+                    .starts_with("tests/inputs/nonexistent.txt: "));
+                grepr_string_free(error);
+                grepr_searcher_free(searcher);
+            }
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! The rules in `.gitignore` and `.ignore` files, which leave paths out of a
+//! recursive search
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use tracing::debug;
+
+use crate::Vfs;
+
+/// The patterns of one ignore file
+#[derive(Debug)]
+pub struct IgnoreFile {
+    globs: GlobSet,
+    rules: Vec<Rule>,
+}
+
+/// One line of an ignore file
+#[derive(Debug)]
+struct Rule {
+    /// The line as written, for --debug
+    line: String,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl IgnoreFile {
+    /// Parses the lines of an ignore file, dropping any that aren't valid globs
+    pub fn parse(text: &str) -> IgnoreFile {
+        let mut builder = GlobSetBuilder::new();
+        let mut rules = vec![];
+        for line in text.lines() {
+            let Some((glob, negated, dir_only)) = parse_line(line) else {
+                continue;
+            };
+            let glob = GlobBuilder::new(&glob).literal_separator(true).build();
+            if let Ok(glob) = glob {
+                builder.add(glob);
+                rules.push(Rule {
+                    line: line.trim().to_string(),
+                    negated,
+                    dir_only,
+                });
+            }
+        }
+
+        IgnoreFile {
+            globs: builder.build().unwrap_or_else(|_| GlobSet::empty()),
+            rules,
+        }
+    }
+
+    /// The rule that decides whether `path`, relative to the ignore file's
+    /// directory, is ignored or, if negated, explicitly kept. As in git, the
+    /// last pattern that matches decides.
+    fn rule(&self, path: &Path, is_dir: bool) -> Option<&Rule> {
+        self.globs
+            .matches(path)
+            .into_iter()
+            .rev()
+            .map(|i| &self.rules[i])
+            .find(|rule| is_dir || !rule.dir_only)
+    }
+}
+
+/// Turns a line of an ignore file into a glob matching paths relative to
+/// the file's directory, whether it's negated, and whether it only matches
+/// directories. Blank lines and comments give `None`.
+fn parse_line(line: &str) -> Option<(String, bool, bool)> {
+    // Trailing spaces are dropped unless escaped
+    let mut line = line.trim_end_matches('\r');
+    while line.ends_with(' ') && !line.ends_with("\\ ") {
+        line = &line[..line.len() - 1];
+    }
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (line, negated) = match line.strip_prefix('!') {
+        Some(rest) => (rest, true),
+        // A leading backslash escapes a `!` or `#`
+        None if line.starts_with("\\!") || line.starts_with("\\#") => (&line[1..], false),
+        None => (line, false),
+    };
+    let (line, dir_only) = match line.strip_suffix('/') {
+        Some(rest) => (rest, true),
+        None => (line, false),
+    };
+    if line.is_empty() {
+        return None;
+    }
+
+    // A pattern with a slash other than at its end is relative to the
+    // file's directory, and otherwise matches at any depth
+    let glob = match line.strip_prefix('/') {
+        Some(rest) => rest.to_string(),
+        None if line.contains('/') => line.to_string(),
+        None => format!("**/{}", line),
+    };
+    Some((glob, negated, dir_only))
+}
+
+/// The ignore files that apply under one of the paths searched, read from
+/// `vfs` as the directories holding them are reached
+#[derive(Debug)]
+pub struct Ignores<'a> {
+    vfs: &'a dyn Vfs,
+    /// The highest directory whose ignore files count: the root of the git
+    /// repository the search is in, or else the path searched
+    top: PathBuf,
+    /// The names of the ignore files that count, in order of precedence
+    names: Vec<&'static str>,
+    files: HashMap<PathBuf, Vec<(&'static str, IgnoreFile)>>,
+}
+
+impl<'a> Ignores<'a> {
+    /// `root` is the canonical path the search starts from. `dot` says
+    /// whether `.ignore` files count, and `vcs` whether `.gitignore` files
+    /// do, though they only ever count inside a git repository.
+    pub fn new(vfs: &'a dyn Vfs, root: &Path, dot: bool, vcs: bool) -> Ignores<'a> {
+        let repo = root
+            .ancestors()
+            .find(|dir| vfs.metadata(&dir.join(".git")).is_ok());
+        let mut names = vec![];
+        if dot {
+            names.push(".ignore");
+        }
+        if vcs && repo.is_some() {
+            names.push(".gitignore");
+        }
+        Ignores {
+            vfs,
+            top: repo.unwrap_or(root).to_path_buf(),
+            names,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Whether a canonical `path` under the root is ignored. The ignore files
+    /// of deeper directories take precedence, and in each directory `.ignore`
+    /// takes precedence over `.gitignore`.
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        for dir in path.ancestors().skip(1) {
+            if !dir.starts_with(&self.top) {
+                break;
+            }
+
+            let (vfs, names) = (self.vfs, &self.names);
+            let files = self
+                .files
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| read_ignore_files(vfs, dir, names));
+            let relative = path.strip_prefix(dir).unwrap_or(path);
+            let found = files
+                .iter()
+                .find_map(|(name, file)| Some((name, file.rule(relative, is_dir)?)));
+            if let Some((name, rule)) = found {
+                debug!(
+                    "{}: {} by \"{}\" in {}",
+                    path.display(),
+                    if rule.negated { "kept" } else { "ignored" },
+                    rule.line,
+                    dir.join(name).display()
+                );
+                return !rule.negated;
+            }
+        }
+        false
+    }
+}
+
+/// The ignore files in `dir` with the given names
+fn read_ignore_files(
+    vfs: &dyn Vfs,
+    dir: &Path,
+    names: &[&'static str],
+) -> Vec<(&'static str, IgnoreFile)> {
+    names
+        .iter()
+        .filter_map(|&name| {
+            let mut text = String::new();
+            vfs.open(&dir.join(name))
+                .ok()?
+                .read_to_string(&mut text)
+                .ok()?;
+            debug!("read {}", dir.join(name).display());
+            Some((name, IgnoreFile::parse(&text)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_line, IgnoreFile, Ignores};
+    use crate::{MemoryFs, RealFs};
+    use std::path::Path;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line(""), None);
+        assert_eq!(parse_line("# comment"), None);
+        assert_eq!(
+            parse_line("*.log"),
+            Some(("**/*.log".to_string(), false, false))
+        );
+        assert_eq!(
+            parse_line("target/"),
+            Some(("**/target".to_string(), false, true))
+        );
+        assert_eq!(
+            parse_line("/build"),
+            Some(("build".to_string(), false, false))
+        );
+        assert_eq!(
+            parse_line("doc/*.txt"),
+            Some(("doc/*.txt".to_string(), false, false))
+        );
+        assert_eq!(
+            parse_line("!keep.log  "),
+            Some(("**/keep.log".to_string(), true, false))
+        );
+        assert_eq!(
+            parse_line("\\#hash"),
+            Some(("**/#hash".to_string(), false, false))
+        );
+        assert_eq!(
+            parse_line("\\!bang"),
+            Some(("**/!bang".to_string(), false, false))
+        );
+    }
+
+    #[test]
+    fn test_matched() {
+        let file = IgnoreFile::parse("*.log\n!keep.log\ntarget/\n/root.txt\ndoc/*.md\n");
+        let matched =
+            |path: &str, is_dir| file.rule(Path::new(path), is_dir).map(|rule| !rule.negated);
+
+        assert_eq!(matched("debug.log", false), Some(true));
+        assert_eq!(matched("a/b/debug.log", false), Some(true));
+        assert_eq!(matched("a/keep.log", false), Some(false));
+        assert_eq!(matched("target", true), Some(true));
+        assert_eq!(matched("target", false), None);
+        assert_eq!(matched("root.txt", false), Some(true));
+        assert_eq!(matched("sub/root.txt", false), None);
+        assert_eq!(matched("doc/a.md", false), Some(true));
+        assert_eq!(matched("doc/sub/a.md", false), None);
+        assert_eq!(matched("main.rs", false), None);
+    }
+
+    #[test]
+    fn test_ignores() {
+        let dir = std::env::temp_dir().join(format!("grepr-ignores-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("repo/.git")).unwrap();
+        std::fs::create_dir_all(dir.join("repo/sub")).unwrap();
+        std::fs::write(dir.join("repo/.gitignore"), "*.log\n").unwrap();
+        std::fs::write(dir.join("repo/sub/.gitignore"), "!keep.log\n").unwrap();
+        std::fs::write(dir.join("repo/sub/.ignore"), "notes.txt\n").unwrap();
+
+        let mut ignores = Ignores::new(&RealFs, &dir.join("repo/sub"), true, true);
+        let ignored = ignores.is_ignored(&dir.join("repo/sub/debug.log"), false);
+        let kept = ignores.is_ignored(&dir.join("repo/sub/keep.log"), false);
+        let notes = ignores.is_ignored(&dir.join("repo/sub/notes.txt"), false);
+        let plain = ignores.is_ignored(&dir.join("repo/sub/main.rs"), false);
+
+        // Each kind of file can be turned off on its own
+        let mut no_vcs = Ignores::new(&RealFs, &dir.join("repo"), true, false);
+        let no_vcs_log = no_vcs.is_ignored(&dir.join("repo/sub/debug.log"), false);
+        let no_vcs_notes = no_vcs.is_ignored(&dir.join("repo/sub/notes.txt"), false);
+        let mut no_dot = Ignores::new(&RealFs, &dir.join("repo"), false, true);
+        let no_dot_log = no_dot.is_ignored(&dir.join("repo/sub/debug.log"), false);
+        let no_dot_notes = no_dot.is_ignored(&dir.join("repo/sub/notes.txt"), false);
+
+        // Outside a repository only .ignore files count
+        std::fs::remove_dir(dir.join("repo/.git")).unwrap();
+        let mut outside = Ignores::new(&RealFs, &dir.join("repo"), true, true);
+        let outside_log = outside.is_ignored(&dir.join("repo/sub/debug.log"), false);
+        let outside_notes = outside.is_ignored(&dir.join("repo/sub/notes.txt"), false);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(ignored);
+        assert!(!kept);
+        assert!(notes);
+        assert!(!plain);
+        assert!(!no_vcs_log);
+        assert!(no_vcs_notes);
+        assert!(no_dot_log);
+        assert!(!no_dot_notes);
+        assert!(!outside_log);
+        assert!(outside_notes);
+    }
+
+    #[test]
+    fn test_ignores_vfs() {
+        // The files are read from the file system walked, whose root is ""
+        let mut vfs = MemoryFs::new();
+        vfs.insert(".git/HEAD", "ref\n");
+        vfs.insert(".gitignore", "*.log\n");
+        vfs.insert("sub/.ignore", "notes.txt\n");
+        let mut ignores = Ignores::new(&vfs, Path::new("sub"), true, true);
+        assert!(ignores.is_ignored(Path::new("sub/debug.log"), false));
+        assert!(ignores.is_ignored(Path::new("sub/notes.txt"), false));
+        assert!(!ignores.is_ignored(Path::new("sub/main.rs"), false));
+    }
+}
@@ -1,243 +1,1101 @@
-use std::fmt::Write;
-use std::io::{self, BufRead, BufReader};
-use std::{error::Error, vec};
+//! Line-by-line searching with grep's options, which the `grepr` command
+//! is built on. A [`Searcher`] compiles the patterns and runs over any
+//! reader, and [`find_files`] picks out the files to search the way the
+//! command does. Finding files takes the "fs" feature, which the command
+//! turns on; without it the library builds for wasm32-unknown-unknown and
+//! searches only what it's handed, as with [`Searcher::find_in_str`].
+//!
+//! ```
+//! use grepr::{RegexOptions, SearchOptions, Searcher, Syntax};
+//!
+//! let patterns = ["fox".to_string()];
+//! let options = SearchOptions::default();
+//! let searcher =
+//!     Searcher::new(&patterns, Syntax::Regex, &RegexOptions::default(), options).unwrap();
+//! let lines = searcher.find_lines("a dog\na fox\n".as_bytes()).unwrap();
+//! assert_eq!(lines[0].number, 2);
+//! assert_eq!(lines[0].spans, [(2, 5)]);
+//! ```
 
-use clap::{App, Arg};
-use regex::{Regex, RegexBuilder};
-use walkdir::WalkDir;
+use std::collections::{vec_deque, VecDeque};
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::time::Instant;
 
-const PATTERN: &'static str = "pattern";
-const FILE: &'static str = "file";
-const RECURSIVE: &'static str = "recursive";
-const INVERT_MATCH: &'static str = "invert-match";
-const COUNT: &'static str = "count";
-const INSENSITIVE: &'static str = "insensitive";
+use aho_corasick::{AhoCorasick, MatchKind};
+use memchr::memmem::Finder;
+use regex::bytes::{Regex, RegexBuilder};
+use tracing::debug;
 
-type MyResult<T> = Result<T, Box<dyn Error>>;
+mod ascii;
+#[cfg(feature = "async")]
+mod async_search;
+// Only a run has a memory limit to set
+#[cfg_attr(not(feature = "run"), allow(dead_code))]
+mod budget;
+#[cfg(feature = "cli")]
+pub mod cli;
+mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fs")]
+mod ignore;
+mod matcher;
+mod posix;
+#[cfg(feature = "run")]
+mod run;
+mod sink;
+mod stats;
+#[cfg(feature = "fs")]
+mod vfs;
+#[cfg(feature = "fs")]
+mod walk;
+#[cfg(feature = "fs")]
+mod walker;
 
-#[derive(Debug)]
-pub struct Config {
-    pattern: Regex,
-    files: Vec<String>,
-    recursive: bool,
-    count: bool,
-    invert_match: bool,
-}
-
-pub fn get_args() -> MyResult<Config> {
-    let matches = App::new("grepr")
-        .version("0.1.0")
-        .author("Myron Lioz <liozmyron@gmail.com>")
-        .about("Rust grep")
-        .arg(
-            Arg::with_name(PATTERN)
-                .value_name("PATTERN")
-                .help("Search pattern")
-                .required(true),
-        )
-        .arg(
-            Arg::with_name(FILE)
-                .value_name("FILE")
-                .help("Input file(s)")
-                .default_value("-")
-                .multiple(true),
-        )
-        .arg(
-            Arg::with_name(COUNT)
-                .help("Count occurences.")
-                .short("c")
-                .long("count")
-                .takes_value(false),
-        )
-        .arg(
-            Arg::with_name(INSENSITIVE)
-                .help("Case-insensitive")
-                .short("i")
-                .long("insensitive")
-                .takes_value(false),
-        )
-        .arg(
-            Arg::with_name(INVERT_MATCH)
-                .help("Invert match")
-                .short("v")
-                .long("invert-match")
-                .takes_value(false),
-        )
-        .arg(
-            Arg::with_name(RECURSIVE)
-                .help("Recursive search")
-                .short("r")
-                .long("recursive")
-                .takes_value(false),
-        )
-        .get_matches();
-
-    let pattern_str = matches.value_of(PATTERN).unwrap();
-    let pattern = RegexBuilder::new(pattern_str)
-        .case_insensitive(matches.is_present(INSENSITIVE))
-        .build()
-        .map_err(|_| format!("Invalid pattern \"{}\"", pattern_str))?;
-
-    Ok(Config {
-        pattern,
-        files: matches.values_of_lossy(FILE).unwrap(),
-        recursive: matches.is_present(RECURSIVE),
-        count: matches.is_present(COUNT),
-        invert_match: matches.is_present(INVERT_MATCH),
-    })
+#[cfg(feature = "async")]
+pub use async_search::AsyncSearcher;
+use budget::Allowance;
+pub use error::GreprError;
+#[cfg(feature = "perl")]
+use matcher::Fancy;
+pub use matcher::Matcher;
+use matcher::{Literal, Literals};
+#[cfg(feature = "run")]
+pub use run::{
+    run, run_with_output, search, Config, ConfigBuilder, Decoder, Decompressor, FileResult,
+    Progress, Search, Status, Summary,
+};
+pub use sink::Sink;
+pub use stats::SearchStats;
+#[cfg(feature = "fs")]
+pub use vfs::{MemoryFs, Metadata, RealFs, Vfs};
+#[cfg(feature = "fs")]
+pub use walk::{build_globs, find_files, find_files_in, SortBy, WalkOptions};
+#[cfg(feature = "fs")]
+pub use walker::FileWalker;
+
+type MyResult<T> = Result<T, GreprError>;
+
+/// Controls how the patterns of a `Searcher` are compiled
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RegexOptions {
+    /// Case is ignored
+    pub insensitive: bool,
+    /// Matches must be whole words
+    pub word_regexp: bool,
+    /// Matches must be whole lines
+    pub line_regexp: bool,
+    /// ^ and $ anchor at every line of the text searched
+    pub multiline: bool,
+    /// ^ and $ anchor on either side of a CRLF
+    pub crlf: bool,
+    /// \w, \d, \s and \b only know ASCII
+    pub ascii: bool,
+    /// The most memory a compiled regex may take, where not the default
+    pub size_limit: Option<usize>,
+    /// The most memory the lazy DFA may cache, where not the default
+    pub dfa_size_limit: Option<usize>,
+}
+
+/// Controls which lines a `Searcher` selects from its input
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SearchOptions {
+    /// Select the lines that don't match instead
+    pub invert_match: bool,
+    /// How many lines before each selected one to keep as context
+    pub before_context: usize,
+    /// How many lines after each selected one to keep as context
+    pub after_context: usize,
+    /// Stop after selecting this many lines
+    pub max_count: Option<usize>,
+    /// Records are terminated by NUL rather than newline
+    pub null_data: bool,
+    /// Matches may span records, so the whole input is searched at once
+    pub multiline: bool,
+    /// Every line is kept, as though the context were unlimited
+    pub passthru: bool,
+    /// Lines end in CRLF, and the CR is no part of their content
+    pub crlf: bool,
 }
 
-pub fn run(config: Config) -> MyResult<()> {
-    let file_paths = find_files(&config.files, config.recursive);
-    let many_files = file_paths.len() > 1;
+impl SearchOptions {
+    /// Hands the next record, including its terminator, to `f` along with
+    /// its length in the input. A record that's already whole in the
+    /// reader's buffer is passed from there rather than copied into
+    /// `buffer`. With a `budget` the record may be cut short. Returns
+    /// `None` at the end of the input.
+    fn with_record<T: BufRead, R>(
+        &self,
+        file: &mut T,
+        buffer: &mut Vec<u8>,
+        budget: Option<&Allowance>,
+        f: impl FnOnce(&[u8], usize) -> R,
+    ) -> io::Result<Option<R>> {
+        let terminator = if self.null_data { b'\0' } else { b'\n' };
+        if let Some(budget) = budget {
+            return budget.with_record(file, terminator, buffer, f);
+        }
+        let buf = file.fill_buf()?;
+        if let Some(end) = memchr::memchr(terminator, buf) {
+            let res = f(&buf[..=end], end + 1);
+            file.consume(end + 1);
+            return Ok(Some(res));
+        }
 
-    for path in file_paths {
-        match path {
-            Err(e) => eprintln!("{}", e),
-            Ok(path) => match open(&path) {
-                Err(e) => eprintln!("{}: {}", path, e),
-                Ok(file) => {
-                    let matches = find_lines(file, &config.pattern, config.invert_match)?;
+        buffer.clear();
+        let len = file.read_until(terminator, buffer)?;
+        if len == 0 {
+            return Ok(None);
+        }
+        Ok(Some(f(buffer, len)))
+    }
 
-                    let mut prefix = String::new();
-                    if many_files {
-                        write!(prefix, "{}:", path)?;
-                    }
+    /// The part of a record the pattern is matched against, without its terminator
+    fn content<'a>(&self, record: &'a [u8]) -> &'a [u8] {
+        let terminator = if self.null_data { b"\0" } else { b"\n" };
+        let content = record.strip_suffix(terminator).unwrap_or(record);
+        if self.crlf && !self.null_data {
+            content.strip_suffix(b"\r").unwrap_or(content)
+        } else {
+            content
+        }
+    }
+}
 
-                    if config.count {
-                        println!("{}{}", prefix, matches.len());
-                        continue;
-                    }
+/// How patterns given on the command line are parsed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    /// The `regex` crate's own syntax
+    Regex,
+    /// POSIX basic regular expressions (-G)
+    Basic,
+    /// POSIX extended regular expressions (-E)
+    Extended,
+    /// Literal strings (-F)
+    Fixed,
+    /// `fancy-regex` syntax, with lookaround and backreferences (-P)
+    Perl,
+}
 
-                    for match_ in &matches {
-                        print!("{}{}", prefix, match_);
-                    }
-                }
-            },
+/// A line wanted from the input, either selected or kept as context
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Line {
+    /// The 1-based line number
+    pub number: usize,
+    /// Byte offset of the start of the line within the input
+    pub offset: usize,
+    /// The whole line, terminator included, as it was in the input
+    #[cfg_attr(feature = "serde", serde(with = "line_text"))]
+    pub text: Vec<u8>,
+    /// Whether the line was selected rather than kept as context
+    pub is_match: bool,
+    /// Byte ranges of every occurrence of the pattern within `text`
+    pub spans: Vec<(usize, usize)>,
+}
+
+/// One match within a line, with its capture groups
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Match {
+    /// Byte range of the whole match within the line's text
+    pub span: (usize, usize),
+    /// Byte range of each capture group from `$1` on, or `None` for a group
+    /// that took no part in the match
+    pub groups: Vec<Option<(usize, usize)>>,
+}
+
+/// Serializes the text of a line as a string where it's UTF-8, as it nearly
+/// always is, and as its bytes where it isn't
+#[cfg(feature = "serde")]
+mod line_text {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Text {
+        Str(String),
+        Bytes(Vec<u8>),
+    }
+
+    pub fn serialize<S: Serializer>(text: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(text) {
+            Ok(text) => text.serialize(serializer),
+            Err(_) => text.serialize(serializer),
         }
     }
 
-    Ok(())
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        Ok(match Text::deserialize(deserializer)? {
+            Text::Str(text) => text.into_bytes(),
+            Text::Bytes(bytes) => bytes,
+        })
+    }
 }
 
-fn open(filename: &str) -> MyResult<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(std::fs::File::open(filename)?))),
+/// Whether a pattern contains an uppercase letter, for --smart-case. In a
+/// regex the character after a backslash is syntax, as in `\S` or `\W`.
+pub fn has_uppercase(pattern: &str, syntax: Syntax) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' && syntax != Syntax::Fixed {
+            chars.next();
+        } else if c.is_uppercase() {
+            return true;
+        }
     }
+    false
 }
 
-fn find_files(paths: &[String], recursive: bool) -> Vec<MyResult<String>> {
-    let mut res = vec![];
-    for path in paths {
-        if path == "-" {
-            res.push(Ok(path.to_owned()));
-            continue;
-        }
-
-        for dir_entry in WalkDir::new(path) {
-            match dir_entry {
-                Err(e) => res.push(Err(From::from(format!(
-                    "{}: {}",
-                    path,
-                    e.io_error().unwrap().to_string()
-                )))),
-                Ok(dir) => {
-                    if dir.file_type().is_dir() && recursive == false {
-                        res.push(Err(From::from(format!("{} is a directory", path))));
-                        break;
-                    }
-
-                    if dir.file_type().is_file() {
-                        res.push(Ok(dir.path().display().to_string()));
-                    }
+fn build_pattern(
+    originals: &[String],
+    options: &RegexOptions,
+    syntax: Syntax,
+) -> MyResult<Box<dyn Matcher>> {
+    if syntax == Syntax::Fixed
+        && originals.len() == 1
+        && !(options.insensitive || options.word_regexp || options.line_regexp)
+    {
+        debug!("searching for {:?} as a plain substring", originals[0]);
+        return Ok(Box::new(Literal(Finder::new(&originals[0]).into_owned())));
+    }
+
+    // Many literals, as in a blocklist read with -f, would make an
+    // alternation too big to compile. Empty ones are left to the regex
+    // engine, which only matches them between characters.
+    if syntax == Syntax::Fixed
+        && originals.len() > 1
+        && !(options.insensitive || options.word_regexp || options.line_regexp)
+        && originals.iter().all(|p| !p.is_empty())
+    {
+        debug!(
+            "searching for {} literals with Aho-Corasick",
+            originals.len()
+        );
+        // Leftmost-first picks the same match as the alternation would
+        let ac = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostFirst)
+            .build(originals)?;
+        return Ok(Box::new(Literals(ac)));
+    }
+
+    // Anything beyond a single plain substring is left to the regex engine
+    let patterns = originals
+        .iter()
+        .map(|p| match syntax {
+            Syntax::Regex | Syntax::Perl => Ok(p.clone()),
+            Syntax::Fixed => Ok(regex::escape(p)),
+            Syntax::Basic | Syntax::Extended => posix::translate(p, syntax == Syntax::Basic)
+                .ok_or_else(|| GreprError::InvalidPattern(p.clone())),
+        })
+        .collect::<MyResult<Vec<_>>>()?;
+
+    if patterns.is_empty() {
+        // An empty pattern list (e.g. from an empty -f file) matches nothing
+        let nothing = build_regex(r"[^\s\S]", &RegexOptions::default()).unwrap();
+        return Ok(Box::new(nothing));
+    }
+
+    if syntax == Syntax::Perl {
+        return build_fancy(&patterns, options);
+    }
+
+    // Errors name the pattern as given rather than its translation or the
+    // combined alternation
+    if let [pattern] = patterns.as_slice() {
+        return build_regex(pattern, options)
+            .map(|re| Box::new(re) as Box<dyn Matcher>)
+            .map_err(|e| regex_error(e, &originals[0]));
+    }
+
+    let each = RegexOptions {
+        word_regexp: false,
+        line_regexp: false,
+        ..*options
+    };
+    for (pattern, original) in patterns.iter().zip(originals) {
+        build_regex(pattern, &each).map_err(|e| regex_error(e, original))?;
+    }
+
+    let alternation = patterns
+        .iter()
+        .map(|p| format!("(?:{})", p))
+        .collect::<Vec<_>>()
+        .join("|");
+    build_regex(&alternation, options)
+        .map(|re| Box::new(re) as Box<dyn Matcher>)
+        .map_err(|e| regex_error(e, &alternation))
+}
+
+fn build_regex(pattern: &str, options: &RegexOptions) -> Result<Regex, regex::Error> {
+    // The half boundaries only look outside the match, so patterns that
+    // begin or end with a non-word character still work with -w
+    let mut regex_str = if options.line_regexp {
+        format!("^(?:{})$", pattern)
+    } else if options.word_regexp {
+        format!(r"\b{{start-half}}(?:{})\b{{end-half}}", pattern)
+    } else {
+        pattern.to_string()
+    };
+    if options.ascii {
+        regex_str = ascii::translate(&regex_str, false);
+    }
+
+    // Within a whole file, ^ and $ should still anchor at each line, and
+    // with --crlf on either side of a CRLF. There's no clock to time the
+    // compile by on wasm32-unknown-unknown.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    let started = Instant::now();
+    let mut builder = RegexBuilder::new(&regex_str);
+    builder
+        .case_insensitive(options.insensitive)
+        .multi_line(options.multiline)
+        .crlf(options.crlf);
+    if let Some(limit) = options.size_limit {
+        builder.size_limit(limit);
+    }
+    if let Some(limit) = options.dfa_size_limit {
+        builder.dfa_size_limit(limit);
+    }
+    let re = builder.build().inspect_err(|e| {
+        debug!("{:?} failed to compile: {}", regex_str, e);
+    })?;
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    debug!(
+        ?options,
+        "compiled {:?} in {:?}",
+        regex_str,
+        started.elapsed()
+    );
+    Ok(re)
+}
+
+#[cfg(feature = "perl")]
+fn build_fancy(patterns: &[String], options: &RegexOptions) -> MyResult<Box<dyn Matcher>> {
+    // The backtracking engine's $ only ever anchors before LF
+    if options.multiline && options.crlf {
+        return Err(GreprError::Unsupported(
+            "--crlf is not supported with -P and -U together",
+        ));
+    }
+
+    let compile = |pattern: &str| {
+        let mut regex_str = if options.line_regexp {
+            format!("^(?:{})$", pattern)
+        } else if options.word_regexp {
+            format!(r"(?<!\w)(?:{})(?!\w)", pattern)
+        } else {
+            pattern.to_string()
+        };
+        if options.ascii {
+            regex_str = ascii::translate(&regex_str, true);
+        }
+        if options.multiline {
+            regex_str.insert_str(0, "(?m)");
+        }
+        if options.insensitive {
+            regex_str.insert_str(0, "(?i)");
+        }
+        let mut builder = fancy_regex::RegexBuilder::new(&regex_str);
+        if let Some(limit) = options.size_limit {
+            builder.delegate_size_limit(limit);
+        }
+        if let Some(limit) = options.dfa_size_limit {
+            builder.delegate_dfa_size_limit(limit);
+        }
+        builder.build().map_err(|e| match e {
+            fancy_regex::Error::CompileError(fancy_regex::CompileError::InnerError(e)) => {
+                match e.size_limit() {
+                    Some(limit) => too_big(pattern, limit),
+                    None => GreprError::InvalidPattern(pattern.to_string()),
                 }
-            };
+            }
+            _ => GreprError::InvalidPattern(pattern.to_string()),
+        })
+    };
+
+    // Unlike with the regex engine the patterns aren't joined into one
+    // alternation, which would renumber their groups
+    patterns
+        .iter()
+        .map(|p| compile(p))
+        .collect::<MyResult<_>>()
+        .map(|res| Box::new(Fancy(res)) as Box<dyn Matcher>)
+}
+
+#[cfg(not(feature = "perl"))]
+fn build_fancy(_: &[String], _: &RegexOptions) -> MyResult<Box<dyn Matcher>> {
+    Err(GreprError::Unsupported(
+        "-P is not supported: grepr was built without the \"perl\" feature",
+    ))
+}
+
+/// The error for a pattern the regex engine rejected, named as it was given
+fn regex_error(e: regex::Error, pattern: &str) -> GreprError {
+    match e {
+        regex::Error::CompiledTooBig(limit) => too_big(pattern, limit),
+        _ => GreprError::InvalidPattern(pattern.to_string()),
+    }
+}
+
+fn too_big(pattern: &str, limit: usize) -> GreprError {
+    GreprError::PatternTooBig {
+        pattern: pattern.to_string(),
+        limit,
+    }
+}
+
+fn is_cancelled(cancel: Option<&AtomicBool>) -> bool {
+    cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+}
+
+/// A reader that runs dry once its search is cancelled, which a search takes
+/// as the end of the input
+struct Cancellable<'a, R> {
+    inner: R,
+    cancel: Option<&'a AtomicBool>,
+}
+
+impl<R: Read> Read for Cancellable<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if is_cancelled(self.cancel) {
+            return Ok(0);
         }
+        self.inner.read(buf)
     }
+}
 
-    res
+impl<R: BufRead> BufRead for Cancellable<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if is_cancelled(self.cancel) {
+            return Ok(&[]);
+        }
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+    }
 }
 
 fn find_lines<T: BufRead>(
-    mut file: T,
-    pattern: &Regex,
-    invert_match: bool,
-) -> MyResult<Vec<String>> {
+    file: T,
+    pattern: &dyn Matcher,
+    options: &SearchOptions,
+) -> MyResult<Vec<Line>> {
     let mut res = vec![];
+    for_each_line(file, pattern, options, None, |line| {
+        res.push(line);
+        Ok(())
+    })?;
+    Ok(res)
+}
+
+/// Like `find_lines`, but hands each line to `emit` as soon as it is known
+/// to be wanted, so output can keep up with input that arrives slowly.
+/// With a `budget` what's held is cut short to fit it.
+fn for_each_line<T: BufRead>(
+    mut file: T,
+    pattern: &dyn Matcher,
+    options: &SearchOptions,
+    budget: Option<&Allowance>,
+    emit: impl FnMut(Line) -> MyResult<()>,
+) -> MyResult<()> {
+    if !options.multiline {
+        return search_lines(
+            file,
+            options,
+            budget,
+            |content, _| pattern.find_spans(content),
+            emit,
+        );
+    }
+
+    let mut bytes = vec![];
+    let Some(budget) = budget else {
+        file.read_to_end(&mut bytes)?;
+        return search_text(&bytes, pattern, options, emit);
+    };
+    budget.read_to_end(&mut file, &mut bytes)?;
+    let res = search_text(&bytes, pattern, options, emit);
+    budget.budget.release(bytes.len());
+    res
+}
 
-    let mut buffer = String::new();
-    while let Ok(bytes) = file.read_line(&mut buffer) {
-        if bytes == 0 {
+/// Searches input that's all in memory for a multiline pattern, which runs
+/// over the whole text. Each line gets the parts of the matches that fall
+/// within it.
+fn search_text(
+    text: &[u8],
+    pattern: &dyn Matcher,
+    options: &SearchOptions,
+    emit: impl FnMut(Line) -> MyResult<()>,
+) -> MyResult<()> {
+    let spans = pattern.find_spans(text);
+    let mut first = 0;
+    let find = |content: &[u8], offset: usize| {
+        let end = offset + content.len();
+        while spans
+            .get(first)
+            .is_some_and(|&(s, e)| e < offset || (e == offset && s < e))
+        {
+            first += 1;
+        }
+        // Matches are sorted and disjoint, so once those ending before this
+        // line are skipped, the rest start within it until one starts past
+        // its terminator
+        spans[first..]
+            .iter()
+            .take_while(|&&(s, _)| s <= end)
+            .map(|&(s, e)| (s.max(offset) - offset, e.min(end) - offset))
+            .collect()
+    };
+    search_lines(text, options, None, find, emit)
+}
+
+/// Hands the lines wanted from `file` to `sink`, between its `begin` and
+/// `end` for `path`
+fn search_sink<T: BufRead>(
+    path: &Path,
+    file: T,
+    pattern: &dyn Matcher,
+    options: &SearchOptions,
+    budget: Option<&Allowance>,
+    sink: &mut dyn Sink,
+) -> MyResult<()> {
+    sink.begin(path)?;
+    // Errors of the sink's own are passed on as they are
+    let mut sink_failed = false;
+    for_each_line(file, pattern, options, budget, |line| {
+        to_sink(sink, line).inspect_err(|_| sink_failed = true)
+    })
+    .map_err(|e| if sink_failed { e } else { reading(path, e) })?;
+    sink.end(path)
+}
+
+/// Names `path` in an error from reading it, which comes up without one
+fn reading(path: &Path, e: GreprError) -> GreprError {
+    match e {
+        GreprError::Stream(source) => GreprError::Io {
+            path: path.to_path_buf(),
+            source,
+        },
+        e => e,
+    }
+}
+
+/// Hands a line to whichever of the sink's methods is for its kind
+fn to_sink(sink: &mut dyn Sink, line: Line) -> MyResult<()> {
+    if line.is_match {
+        sink.matched(line)
+    } else {
+        sink.context(line)
+    }
+}
+
+/// Emits the matching lines and their context, using `find` to locate the
+/// pattern within each line's content given its offset in the input
+fn search_lines<T: BufRead>(
+    mut file: T,
+    options: &SearchOptions,
+    budget: Option<&Allowance>,
+    mut find: impl FnMut(&[u8], usize) -> Vec<(usize, usize)>,
+    mut emit: impl FnMut(Line) -> MyResult<()>,
+) -> MyResult<()> {
+    if options.max_count == Some(0) {
+        return Ok(());
+    }
+
+    let mut selector = Selector::new(options, budget);
+    let mut buffer = vec![];
+    while let Some(more) = options.with_record(&mut file, &mut buffer, budget, |record, len| {
+        selector.record(record, len, &mut find, &mut emit)
+    })? {
+        if !more? {
             break;
         }
+    }
+
+    Ok(())
+}
+
+/// Picks out the wanted lines as the records of an input come in one by
+/// one: those selected, up to the maximum count, and their context
+struct Selector<'a> {
+    options: SearchOptions,
+    matches_left: usize,
+    /// Most recent non-matching lines, kept around in case a match follows.
+    /// With a budget there are only as many as it has room for.
+    recent: VecDeque<Line>,
+    budget: Option<&'a Allowance<'a>>,
+    after_left: usize,
+    line_num: usize,
+    offset: usize,
+}
+
+impl<'a> Selector<'a> {
+    fn new(options: &SearchOptions, budget: Option<&'a Allowance<'a>>) -> Selector<'a> {
+        Selector {
+            options: *options,
+            matches_left: options.max_count.unwrap_or(usize::MAX),
+            recent: VecDeque::with_capacity(options.before_context),
+            budget,
+            after_left: 0,
+            line_num: 0,
+            offset: 0,
+        }
+    }
+
+    /// Keeps a line of context, making room for it by letting go of the
+    /// oldest ones, or of the line itself if that isn't enough
+    fn keep(&mut self, line: Line) {
+        if self.recent.len() == self.options.before_context {
+            self.forget_oldest();
+        }
+        if let Some(budget) = self.budget {
+            while !budget.budget.hold(line.text.len()) {
+                if self.recent.is_empty() {
+                    return;
+                }
+                self.forget_oldest();
+            }
+        }
+        self.recent.push_back(line);
+    }
+
+    fn forget_oldest(&mut self) {
+        if let (Some(line), Some(budget)) = (self.recent.pop_front(), self.budget) {
+            budget.budget.release(line.text.len());
+        }
+    }
 
-        if invert_match ^ pattern.is_match(&buffer) {
-            res.push(std::mem::take(&mut buffer));
+    /// Hands over the lines of context kept, which are no longer held here
+    fn take_recent(&mut self) -> vec_deque::Drain<'_, Line> {
+        if let Some(budget) = self.budget {
+            budget
+                .budget
+                .release(self.recent.iter().map(|line| line.text.len()).sum());
+        }
+        self.recent.drain(..)
+    }
+
+    /// Takes the next record, `len` bytes of the input, handing `emit` any
+    /// lines it makes wanted. Returns whether to carry on with the next record.
+    fn record(
+        &mut self,
+        record: &[u8],
+        len: usize,
+        find: &mut impl FnMut(&[u8], usize) -> Vec<(usize, usize)>,
+        emit: &mut impl FnMut(Line) -> MyResult<()>,
+    ) -> MyResult<bool> {
+        let options = &self.options;
+        self.line_num += 1;
+        let line_offset = self.offset;
+        self.offset += len;
+
+        let spans = find(options.content(record), line_offset);
+        let mut is_match = options.invert_match ^ !spans.is_empty();
+
+        // Past the last wanted match, only trailing context is still printed
+        if self.matches_left == 0 {
+            if self.after_left == 0 && !options.passthru {
+                return Ok(false);
+            }
+            is_match = false;
+        }
+
+        // Lines that won't be printed are never copied out of the input
+        if !(is_match || options.passthru || self.after_left > 0 || options.before_context > 0) {
+            return Ok(true);
+        }
+        let mut text = record.to_vec();
+        if options.crlf && text.ends_with(b"\r\n") {
+            text.remove(text.len() - 2);
+        }
+        let line = Line {
+            number: self.line_num,
+            offset: line_offset,
+            text,
+            is_match,
+            spans,
+        };
+
+        if is_match {
+            self.matches_left -= 1;
+            self.take_recent().try_for_each(&mut *emit)?;
+            emit(line)?;
+            self.after_left = self.options.after_context;
+        } else if options.passthru {
+            emit(line)?;
+        } else if self.after_left > 0 {
+            self.after_left -= 1;
+            emit(line)?;
         } else {
-            buffer.clear();
+            self.keep(line);
         }
+        Ok(true)
     }
+}
 
-    Ok(res)
+impl Drop for Selector<'_> {
+    fn drop(&mut self) {
+        self.take_recent();
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{find_files, find_lines};
-    use rand::{distributions::Alphanumeric, Rng};
-    use regex::{Regex, RegexBuilder};
-    use std::io::Cursor;
+/// The lines wanted from an input, read only as far as it takes to find
+/// the next one. Made by [`Searcher::lines`].
+pub struct Lines<'s, R> {
+    searcher: &'s Searcher,
+    input: R,
+    selector: Selector<'s>,
+    buffer: Vec<u8>,
+    /// Lines found but not yet returned, as a match brings its context
+    pending: VecDeque<Line>,
+    finished: bool,
+}
 
-    #[test]
-    fn test_find_files() {
-        // Verify that the function finds a file known to exist
-        let files = find_files(&["./tests/inputs/fox.txt".to_string()], false);
-        assert_eq!(files.len(), 1);
-        assert_eq!(files[0].as_ref().unwrap(), "./tests/inputs/fox.txt");
-
-        // The function should reject a directory without the recursive option
-        let files = find_files(&["./tests/inputs/".to_string()], false);
-        assert_eq!(files.len(), 1);
-        if let Err(e) = &files[0] {
-            assert_eq!(e.to_string(), "./tests/inputs/ is a directory");
-        }
-
-        // Verify the function recurses to find four files in the directory
-        let res = find_files(&["./tests/inputs/".to_string()], true);
-        let files = res
-            .iter()
-            .map(|r| r.as_ref().unwrap().replace("\\", "/"))
-            .collect::<Vec<String>>();
+impl<R: BufRead> Iterator for Lines<'_, R> {
+    type Item = MyResult<Line>;
 
-        assert_eq!(files.len(), 4);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.pending.pop_front() {
+                return Some(Ok(line));
+            }
+            if self.finished {
+                return None;
+            }
+            self.finished = true;
+            if self.searcher.is_cancelled() {
+                return Some(Err(GreprError::Cancelled));
+            }
 
-        // Generate a random string to represent a nonexistent file
-        let bad: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(7)
-            .map(char::from)
-            .collect();
-        // Verify that the function returns the bad file as an error
-        let files = find_files(&[bad], false);
-        assert_eq!(files.len(), 1);
-        assert!(files[0].is_err());
+            // A multiline pattern runs over the whole input at once
+            let pattern = &*self.searcher.pattern;
+            let options = &self.searcher.options;
+            if options.multiline {
+                match find_lines(&mut self.input, pattern, options) {
+                    Ok(lines) => self.pending.extend(lines),
+                    Err(e) => return Some(Err(e)),
+                }
+                continue;
+            }
+
+            let Lines {
+                input,
+                selector,
+                buffer,
+                pending,
+                ..
+            } = self;
+            let more = options.with_record(input, buffer, None, |record, len| {
+                let mut find = |content: &[u8], _| pattern.find_spans(content);
+                selector.record(record, len, &mut find, &mut |line| {
+                    pending.push_back(line);
+                    Ok(())
+                })
+            });
+            match more {
+                Ok(Some(Ok(true))) => self.finished = false,
+                Ok(Some(Err(e))) => return Some(Err(e)),
+                Err(e) => return Some(Err(e.into())),
+                Ok(_) => {}
+            }
+        }
+    }
+}
+
+fn has_match<T: BufRead>(
+    mut file: T,
+    pattern: &dyn Matcher,
+    options: &SearchOptions,
+    budget: Option<&Allowance>,
+) -> MyResult<bool> {
+    if options.multiline {
+        let options = SearchOptions {
+            before_context: 0,
+            after_context: 0,
+            max_count: Some(1),
+            ..*options
+        };
+        let mut found = false;
+        for_each_line(file, pattern, &options, budget, |_| {
+            found = true;
+            Ok(())
+        })?;
+        return Ok(found);
+    }
+
+    // No need to read any further once a single line matches
+    let mut buffer = vec![];
+    let is_match =
+        |record: &[u8], _| options.invert_match ^ pattern.is_match(options.content(record));
+    while let Some(found) = options.with_record(&mut file, &mut buffer, budget, is_match)? {
+        if found {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Patterns compiled along with the options that decide which lines of the
+/// input are wanted
+#[derive(Debug)]
+pub struct Searcher {
+    pattern: Box<dyn Matcher>,
+    options: SearchOptions,
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+impl Searcher {
+    /// Compiles the patterns, any of which may match. Errors name the
+    /// pattern at fault as it was given.
+    pub fn new(
+        patterns: &[String],
+        syntax: Syntax,
+        regex: &RegexOptions,
+        options: SearchOptions,
+    ) -> Result<Searcher, GreprError> {
+        Ok(Searcher {
+            pattern: build_pattern(patterns, regex, syntax)?,
+            options,
+            cancel: None,
+        })
+    }
+
+    /// Searches with a pattern engine of the caller's own
+    pub fn with_matcher(matcher: impl Matcher + 'static, options: SearchOptions) -> Searcher {
+        Searcher {
+            pattern: Box::new(matcher),
+            options,
+            cancel: None,
+        }
+    }
+
+    /// Makes every search stop soon after `cancel` is set, from any thread,
+    /// and return a `Cancelled` error
+    pub fn with_cancel(mut self, cancel: Arc<AtomicBool>) -> Searcher {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub fn options(&self) -> &SearchOptions {
+        &self.options
+    }
+
+    /// Whether the patterns match anywhere in `text`
+    pub fn is_match(&self, text: &[u8]) -> bool {
+        self.pattern.is_match(text)
+    }
+
+    /// The byte ranges of every match in `text`
+    pub fn find_spans(&self, text: &[u8]) -> Vec<(usize, usize)> {
+        self.pattern.find_spans(text)
+    }
+
+    /// Every match in `text` along with `template` expanded from its
+    /// capture groups, as in `$1` or `${name}`
+    pub fn replacements(&self, text: &[u8], template: &str) -> Vec<((usize, usize), Vec<u8>)> {
+        self.pattern.replacements(text, template)
+    }
+
+    /// Every match on a line found by this searcher, with its capture
+    /// groups. The pattern is run over the line alone, even for a
+    /// multiline search.
+    pub fn matches(&self, line: &Line) -> Vec<Match> {
+        let content = self.options.content(&line.text);
+        self.pattern
+            .captures(content)
+            .into_iter()
+            .filter_map(|mut groups| {
+                let span = groups.remove(0)?;
+                Some(Match { span, groups })
+            })
+            .collect()
+    }
+
+    /// The lines wanted from `input`: those selected, and their context
+    pub fn find_lines(&self, input: impl BufRead) -> Result<Vec<Line>, GreprError> {
+        let input = self.cancellable(input);
+        self.checked(find_lines(input, &*self.pattern, &self.options))
+    }
+
+    /// The lines wanted from text that's already in memory, such as a log
+    /// shown in a browser, found exactly as they would be in a file
+    pub fn find_in_str(&self, text: &str) -> Result<Vec<Line>, GreprError> {
+        if !self.options.multiline {
+            return self.find_lines(text.as_bytes());
+        }
+        let mut res = vec![];
+        search_text(text.as_bytes(), &*self.pattern, &self.options, |line| {
+            res.push(line);
+            Ok(())
+        })?;
+        self.checked(Ok(res))
+    }
+
+    /// Like `find_lines`, but yields each line as soon as it's known to be
+    /// wanted, holding no more of the input than its context needs
+    pub fn lines<R: BufRead>(&self, input: R) -> Lines<'_, R> {
+        Lines {
+            searcher: self,
+            input,
+            selector: Selector::new(&self.options, None),
+            buffer: vec![],
+            pending: VecDeque::new(),
+            finished: self.options.max_count == Some(0),
+        }
     }
 
+    /// Like `find_lines`, but hands each line to `f` as soon as it's known
+    /// to be wanted. An error from `f` ends the search.
+    pub fn for_each_line(
+        &self,
+        input: impl BufRead,
+        f: impl FnMut(Line) -> Result<(), GreprError>,
+    ) -> Result<(), GreprError> {
+        let input = self.cancellable(input);
+        self.checked(for_each_line(input, &*self.pattern, &self.options, None, f))
+    }
+
+    /// Tells `sink` about the search of `input` as it goes. The `path` is
+    /// only passed on to the sink.
+    pub fn search(
+        &self,
+        path: &Path,
+        input: impl BufRead,
+        sink: &mut dyn Sink,
+    ) -> Result<(), GreprError> {
+        let input = self.cancellable(input);
+        self.checked(search_sink(
+            path,
+            input,
+            &*self.pattern,
+            &self.options,
+            None,
+            sink,
+        ))
+    }
+
+    /// Searches text that's already in memory, which needn't have come from
+    /// a file. The `name` is only passed on to the sink.
+    pub fn search_slice(
+        &self,
+        name: &str,
+        bytes: &[u8],
+        sink: &mut dyn Sink,
+    ) -> Result<(), GreprError> {
+        if !self.options.multiline {
+            return self.search(Path::new(name), bytes, sink);
+        }
+
+        // A multiline pattern runs over the slice where it is, not a copy
+        let name = Path::new(name);
+        sink.begin(name)?;
+        search_text(bytes, &*self.pattern, &self.options, |line| {
+            if self.is_cancelled() {
+                return Err(GreprError::Cancelled);
+            }
+            to_sink(sink, line)
+        })?;
+        sink.end(name)
+    }
+
+    /// Searches the file at `path`, or stdin for `-`. A file that can't be
+    /// opened goes to the sink's `error` rather than ending the search.
+    #[cfg(feature = "fs")]
+    pub fn search_path(&self, path: &Path, sink: &mut dyn Sink) -> Result<(), GreprError> {
+        if path == Path::new("-") {
+            return self.search(path, io::stdin().lock(), sink);
+        }
+        match std::fs::File::open(path) {
+            Ok(file) => self.search(path, io::BufReader::new(file), sink),
+            Err(source) => {
+                let e = GreprError::Io {
+                    path: path.to_path_buf(),
+                    source,
+                };
+                sink.error(path, &e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether any line of `input` is selected, reading no further than
+    /// the first
+    pub fn has_match(&self, input: impl BufRead) -> Result<bool, GreprError> {
+        let input = self.cancellable(input);
+        self.checked(has_match(input, &*self.pattern, &self.options, None))
+    }
+
+    fn is_cancelled(&self) -> bool {
+        is_cancelled(self.cancel.as_deref())
+    }
+
+    fn cancellable<R: BufRead>(&self, input: R) -> Cancellable<'_, R> {
+        Cancellable {
+            inner: input,
+            cancel: self.cancel.as_deref(),
+        }
+    }
+
+    /// The result of a search, unless it was cut short by cancelling
+    fn checked<T>(&self, res: MyResult<T>) -> MyResult<T> {
+        if self.is_cancelled() {
+            return Err(GreprError::Cancelled);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        build_pattern, build_regex, find_lines, has_match, has_uppercase, GreprError, Line, Match,
+        RegexOptions, SearchOptions, Searcher, Syntax,
+    };
+    use regex::bytes::{Regex, RegexBuilder};
+    use std::io::{self, BufRead, BufReader, Cursor, Read};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
     #[test]
     fn test_find_lines() {
         let text = b"Lorem\nIpsum\r\nDOLOR";
 
         // Pattern _or_ should match the one line, "Lorem"
         let re1 = Regex::new("or").unwrap();
-        let matches = find_lines(Cursor::new(&text), &re1, false);
+        let matches = find_lines(Cursor::new(&text), &re1, &SearchOptions::default());
         assert!(matches.is_ok());
         assert_eq!(matches.unwrap().len(), 1);
 
         // When inverted, the function should match the other two lines
-        let matches = find_lines(Cursor::new(&text), &re1, true);
+        let matches = find_lines(
+            Cursor::new(&text),
+            &re1,
+            &SearchOptions {
+                invert_match: true,
+                ..Default::default()
+            },
+        );
         assert!(matches.is_ok());
-        assert_eq!(matches.unwrap().len(), 2);
+        let matches = matches.unwrap();
+        assert_eq!(matches.len(), 2);
+
+        // Line numbers are 1-based and refer to the position in the input
+        assert_eq!(matches[0].number, 2);
+        assert_eq!(matches[1].number, 3);
+
+        // Byte offsets count every byte before the line, terminators included
+        assert_eq!(matches[0].offset, 6);
+        assert_eq!(matches[1].offset, 13);
 
         // This regex will be case-insensitive
         let re2 = RegexBuilder::new("or")
@@ -246,13 +1104,693 @@ mod tests {
             .unwrap();
 
         // The two lines "Lorem" and "DOLOR" should match
-        let matches = find_lines(Cursor::new(&*text), &re2, false);
+        let matches = find_lines(Cursor::new(&text), &re2, &SearchOptions::default());
         assert!(matches.is_ok());
         assert_eq!(matches.unwrap().len(), 2);
 
         // When inverted, the one remaining line should match
-        let matches = find_lines(Cursor::new(&text), &re2, true);
+        let matches = find_lines(
+            Cursor::new(&text),
+            &re2,
+            &SearchOptions {
+                invert_match: true,
+                ..Default::default()
+            },
+        );
         assert!(matches.is_ok());
         assert_eq!(matches.unwrap().len(), 1);
     }
+
+    #[test]
+    fn test_find_in_str() {
+        let text = "one\ntwo\nsix\n";
+        let searcher = Searcher::with_matcher(Regex::new("o").unwrap(), SearchOptions::default());
+        let lines = searcher.find_in_str(text).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].offset, 4);
+
+        // A multiline pattern runs over the text as a whole
+        let options = SearchOptions {
+            multiline: true,
+            ..Default::default()
+        };
+        let searcher = Searcher::with_matcher(Regex::new(r"e\nt").unwrap(), options);
+        let lines = searcher.find_in_str(text).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].spans, [(2, 3)]);
+    }
+
+    #[test]
+    fn test_find_lines_spans() {
+        let text = b"foo bar foo\nbaz\n";
+
+        // Every occurrence on a line is reported, not just the first
+        let re = Regex::new("fo+").unwrap();
+        let lines = find_lines(Cursor::new(&text), &re, &SearchOptions::default()).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].spans, [(0, 3), (8, 11)]);
+
+        // Lines selected by an inverted match have nothing to highlight
+        let lines = find_lines(
+            Cursor::new(&text),
+            &re,
+            &SearchOptions {
+                invert_match: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].spans.is_empty());
+    }
+
+    #[test]
+    fn test_find_lines_bytes() {
+        let text = b"a\xffb fox\n\xfe fox\n";
+
+        // Spans and offsets count the bytes that aren't UTF-8, whichever
+        // engine finds the matches
+        let patterns = [
+            (vec!["fox".to_string()], Syntax::Regex),
+            (vec!["fox".to_string()], Syntax::Fixed),
+            (vec!["fox".to_string(), "dog".to_string()], Syntax::Fixed),
+        ];
+        for (patterns, syntax) in patterns {
+            let pattern = build_pattern(&patterns, &RegexOptions::default(), syntax).unwrap();
+            let lines = find_lines(Cursor::new(&text), &*pattern, &SearchOptions::default());
+            let lines = lines.unwrap();
+            assert_eq!(lines[0].text, b"a\xffb fox\n");
+            assert_eq!(lines[0].spans, [(4, 7)]);
+            assert_eq!(lines[1].offset, 8);
+            assert_eq!(lines[1].spans, [(2, 5)]);
+        }
+
+        // So does a multiline search, and the bytes can be matched alone
+        let options = SearchOptions {
+            multiline: true,
+            ..Default::default()
+        };
+        let re = Regex::new(r"(?-u:\xff)b").unwrap();
+        let lines = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].spans, [(1, 3)]);
+    }
+
+    #[test]
+    fn test_build_regex_word() {
+        let word = RegexOptions {
+            word_regexp: true,
+            ..Default::default()
+        };
+        let re = build_regex("foo", &word).unwrap();
+        assert!(re.is_match(b"a foo b"));
+        assert!(re.is_match(b"foo"));
+        assert!(!re.is_match(b"foobar"));
+        assert!(!re.is_match(b"barfoo"));
+
+        // Patterns starting or ending with non-word characters
+        let re = build_regex("@foo", &word).unwrap();
+        assert!(re.is_match(b"mail @foo now"));
+        assert!(!re.is_match(b"mail a@foo now"));
+        let re = build_regex(r"foo\(", &word).unwrap();
+        assert!(re.is_match(b"call foo()"));
+        assert!(!re.is_match(b"call xfoo()"));
+
+        // Alternations are grouped so the boundaries apply to every branch
+        let options = RegexOptions {
+            insensitive: true,
+            ..word
+        };
+        let re = build_regex("foo|foobar", &options).unwrap();
+        let found: Vec<_> = re.find_iter(b"FOOBAR foo").map(|m| m.as_bytes()).collect();
+        assert_eq!(found, [&b"FOOBAR"[..], b"foo"]);
+
+        // With ASCII-only boundaries a non-ASCII letter isn't part of a word
+        let options = RegexOptions {
+            ascii: true,
+            ..word
+        };
+        let re = build_regex("foo", &options).unwrap();
+        assert!(re.is_match("éfooé".as_bytes()));
+        assert!(!build_regex("foo", &word)
+            .unwrap()
+            .is_match("éfooé".as_bytes()));
+    }
+
+    #[test]
+    fn test_build_regex_line() {
+        let line = RegexOptions {
+            line_regexp: true,
+            ..Default::default()
+        };
+        let re = build_regex("foo|bar", &line).unwrap();
+        assert!(re.is_match(b"foo"));
+        assert!(re.is_match(b"bar"));
+        assert!(!re.is_match(b"foobar"));
+        assert!(!re.is_match(b"a bar"));
+
+        // -x wins over -w
+        let options = RegexOptions {
+            word_regexp: true,
+            ..line
+        };
+        let re = build_regex("foo", &options).unwrap();
+        assert!(!re.is_match(b"foo bar"));
+    }
+
+    #[test]
+    fn test_build_pattern_fixed() {
+        // Regex metacharacters are taken literally
+        let pattern = build_pattern(
+            &["a.b(".to_string()],
+            &RegexOptions::default(),
+            Syntax::Fixed,
+        )
+        .unwrap();
+        assert!(format!("{:?}", pattern).starts_with("Literal("));
+        assert!(pattern.is_match(b"x a.b( y"));
+        assert!(!pattern.is_match(b"x axb( y"));
+        assert_eq!(pattern.find_spans(b"a.b(a.b("), [(0, 4), (4, 8)]);
+
+        // Several are found together, earliest first as in an alternation
+        let pattern = build_pattern(
+            &["b(".to_string(), "a.b".to_string(), "a".to_string()],
+            &RegexOptions::default(),
+            Syntax::Fixed,
+        )
+        .unwrap();
+        assert!(format!("{:?}", pattern).starts_with("Literals("));
+        assert_eq!(pattern.find_spans(b"a.b( b( xa"), [(0, 3), (5, 7), (9, 10)]);
+        assert!(!pattern.is_match(b"b.x"));
+
+        // Combined with other options it still matches literally
+        let pattern = build_pattern(
+            &["a.b".to_string()],
+            &RegexOptions {
+                insensitive: true,
+                word_regexp: true,
+                ..Default::default()
+            },
+            Syntax::Fixed,
+        )
+        .unwrap();
+        assert!(pattern.is_match(b"x A.B y"));
+        assert!(!pattern.is_match(b"x AxB y"));
+        assert!(!pattern.is_match(b"xa.b y"));
+    }
+
+    #[test]
+    fn test_build_pattern_multiple() {
+        let patterns = ["fox".to_string(), "d.g".to_string()];
+
+        // A line matches if any of the patterns matches
+        let pattern = build_pattern(&patterns, &RegexOptions::default(), Syntax::Regex).unwrap();
+        assert!(pattern.is_match(b"the fox"));
+        assert!(pattern.is_match(b"the dog"));
+        assert!(!pattern.is_match(b"the cat"));
+
+        // Line anchoring applies to each pattern, not just the first and last
+        let pattern = build_pattern(
+            &patterns,
+            &RegexOptions {
+                line_regexp: true,
+                ..Default::default()
+            },
+            Syntax::Regex,
+        )
+        .unwrap();
+        assert!(pattern.is_match(b"dog"));
+        assert!(!pattern.is_match(b"fox dog"));
+
+        // Fixed strings stay literal when combined
+        let pattern = build_pattern(&patterns, &RegexOptions::default(), Syntax::Fixed).unwrap();
+        assert!(pattern.is_match(b"d.g"));
+        assert!(!pattern.is_match(b"dog"));
+
+        // Errors name the pattern that failed to compile
+        let patterns = ["fox".to_string(), "*dog".to_string()];
+        let err = build_pattern(&patterns, &RegexOptions::default(), Syntax::Regex).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid pattern \"*dog\"");
+
+        // No patterns at all never match
+        let pattern = build_pattern(&[], &RegexOptions::default(), Syntax::Regex).unwrap();
+        assert!(!pattern.is_match(b""));
+        assert!(!pattern.is_match(b"anything"));
+    }
+
+    #[cfg(feature = "perl")]
+    #[test]
+    fn test_build_pattern_perl() {
+        let patterns = [r"fox(?! jumps)".to_string(), r"(\w)\1".to_string()];
+        let pattern = build_pattern(&patterns, &RegexOptions::default(), Syntax::Perl).unwrap();
+        assert!(pattern.is_match(b"a fox"));
+        assert!(!pattern.is_match(b"a fox jumps"));
+        // Each pattern's backreferences refer to its own groups
+        assert!(pattern.is_match(b"the jeep"));
+        assert_eq!(pattern.find_spans(b"fox zoo"), vec![(0, 3), (5, 7)]);
+
+        let patterns = ["fo".to_string(), "o".to_string()];
+        let pattern = build_pattern(
+            &patterns,
+            &RegexOptions {
+                word_regexp: true,
+                ..Default::default()
+            },
+            Syntax::Perl,
+        )
+        .unwrap();
+        assert!(!pattern.is_match(b"fox"));
+        assert_eq!(pattern.find_spans(b"fo o"), vec![(0, 2), (3, 4)]);
+
+        let err = build_pattern(
+            &["(?<=a+)b".to_string()],
+            &RegexOptions::default(),
+            Syntax::Perl,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid pattern \"(?<=a+)b\"");
+    }
+
+    #[test]
+    fn test_has_uppercase() {
+        assert!(!has_uppercase("the fox", Syntax::Regex));
+        assert!(has_uppercase("the Fox", Syntax::Regex));
+        assert!(has_uppercase("ΘΕΟΣ", Syntax::Regex));
+        // Escapes such as \S are not letters to match
+        assert!(!has_uppercase(r"\Sfox\W", Syntax::Regex));
+        assert!(has_uppercase(r"\Sfox\W", Syntax::Fixed));
+    }
+
+    #[test]
+    fn test_build_pattern_posix() {
+        let patterns = [r"\(fox\|dog\)\+".to_string()];
+        let pattern = build_pattern(&patterns, &RegexOptions::default(), Syntax::Basic).unwrap();
+        assert!(pattern.is_match(b"the dog"));
+        assert!(!pattern.is_match(b"the cat"));
+
+        let pattern = build_pattern(&patterns, &RegexOptions::default(), Syntax::Extended).unwrap();
+        assert!(pattern.is_match(b"(fox|dog)+"));
+        assert!(!pattern.is_match(b"the dog"));
+
+        // Errors name the pattern as given, not its translation
+        let patterns = [r"\(fox".to_string()];
+        let err = build_pattern(&patterns, &RegexOptions::default(), Syntax::Basic).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid pattern \"\\(fox\"");
+    }
+
+    #[test]
+    fn test_find_lines_match_counts() {
+        let text = b"a fox, a fox\nno match\nfox\n";
+        let re = Regex::new("fox").unwrap();
+
+        // Each line carries every match, which --count-matches adds up
+        let lines = find_lines(Cursor::new(&text), &re, &Default::default()).unwrap();
+        let counts: Vec<_> = lines.iter().map(|line| line.spans.len()).collect();
+        assert_eq!(counts, vec![2, 1]);
+    }
+
+    #[test]
+    fn test_find_lines_max_count() {
+        let text = b"one\ntwo\nthree\nfour\nfive\n";
+        let re = Regex::new("o").unwrap();
+
+        let lines = find_lines(
+            Cursor::new(&text),
+            &re,
+            &SearchOptions {
+                max_count: Some(2),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let numbers: Vec<_> = lines.iter().map(|l| l.number).collect();
+        assert_eq!(numbers, [1, 2]);
+
+        // Trailing context after the last match is still included
+        let lines = find_lines(
+            Cursor::new(&text),
+            &re,
+            &SearchOptions {
+                after_context: 2,
+                max_count: Some(1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let numbers: Vec<_> = lines.iter().map(|l| l.number).collect();
+        assert_eq!(numbers, [1, 2, 3]);
+        assert!(lines[0].is_match);
+        assert!(!lines[1].is_match && !lines[2].is_match);
+
+        let lines = find_lines(
+            Cursor::new(&text),
+            &re,
+            &SearchOptions {
+                max_count: Some(0),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(lines.is_empty());
+    }
+
+    #[test]
+    fn test_find_lines_null_data() {
+        let text = b"foo\nbar\0baz\0qux";
+        let re = Regex::new("^(?:bar|qux)$").unwrap();
+        let options = SearchOptions {
+            null_data: true,
+            ..Default::default()
+        };
+
+        // Newlines are ordinary characters and $ anchors at the NUL
+        let lines = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].number, 3);
+        assert_eq!(lines[0].text, b"qux");
+
+        let re = Regex::new("(?s)o.b").unwrap();
+        let lines = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].text, b"foo\nbar\0");
+    }
+
+    #[test]
+    fn test_find_lines_crlf() {
+        let text = b"foo\r\nbar foo\r\nfoo bar\nfoo";
+        let re = Regex::new("foo$").unwrap();
+        let options = SearchOptions {
+            crlf: true,
+            ..Default::default()
+        };
+
+        // $ anchors before the CR, which is dropped from the line
+        let lines = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        let texts: Vec<_> = lines.iter().map(|l| l.text.as_slice()).collect();
+        assert_eq!(texts, [&b"foo\n"[..], b"bar foo\n", b"foo"]);
+        assert_eq!(lines[1].offset, 5);
+
+        // Across lines, the regex itself has to know about CRLF
+        let re = build_pattern(
+            &["foo$".to_string()],
+            &RegexOptions {
+                multiline: true,
+                crlf: true,
+                ..Default::default()
+            },
+            Syntax::Regex,
+        )
+        .unwrap();
+        let options = SearchOptions {
+            multiline: true,
+            ..options
+        };
+        let lines = find_lines(Cursor::new(&text), &*re, &options).unwrap();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1].spans, [(4, 7)]);
+    }
+
+    #[test]
+    fn test_find_lines_multiline() {
+        let text = b"fn foo(\n    x: u8,\n) {}\nfn bar(x: u8) {}\n";
+        let re = RegexBuilder::new(r"^fn \w+\(\n\s+x|\n\)")
+            .multi_line(true)
+            .build()
+            .unwrap();
+        let options = SearchOptions {
+            multiline: true,
+            ..Default::default()
+        };
+
+        // Every line a match touches is reported with its share of the match,
+        // including lines where it covers only the newline
+        let lines = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        assert_eq!(lines.len(), 3);
+        assert_eq!((lines[0].number, &lines[0].spans), (1, &vec![(0, 7)]));
+        assert_eq!(lines[1].spans, vec![(0, 5), (10, 10)]);
+        assert_eq!(lines[2].spans, vec![(0, 1)]);
+
+        let options = SearchOptions {
+            invert_match: true,
+            ..options
+        };
+        let lines = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].text, b"fn bar(x: u8) {}\n");
+        assert!(has_match(Cursor::new(&text), &re, &options, None).unwrap());
+    }
+
+    #[test]
+    fn test_with_record() {
+        let options = SearchOptions::default();
+        let mut buffer = vec![];
+        let record = |r: &[u8], len| (r.to_vec(), len);
+
+        // Whole records come straight from the reader's buffer, and the rest
+        // of the input through `buffer`
+        let mut file: &[u8] = b"one\ntwo";
+        let res = options.with_record(&mut file, &mut buffer, None, record);
+        assert_eq!(res.unwrap(), Some((b"one\n".to_vec(), 4)));
+        assert!(buffer.is_empty());
+        let res = options.with_record(&mut file, &mut buffer, None, record);
+        assert_eq!(res.unwrap(), Some((b"two".to_vec(), 3)));
+        assert_eq!(buffer, b"two");
+        let res = options.with_record(&mut file, &mut buffer, None, record);
+        assert_eq!(res.unwrap(), None);
+
+        // Bytes that aren't UTF-8 are passed on as they are
+        let mut file: &[u8] = b"a\xffb\n";
+        let res = options.with_record(&mut file, &mut buffer, None, record);
+        assert_eq!(res.unwrap(), Some((b"a\xffb\n".to_vec(), 4)));
+    }
+
+    #[test]
+    fn test_has_match() {
+        let inverted = SearchOptions {
+            invert_match: true,
+            ..Default::default()
+        };
+        let text = b"Lorem\nIpsum\r\nDOLOR";
+
+        let re = Regex::new("Ips").unwrap();
+        assert!(has_match(Cursor::new(&text), &re, &SearchOptions::default(), None).unwrap());
+        assert!(has_match(Cursor::new(&text), &re, &inverted, None).unwrap());
+
+        let re = Regex::new("sit").unwrap();
+        assert!(!has_match(Cursor::new(&text), &re, &SearchOptions::default(), None).unwrap());
+        assert!(has_match(Cursor::new(&text), &re, &inverted, None).unwrap());
+        assert!(!has_match(Cursor::new(b""), &re, &inverted, None).unwrap());
+    }
+
+    #[test]
+    fn test_find_lines_passthru() {
+        let text = b"one\ntwo\nthree\n";
+        let re = Regex::new("o").unwrap();
+        let options = SearchOptions {
+            passthru: true,
+            max_count: Some(1),
+            ..Default::default()
+        };
+
+        // Every line comes through, even past the last wanted match
+        let lines = find_lines(Cursor::new(&text), &re, &options).unwrap();
+        let matched: Vec<_> = lines.iter().map(|l| l.is_match).collect();
+        assert_eq!(matched, [true, false, false]);
+    }
+
+    #[test]
+    fn test_find_lines_context() {
+        let text = b"one\ntwo\nthree\nfour\nfive\nsix\nseven\n";
+        let re = Regex::new("thr|fou").unwrap();
+
+        // One line before and after the two adjacent matches
+        let lines = find_lines(
+            Cursor::new(&text),
+            &re,
+            &SearchOptions {
+                before_context: 1,
+                after_context: 1,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let numbers: Vec<_> = lines.iter().map(|l| l.number).collect();
+        assert_eq!(numbers, [2, 3, 4, 5]);
+        assert!(!lines[0].is_match);
+        assert!(lines[1].is_match && lines[2].is_match);
+        assert!(!lines[3].is_match);
+
+        // Overlapping context regions are merged rather than duplicated
+        let re = Regex::new("tw|si").unwrap();
+        let lines = find_lines(
+            Cursor::new(&text),
+            &re,
+            &SearchOptions {
+                before_context: 2,
+                after_context: 2,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let numbers: Vec<_> = lines.iter().map(|l| l.number).collect();
+        assert_eq!(numbers, [1, 2, 3, 4, 5, 6, 7]);
+
+        // Before context doesn't reach past the start of the input
+        let re = Regex::new("on").unwrap();
+        let lines = find_lines(
+            Cursor::new(&text),
+            &re,
+            &SearchOptions {
+                before_context: 3,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(lines.len(), 1);
+    }
+
+    #[test]
+    fn test_searcher() {
+        let options = SearchOptions {
+            after_context: 1,
+            ..Default::default()
+        };
+        let patterns = ["b.".to_string(), "x".to_string()];
+        let searcher =
+            Searcher::new(&patterns, Syntax::Fixed, &RegexOptions::default(), options).unwrap();
+        assert!(searcher.is_match(b"ab.c"));
+        assert!(!searcher.is_match(b"abc"));
+        assert_eq!(searcher.find_spans(b"b.xb."), [(0, 2), (2, 3), (3, 5)]);
+
+        let text = "one\nb.\ntwo\nthree\n";
+        let lines = searcher.find_lines(text.as_bytes()).unwrap();
+        let numbers: Vec<_> = lines.iter().map(|l| (l.number, l.is_match)).collect();
+        assert_eq!(numbers, [(2, true), (3, false)]);
+        assert!(searcher.has_match(text.as_bytes()).unwrap());
+
+        // Any engine can be plugged in
+        let searcher = Searcher::with_matcher(Regex::new("t.o").unwrap(), options);
+        assert_eq!(searcher.find_lines(text.as_bytes()).unwrap()[0].number, 3);
+
+        let err = Searcher::new(
+            &["(".to_string()],
+            Syntax::Regex,
+            &RegexOptions::default(),
+            options,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid pattern \"(\"");
+        assert!(matches!(err, GreprError::InvalidPattern(p) if p == "("));
+    }
+
+    /// A reader that fails partway through, as a disk or network might
+    fn failing(text: &'static str) -> impl BufRead {
+        struct Broken;
+        impl Read for Broken {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("read failed"))
+            }
+        }
+        BufReader::new(text.as_bytes().chain(Broken))
+    }
+
+    #[test]
+    fn test_read_error() {
+        // A read that fails is an error rather than the end of the input
+        let searcher = Searcher::with_matcher(Regex::new("fox").unwrap(), Default::default());
+        let err = searcher.find_lines(failing("a fox\n")).unwrap_err();
+        assert!(matches!(err, GreprError::Stream(_)));
+        assert!(searcher.has_match(failing("no\n")).is_err());
+
+        let mut lines = searcher.lines(failing("a fox\nb fox\n"));
+        assert_eq!(lines.next().unwrap().unwrap().number, 1);
+        assert_eq!(lines.next().unwrap().unwrap().number, 2);
+        assert!(lines.next().unwrap().is_err());
+        assert!(lines.next().is_none());
+
+        // A sink is told about the lines read before, and the error names
+        // the path
+        let mut found: Vec<Line> = vec![];
+        let err = searcher
+            .search(Path::new("a.txt"), failing("a fox\n"), &mut found)
+            .unwrap_err();
+        assert_eq!(found.len(), 1);
+        assert_eq!(err.path(), Some(Path::new("a.txt")));
+        assert_eq!(err.to_string(), "a.txt: read failed");
+    }
+
+    #[test]
+    fn test_lines() {
+        let options = SearchOptions {
+            before_context: 1,
+            ..Default::default()
+        };
+        let searcher = Searcher::with_matcher(Regex::new("o").unwrap(), options);
+        let text = "one\nsix\nten\ntwo\n";
+        let lines: Vec<_> = searcher
+            .lines(text.as_bytes())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(lines, searcher.find_lines(text.as_bytes()).unwrap());
+
+        // Lines come out without waiting for the end of the input
+        let searcher = Searcher::with_matcher(Regex::new("^$").unwrap(), options);
+        let input = std::io::BufReader::new(std::io::repeat(b'\n'));
+        let numbers: Vec<_> = searcher
+            .lines(input)
+            .take(3)
+            .map(|line| line.unwrap().number)
+            .collect();
+        assert_eq!(numbers, [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let searcher = Searcher::with_matcher(Regex::new("o").unwrap(), SearchOptions::default())
+            .with_cancel(cancel.clone());
+        let text = "one\ntwo\nfour\n";
+
+        // Cancelling partway through stops at the next line
+        let mut numbers = vec![];
+        let err = searcher
+            .for_each_line(text.as_bytes(), |line| {
+                numbers.push(line.number);
+                cancel.store(true, Ordering::Relaxed);
+                Ok(())
+            })
+            .unwrap_err();
+        assert!(matches!(err, GreprError::Cancelled));
+        assert_eq!(numbers, [1]);
+
+        assert!(matches!(
+            searcher.find_lines(text.as_bytes()),
+            Err(GreprError::Cancelled)
+        ));
+        let mut lines = searcher.lines(text.as_bytes());
+        assert!(matches!(lines.next(), Some(Err(GreprError::Cancelled))));
+        assert!(lines.next().is_none());
+    }
+
+    #[test]
+    fn test_matches() {
+        let options = SearchOptions::default();
+        let patterns = [r"(\w)(\d)?=".to_string()];
+        let searcher =
+            Searcher::new(&patterns, Syntax::Regex, &RegexOptions::default(), options).unwrap();
+        let lines = searcher.find_lines("a= b2=\n".as_bytes()).unwrap();
+        assert_eq!(
+            searcher.matches(&lines[0]),
+            [
+                Match {
+                    span: (0, 2),
+                    groups: vec![Some((0, 1)), None],
+                },
+                Match {
+                    span: (3, 6),
+                    groups: vec![Some((3, 4)), Some((4, 5))],
+                },
+            ]
+        );
+    }
 }
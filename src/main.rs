@@ -1,6 +1,9 @@
 fn main() {
-    if let Err(e) = grepr::get_args().and_then(grepr::run) {
-        eprintln!("{}", e);
-        std::process::exit(1);
+    match grepr::cli::get_args().and_then(grepr::run) {
+        Ok(summary) => std::process::exit(summary.status.code()),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
     }
 }
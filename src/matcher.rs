@@ -0,0 +1,345 @@
+//! The pattern engines a search can run on, behind the `Matcher` trait
+
+#[cfg(feature = "perl")]
+use std::borrow::Cow;
+use std::fmt::Debug;
+use std::sync::OnceLock;
+
+use aho_corasick::AhoCorasick;
+use memchr::memmem::Finder;
+use regex::bytes::Regex;
+
+/// What a search needs of a pattern engine. Matchers are shared by the
+/// threads searching files, so they have to be `Send` and `Sync`. The text
+/// is bytes, which needn't be UTF-8, and spans are byte ranges within it.
+pub trait Matcher: Debug + Send + Sync {
+    /// Whether there's a match anywhere in `text`
+    fn is_match(&self, text: &[u8]) -> bool;
+
+    /// The byte ranges of every match in `text`, in order and without overlaps
+    fn find_spans(&self, text: &[u8]) -> Vec<(usize, usize)>;
+
+    /// Whether `group`, a number or a name, is one of the capture groups. By
+    /// default a matcher has no groups besides the whole match, 0.
+    fn has_group(&self, group: &str) -> bool {
+        group == "0"
+    }
+
+    /// The span of every match in `text`, followed by those of its capture
+    /// groups, with `None` for a group that took no part. By default a
+    /// matcher has no groups besides the whole match.
+    fn captures(&self, text: &[u8]) -> Vec<Vec<Option<(usize, usize)>>> {
+        self.find_spans(text)
+            .into_iter()
+            .map(|span| vec![Some(span)])
+            .collect()
+    }
+
+    /// Every match in `text` along with `template` expanded from its
+    /// capture groups, as in `$1` or `${name}`. By default a matcher has no
+    /// groups besides the whole match, `$0`.
+    fn replacements(&self, text: &[u8], template: &str) -> Vec<((usize, usize), Vec<u8>)> {
+        // A regex matching all of the match provides the captures to expand
+        static WHOLE: OnceLock<Regex> = OnceLock::new();
+        let whole = WHOLE.get_or_init(|| Regex::new(r"(?s)\A.*\z").unwrap());
+        self.find_spans(text)
+            .into_iter()
+            .map(|(start, end)| {
+                let mut replacement = vec![];
+                let caps = whole.captures(&text[start..end]).unwrap();
+                caps.expand(template.as_bytes(), &mut replacement);
+                ((start, end), replacement)
+            })
+            .collect()
+    }
+}
+
+impl Matcher for Regex {
+    fn is_match(&self, text: &[u8]) -> bool {
+        Regex::is_match(self, text)
+    }
+
+    fn has_group(&self, group: &str) -> bool {
+        match group.parse::<usize>() {
+            Ok(n) => n < self.captures_len(),
+            Err(_) => self.capture_names().flatten().any(|name| name == group),
+        }
+    }
+
+    fn find_spans(&self, text: &[u8]) -> Vec<(usize, usize)> {
+        self.find_iter(text).map(|m| (m.start(), m.end())).collect()
+    }
+
+    fn captures(&self, text: &[u8]) -> Vec<Vec<Option<(usize, usize)>>> {
+        self.captures_iter(text)
+            .map(|caps| {
+                caps.iter()
+                    .map(|m| m.map(|m| (m.start(), m.end())))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn replacements(&self, text: &[u8], template: &str) -> Vec<((usize, usize), Vec<u8>)> {
+        self.captures_iter(text)
+            .map(|caps| {
+                let m = caps.get(0).unwrap();
+                let mut replacement = vec![];
+                caps.expand(template.as_bytes(), &mut replacement);
+                ((m.start(), m.end()), replacement)
+            })
+            .collect()
+    }
+}
+
+/// A plain substring, searched for without going through the regex engine
+#[derive(Debug)]
+pub struct Literal(pub Finder<'static>);
+
+impl Matcher for Literal {
+    fn is_match(&self, text: &[u8]) -> bool {
+        self.0.find(text).is_some()
+    }
+
+    fn find_spans(&self, text: &[u8]) -> Vec<(usize, usize)> {
+        let len = self.0.needle().len();
+        self.0
+            .find_iter(text)
+            .map(|start| (start, start + len))
+            .collect()
+    }
+}
+
+/// Several plain substrings, found together in one pass
+#[derive(Debug)]
+pub struct Literals(pub AhoCorasick);
+
+impl Matcher for Literals {
+    fn is_match(&self, text: &[u8]) -> bool {
+        self.0.is_match(text)
+    }
+
+    fn find_spans(&self, text: &[u8]) -> Vec<(usize, usize)> {
+        self.0
+            .find_iter(text)
+            .map(|m| (m.start(), m.end()))
+            .collect()
+    }
+}
+
+/// Patterns for the backtracking engine, kept separate so that each one's
+/// backreferences count its own groups
+#[cfg(feature = "perl")]
+#[derive(Debug)]
+pub struct Fancy(pub Vec<fancy_regex::Regex>);
+
+#[cfg(feature = "perl")]
+impl Matcher for Fancy {
+    // A search that exceeds the backtracking limit counts as no match
+    fn is_match(&self, text: &[u8]) -> bool {
+        let text = Lossy::new(text);
+        self.0
+            .iter()
+            .any(|re| re.is_match(&text.text).unwrap_or(false))
+    }
+
+    // Each pattern's groups are its own
+    fn has_group(&self, group: &str) -> bool {
+        self.0.iter().any(|re| match group.parse::<usize>() {
+            Ok(n) => n < re.captures_len(),
+            Err(_) => re.capture_names().flatten().any(|name| name == group),
+        })
+    }
+
+    fn find_spans(&self, text: &[u8]) -> Vec<(usize, usize)> {
+        let text = Lossy::new(text);
+        let spans = self
+            .0
+            .iter()
+            .flat_map(|re| re.find_iter(&text.text).map_while(Result::ok))
+            .map(|m| (text.span(m.start(), m.end()), ()))
+            .collect();
+        remove_overlaps(spans)
+            .into_iter()
+            .map(|(span, _)| span)
+            .collect()
+    }
+
+    fn captures(&self, text: &[u8]) -> Vec<Vec<Option<(usize, usize)>>> {
+        let text = Lossy::new(text);
+        let captures = self
+            .0
+            .iter()
+            .flat_map(|re| re.captures_iter(&text.text).map_while(Result::ok))
+            .map(|caps| {
+                let m = caps.get(0).unwrap();
+                let groups = caps
+                    .iter()
+                    .map(|m| m.map(|m| text.span(m.start(), m.end())))
+                    .collect();
+                (text.span(m.start(), m.end()), groups)
+            })
+            .collect();
+        remove_overlaps(captures)
+            .into_iter()
+            .map(|(_, groups)| groups)
+            .collect()
+    }
+
+    fn replacements(&self, text: &[u8], template: &str) -> Vec<((usize, usize), Vec<u8>)> {
+        let text = Lossy::new(text);
+        let replacements = self
+            .0
+            .iter()
+            .flat_map(|re| re.captures_iter(&text.text).map_while(Result::ok))
+            .map(|caps| {
+                let m = caps.get(0).unwrap();
+                let mut replacement = String::new();
+                caps.expand(template, &mut replacement);
+                (text.span(m.start(), m.end()), replacement.into_bytes())
+            })
+            .collect();
+        remove_overlaps(replacements)
+    }
+}
+
+/// Text for the backtracking engine, which only takes a `str`. Bytes that
+/// aren't UTF-8 become U+FFFD, and the spans found are mapped back to the
+/// bytes.
+#[cfg(feature = "perl")]
+struct Lossy<'a> {
+    text: Cow<'a, str>,
+    /// Where each U+FFFD is in `text`, and where and how long the bytes it
+    /// stands for are
+    replaced: Vec<(usize, usize, usize)>,
+}
+
+#[cfg(feature = "perl")]
+impl<'a> Lossy<'a> {
+    fn new(bytes: &'a [u8]) -> Lossy<'a> {
+        if let Ok(text) = std::str::from_utf8(bytes) {
+            return Lossy {
+                text: Cow::Borrowed(text),
+                replaced: vec![],
+            };
+        }
+        let mut text = String::with_capacity(bytes.len());
+        let mut replaced = vec![];
+        let mut raw = 0;
+        for chunk in bytes.utf8_chunks() {
+            text.push_str(chunk.valid());
+            raw += chunk.valid().len();
+            let invalid = chunk.invalid().len();
+            if invalid > 0 {
+                replaced.push((text.len(), raw, invalid));
+                text.push(char::REPLACEMENT_CHARACTER);
+                raw += invalid;
+            }
+        }
+        Lossy {
+            text: Cow::Owned(text),
+            replaced,
+        }
+    }
+
+    /// Where an offset into `text`, always at a character, is in the bytes
+    fn raw(&self, offset: usize) -> usize {
+        let len = char::REPLACEMENT_CHARACTER.len_utf8();
+        let before = self
+            .replaced
+            .partition_point(|&(at, _, _)| at + len <= offset);
+        match before.checked_sub(1).map(|i| self.replaced[i]) {
+            Some((at, raw, n)) => raw + n + offset - (at + len),
+            None => offset,
+        }
+    }
+
+    fn span(&self, start: usize, end: usize) -> (usize, usize) {
+        (self.raw(start), self.raw(end))
+    }
+}
+
+/// Sorts matches from several patterns, keeping the earliest and then
+/// longest where they overlap
+#[cfg(feature = "perl")]
+fn remove_overlaps<T>(mut matches: Vec<((usize, usize), T)>) -> Vec<((usize, usize), T)> {
+    matches.sort_by_key(|&((start, end), _)| (start, std::cmp::Reverse(end)));
+    let mut last_end = 0;
+    matches.retain(|&((start, end), _)| {
+        let keep = start >= last_end;
+        if keep {
+            last_end = end;
+        }
+        keep
+    });
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Literal, Literals, Matcher};
+    use memchr::memmem::Finder;
+    use regex::bytes::Regex;
+
+    #[test]
+    fn test_captures() {
+        // A group that doesn't take part has no span
+        let re = Regex::new(r"(a)|(b)").unwrap();
+        assert_eq!(
+            Matcher::captures(&re, b"ab"),
+            vec![
+                vec![Some((0, 1)), Some((0, 1)), None],
+                vec![Some((1, 2)), None, Some((1, 2))]
+            ]
+        );
+
+        let literal = Literal(Finder::new("b").into_owned());
+        assert_eq!(literal.captures(b"abc"), vec![vec![Some((1, 2))]]);
+
+        let re = Regex::new(r"(?P<key>\w+)=(\d)").unwrap();
+        assert!(re.has_group("2") && re.has_group("key") && re.has_group("0"));
+        assert!(!re.has_group("3") && !re.has_group("value"));
+        assert!(literal.has_group("0") && !literal.has_group("1"));
+    }
+
+    #[test]
+    fn test_replacements() {
+        let re = Regex::new(r"(?P<word>\w)(\w+)").unwrap();
+        assert_eq!(
+            re.replacements(b"ab cd", "$2${word}"),
+            vec![((0, 2), b"ba".to_vec()), ((3, 5), b"dc".to_vec())]
+        );
+
+        // A literal only has the whole match to refer to
+        let literal = Literal(Finder::new("b").into_owned());
+        assert_eq!(
+            literal.replacements(b"abc", "[$0$1]"),
+            vec![((1, 2), b"[b]".to_vec())]
+        );
+
+        let literals = Literals(aho_corasick::AhoCorasick::new(["a", "c"]).unwrap());
+        assert_eq!(literals.find_spans(b"abc"), [(0, 1), (2, 3)]);
+        assert_eq!(
+            literals.replacements(b"abc", "<$0>"),
+            vec![((0, 1), b"<a>".to_vec()), ((2, 3), b"<c>".to_vec())]
+        );
+    }
+
+    #[cfg(feature = "perl")]
+    #[test]
+    fn test_fancy_bytes() {
+        use super::Fancy;
+
+        // Spans are of the bytes, though the engine sees U+FFFD
+        let fancy = Fancy(vec![fancy_regex::Regex::new(r"(?<=b )f(o)x").unwrap()]);
+        let text = b"a\xff\xfeb fox \xe2\x82";
+        assert_eq!(fancy.find_spans(text), [(5, 8)]);
+        assert_eq!(fancy.captures(text), vec![vec![Some((5, 8)), Some((6, 7))]]);
+        assert_eq!(
+            fancy.replacements(text, "$1"),
+            vec![((5, 8), b"o".to_vec())]
+        );
+        let fancy = Fancy(vec![fancy_regex::Regex::new("\u{fffd}").unwrap()]);
+        assert_eq!(fancy.find_spans(text), [(1, 2), (2, 3), (9, 11)]);
+    }
+}
@@ -0,0 +1,209 @@
+//! Translation of POSIX basic and extended regular expressions into the
+//! syntax understood by the `regex` crate
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Rewrites a POSIX regex as an equivalent `regex` crate pattern, following
+/// GNU grep's extensions (`\<`, `\>`, `\w`, and in BREs `\+`, `\?`, `\|`).
+/// Returns `None` for malformed patterns and for back-references, which the
+/// `regex` crate cannot match.
+pub fn translate(pattern: &str, basic: bool) -> Option<String> {
+    let mut out = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars().peekable();
+    // Whether the next character begins a (sub)expression, where `*` is
+    // literal and a BRE `^` is an anchor
+    let mut at_start = true;
+
+    while let Some(c) = chars.next() {
+        let was_start = at_start;
+        at_start = false;
+
+        match c {
+            '\\' => {
+                let escaped = chars.next()?;
+                match escaped {
+                    '(' | '|' if basic => {
+                        out.push(escaped);
+                        at_start = true;
+                    }
+                    ')' | '+' | '?' if basic => out.push(escaped),
+                    '{' if basic => push_interval(&mut out, &mut chars, true),
+                    '}' if basic => out.push_str(r"\}"),
+                    '<' => out.push_str(r"\b{start}"),
+                    '>' => out.push_str(r"\b{end}"),
+                    '`' => out.push_str(r"\A"),
+                    '\'' => out.push_str(r"\z"),
+                    'w' | 'W' | 's' | 'S' | 'b' | 'B' => {
+                        out.push('\\');
+                        out.push(escaped);
+                    }
+                    '1'..='9' => return None,
+                    _ => push_literal(&mut out, escaped),
+                }
+            }
+            '(' | '|' if !basic => {
+                out.push(c);
+                at_start = true;
+            }
+            '{' if !basic => push_interval(&mut out, &mut chars, false),
+            '(' | ')' | '|' | '{' | '}' | '+' | '?' if basic => push_literal(&mut out, c),
+            '}' => out.push_str(r"\}"),
+            '*' if was_start => out.push_str(r"\*"),
+            '^' if basic && !was_start => out.push_str(r"\^"),
+            '^' => {
+                out.push(c);
+                at_start = true;
+            }
+            '$' if basic && !at_end(&chars) => out.push_str(r"\$"),
+            '[' => push_bracket(&mut out, &mut chars)?,
+            _ => out.push(c),
+        }
+    }
+
+    Some(out)
+}
+
+/// Whether a BRE `$` at this point ends a (sub)expression and so anchors
+fn at_end(chars: &Peekable<Chars>) -> bool {
+    let mut rest = chars.clone();
+    match rest.next() {
+        None => true,
+        Some('\\') => matches!(rest.next(), Some(')') | Some('|')),
+        _ => false,
+    }
+}
+
+/// Copies `{m,n}` through when it is a valid interval and escapes the brace
+/// otherwise, as GNU grep treats a stray brace literally
+fn push_interval(out: &mut String, chars: &mut Peekable<Chars>, basic: bool) {
+    let mut rest = chars.clone();
+    let mut bounds = String::new();
+    let closed = loop {
+        match rest.next() {
+            Some(c) if c.is_ascii_digit() || c == ',' => bounds.push(c),
+            Some('\\') if basic => break rest.next() == Some('}'),
+            Some('}') if !basic => break true,
+            _ => break false,
+        }
+    };
+
+    let valid = closed
+        && match bounds.split_once(',') {
+            Some((min, max)) => !max.contains(',') && min.len() + max.len() > 0,
+            None => !bounds.is_empty(),
+        };
+
+    if valid {
+        *chars = rest;
+        out.push('{');
+        out.push_str(&bounds);
+        out.push('}');
+    } else {
+        out.push_str(r"\{");
+    }
+}
+
+/// Translates a bracket expression, whose opening `[` has been consumed.
+/// Backslashes are literal inside POSIX brackets but not in the `regex`
+/// crate, so anything the latter treats specially is escaped.
+fn push_bracket(out: &mut String, chars: &mut Peekable<Chars>) -> Option<()> {
+    out.push('[');
+    if chars.peek() == Some(&'^') {
+        chars.next();
+        out.push('^');
+    }
+    if chars.peek() == Some(&']') {
+        chars.next();
+        out.push_str(r"\]");
+    }
+
+    loop {
+        match chars.next()? {
+            ']' => break,
+            '[' => match chars.peek() {
+                Some(':') => {
+                    chars.next();
+                    out.push_str("[:");
+                    loop {
+                        let c = chars.next()?;
+                        out.push(c);
+                        if c == ':' && chars.peek() == Some(&']') {
+                            chars.next();
+                            out.push(']');
+                            break;
+                        }
+                    }
+                }
+                Some(&delim @ ('.' | '=')) => {
+                    // Only single-character collating elements are supported
+                    chars.next();
+                    let c = chars.next()?;
+                    if chars.next()? != delim || chars.next()? != ']' {
+                        return None;
+                    }
+                    push_literal(out, c);
+                }
+                _ => out.push_str(r"\["),
+            },
+            c @ ('\\' | '&' | '~' | '^') => {
+                out.push('\\');
+                out.push(c);
+            }
+            // A dash after a range or before another dash would be read as
+            // a set operation
+            '-' if chars.peek() == Some(&'-') || chars.peek() == Some(&']') => out.push_str(r"\-"),
+            c => out.push(c),
+        }
+    }
+
+    out.push(']');
+    Some(())
+}
+
+fn push_literal(out: &mut String, c: char) {
+    out.push_str(&regex::escape(c.encode_utf8(&mut [0; 4])));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::translate;
+
+    #[test]
+    fn test_translate_basic() {
+        let bre = |p| translate(p, true);
+        assert_eq!(bre(r"\(ab\)\{2\}"), Some("(ab){2}".to_string()));
+        assert_eq!(bre("(a+b?)|{c}"), Some(r"\(a\+b\?\)\|\{c\}".to_string()));
+        assert_eq!(bre(r"a\+\|b\?"), Some("a+|b?".to_string()));
+        assert_eq!(bre("*a*"), Some(r"\*a*".to_string()));
+        assert_eq!(bre("^a^b$c$"), Some(r"^a\^b\$c$".to_string()));
+        assert_eq!(bre(r"\(^a$\)"), Some("(^a$)".to_string()));
+        assert_eq!(bre(r"a\{x"), Some(r"a\{x".to_string()));
+        assert_eq!(bre(r"\<the\>"), Some(r"\b{start}the\b{end}".to_string()));
+        assert_eq!(bre(r"\.\w"), Some(r"\.\w".to_string()));
+        assert_eq!(bre(r"\(a\)\1"), None);
+        assert_eq!(bre("a\\"), None);
+    }
+
+    #[test]
+    fn test_translate_extended() {
+        let ere = |p| translate(p, false);
+        assert_eq!(ere("(ab){2}|c+"), Some("(ab){2}|c+".to_string()));
+        assert_eq!(ere(r"\(a\)"), Some(r"\(a\)".to_string()));
+        assert_eq!(ere("a{,x}"), Some(r"a\{,x\}".to_string()));
+        assert_eq!(ere("*a|*b"), Some(r"\*a|\*b".to_string()));
+        assert_eq!(ere("a^b$"), Some("a^b$".to_string()));
+    }
+
+    #[test]
+    fn test_translate_bracket() {
+        let ere = |p| translate(p, false);
+        assert_eq!(ere(r"[\.]"), Some(r"[\\.]".to_string()));
+        assert_eq!(ere("[]a]"), Some(r"[\]a]".to_string()));
+        assert_eq!(ere("[^]a-]"), Some(r"[^\]a\-]".to_string()));
+        assert_eq!(ere("[[:alpha:]_]"), Some("[[:alpha:]_]".to_string()));
+        assert_eq!(ere("[[.-.][=a=]]"), Some(r"[\-a]".to_string()));
+        assert_eq!(ere("[a&&b~~[]"), Some(r"[a\&\&b\~\~\[]".to_string()));
+        assert_eq!(ere("[abc"), None);
+    }
+}
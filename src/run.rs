@@ -0,0 +1,1810 @@
+//! Running a search as the `grepr` command does, from a `Config` built
+//! by the command line or by code, and the printing of what it finds
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Instant;
+use std::vec;
+
+use globset::GlobSet;
+use memmap2::Mmap;
+use tracing::debug;
+
+use crate::budget::{Allowance, Budget};
+use crate::{
+    build_pattern, find_files_in, has_match, reading, search_sink, FileWalker, GreprError, Line,
+    Matcher, RealFs, RegexOptions, SearchOptions, SearchStats, Syntax, Vfs, WalkOptions,
+};
+
+// Only the command line builds some of what these hold, such as colors
+// from GREP_COLORS or the --progress meter
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod color;
+mod decoder;
+mod decompress;
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod format;
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod hyperlink;
+#[cfg(feature = "serde")]
+mod json;
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod progress;
+mod results;
+mod sink;
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+mod types;
+
+use color::paint;
+pub(crate) use color::Colors;
+use decoder::decode;
+pub use decoder::Decoder;
+pub(crate) use decoder::Transcoder;
+pub use decompress::Decompressor;
+use format::Fields;
+pub(crate) use format::Template;
+pub(crate) use hyperlink::HyperlinkFormat;
+#[cfg(feature = "cli")]
+pub(crate) use progress::Meter;
+pub use progress::Progress;
+pub use results::{search, FileResult, Search};
+#[cfg(feature = "serde")]
+use sink::JsonSink;
+use sink::{CountSink, StandardSink};
+pub(crate) use types::Types;
+
+type MyResult<T> = Result<T, GreprError>;
+
+#[derive(Debug)]
+pub struct Config {
+    pub(crate) pattern: Box<dyn Matcher>,
+    pub(crate) files: Vec<PathBuf>,
+    pub(crate) walk: WalkOptions,
+    pub(crate) search: SearchOptions,
+    pub(crate) count: bool,
+    /// With `count`, count every match rather than every matching line
+    pub(crate) count_matches: bool,
+    /// With `count`, finish with the sum over all files
+    pub(crate) total: bool,
+    /// Finish with a summary of what was searched and found
+    pub(crate) stats: bool,
+    /// Print results as JSON messages in ripgrep's format
+    pub(crate) json: bool,
+    pub(crate) line_number: bool,
+    pub(crate) byte_offset: bool,
+    pub(crate) column: bool,
+    /// Repeat each line once per match, for editors' quickfix lists
+    pub(crate) vimgrep: bool,
+    /// Lay out every match as this says instead
+    pub(crate) format: Option<Template>,
+    /// Lines longer than this many bytes are cut short in the output
+    pub(crate) max_columns: Option<usize>,
+    /// Print the start of a long line rather than just its match count
+    pub(crate) max_columns_preview: bool,
+    /// Leave out the indentation of printed lines
+    pub(crate) trim: bool,
+    /// Start the text of each line at a tab stop after its prefix
+    pub(crate) initial_tab: bool,
+    pub(crate) files_with_matches: bool,
+    /// Only print the paths that would be searched
+    pub(crate) list_files: bool,
+    /// Only print the definitions of these file types
+    pub(crate) type_list: Option<Types>,
+    /// Template each match is rewritten with before printing
+    pub(crate) replace: Option<String>,
+    pub(crate) files_without_match: bool,
+    pub(crate) only_matching: bool,
+    /// Leave out lines printed before for the same file
+    pub(crate) unique: bool,
+    /// With `unique`, print how many times each line occurred
+    pub(crate) unique_count: bool,
+    pub(crate) quiet: bool,
+    pub(crate) no_messages: bool,
+    pub(crate) colors: Colors,
+    /// Print each file name once above its lines rather than on every line
+    pub(crate) heading: bool,
+    /// What goes between groups of context lines; `None` for nothing
+    pub(crate) group_separator: Option<String>,
+    /// Whether to prefix output with file names; `None` decides by the file count
+    pub(crate) with_filename: Option<bool>,
+    pub(crate) null: bool,
+    /// What follows the file name and numbers of a matching line
+    pub(crate) field_match_separator: String,
+    /// What replaces the separators within printed paths
+    pub(crate) path_separator: Option<String>,
+    /// What file names link to, in color output
+    pub(crate) hyperlink: Option<HyperlinkFormat>,
+    /// The name standard input is shown under
+    pub(crate) label: Option<String>,
+    pub(crate) binary_files: BinaryFiles,
+    /// What input goes through before it's searched, in order
+    pub(crate) decoders: Vec<Arc<dyn Decoder>>,
+    /// How many files to search at once
+    pub(crate) threads: usize,
+    /// Map large files into memory rather than reading them
+    pub(crate) mmap: bool,
+    /// The most bytes to hold in memory at once over every file searched,
+    /// in lines, context, --unique's lines and output waiting to be printed
+    pub(crate) max_memory: Option<u64>,
+    /// Command whose output is searched in place of each file's contents
+    pub(crate) pre: Option<String>,
+    /// Limits `pre` to the files whose name matches
+    pub(crate) pre_glob: GlobSet,
+    /// Write output a line at a time rather than in blocks
+    pub(crate) line_buffered: bool,
+    /// Page output that goes to a terminal
+    pub(crate) pager: bool,
+    /// Told about each file as it's searched
+    pub(crate) progress: Option<Arc<dyn Progress>>,
+    /// Where files are read from in place of the disk
+    pub(crate) vfs: Option<Arc<dyn Vfs>>,
+}
+
+impl Config {
+    /// Starts a config to fill in from code rather than the command line
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// The files to search, along with how many were left out for their size
+    pub(crate) fn find_files(&self) -> (Vec<Result<PathBuf, GreprError>>, usize) {
+        let vfs = self.vfs.as_deref().unwrap_or(&RealFs);
+        find_files_in(vfs, &self.files, &self.walk)
+    }
+}
+
+/// Builds a `Config` without going through `get_args`. Whatever isn't set
+/// is as with no options and output that isn't a terminal: plain lines
+/// without color, headings or line numbers, from stdin if no files are given.
+#[derive(Debug)]
+pub struct ConfigBuilder {
+    patterns: Vec<String>,
+    syntax: Syntax,
+    regex: RegexOptions,
+    files: Vec<PathBuf>,
+    walk: WalkOptions,
+    search: SearchOptions,
+    count: bool,
+    count_matches: bool,
+    line_number: bool,
+    byte_offset: bool,
+    column: bool,
+    only_matching: bool,
+    files_with_matches: bool,
+    files_without_match: bool,
+    quiet: bool,
+    no_messages: bool,
+    with_filename: Option<bool>,
+    heading: bool,
+    replace: Option<String>,
+    json: bool,
+    threads: usize,
+    max_memory: Option<u64>,
+    progress: Option<Arc<dyn Progress>>,
+    vfs: Option<Arc<dyn Vfs>>,
+    decoders: Vec<Arc<dyn Decoder>>,
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        ConfigBuilder {
+            patterns: vec![],
+            syntax: Syntax::Regex,
+            regex: RegexOptions::default(),
+            files: vec![],
+            walk: WalkOptions::default(),
+            search: SearchOptions::default(),
+            count: false,
+            count_matches: false,
+            line_number: false,
+            byte_offset: false,
+            column: false,
+            only_matching: false,
+            files_with_matches: false,
+            files_without_match: false,
+            quiet: false,
+            no_messages: false,
+            with_filename: None,
+            heading: false,
+            replace: None,
+            json: false,
+            threads: 1,
+            max_memory: None,
+            progress: None,
+            vfs: None,
+            decoders: vec![],
+        }
+    }
+}
+
+impl ConfigBuilder {
+    /// Adds a pattern, any of which may match
+    pub fn pattern(mut self, pattern: &str) -> Self {
+        self.patterns.push(pattern.to_string());
+        self
+    }
+
+    /// How the patterns are read, as with -E, -F or -P
+    pub fn syntax(mut self, syntax: Syntax) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    pub fn regex_options(mut self, regex: RegexOptions) -> Self {
+        self.regex = regex;
+        self
+    }
+
+    /// Adds a file or directory to search, or `-` for stdin
+    pub fn file(mut self, file: impl AsRef<Path>) -> Self {
+        self.files.push(file.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn walk_options(mut self, walk: WalkOptions) -> Self {
+        self.walk = walk;
+        self
+    }
+
+    pub fn search_options(mut self, search: SearchOptions) -> Self {
+        self.search = search;
+        self
+    }
+
+    /// Print how many lines matched, as with -c
+    pub fn count(mut self, yes: bool) -> Self {
+        self.count = yes;
+        self
+    }
+
+    /// Print how many matches there were, as with --count-matches
+    pub fn count_matches(mut self, yes: bool) -> Self {
+        self.count_matches = yes;
+        self
+    }
+
+    pub fn line_number(mut self, yes: bool) -> Self {
+        self.line_number = yes;
+        self
+    }
+
+    pub fn byte_offset(mut self, yes: bool) -> Self {
+        self.byte_offset = yes;
+        self
+    }
+
+    pub fn column(mut self, yes: bool) -> Self {
+        self.column = yes;
+        self
+    }
+
+    pub fn only_matching(mut self, yes: bool) -> Self {
+        self.only_matching = yes;
+        self
+    }
+
+    pub fn files_with_matches(mut self, yes: bool) -> Self {
+        self.files_with_matches = yes;
+        self
+    }
+
+    pub fn files_without_match(mut self, yes: bool) -> Self {
+        self.files_without_match = yes;
+        self
+    }
+
+    pub fn quiet(mut self, yes: bool) -> Self {
+        self.quiet = yes;
+        self
+    }
+
+    pub fn no_messages(mut self, yes: bool) -> Self {
+        self.no_messages = yes;
+        self
+    }
+
+    /// Whether to prefix lines with file names, rather than deciding by
+    /// how many files there are
+    pub fn with_filename(mut self, yes: bool) -> Self {
+        self.with_filename = Some(yes);
+        self
+    }
+
+    pub fn heading(mut self, yes: bool) -> Self {
+        self.heading = yes;
+        self
+    }
+
+    /// Rewrites each match with a template, as with -r
+    pub fn replace(mut self, template: &str) -> Self {
+        self.replace = Some(template.to_string());
+        self
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn json(mut self, yes: bool) -> Self {
+        self.json = yes;
+        self
+    }
+
+    /// How many files to search at once, at least one
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Holds no more than this many bytes in memory at once, cutting lines
+    /// short and printing output as it's found rather than running out, as
+    /// with --max-memory
+    pub fn max_memory(mut self, bytes: u64) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+
+    /// Hooks to tell about each file as it's searched
+    pub fn progress(mut self, progress: Arc<dyn Progress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Adds a decoder for input to go through before it's searched, after
+    /// those added before it. Transcoding from UTF-16 with a BOM comes last.
+    pub fn decoder(mut self, decoder: Arc<dyn Decoder>) -> Self {
+        self.decoders.push(decoder);
+        self
+    }
+
+    /// Walks and reads the files from `vfs` rather than the disk
+    pub fn vfs(mut self, vfs: Arc<dyn Vfs>) -> Self {
+        self.vfs = Some(vfs);
+        self
+    }
+
+    /// Compiles the patterns into a config ready to `run`
+    pub fn build(self) -> MyResult<Config> {
+        let files = if self.files.is_empty() {
+            vec![PathBuf::from("-")]
+        } else {
+            self.files
+        };
+        Ok(Config {
+            pattern: build_pattern(&self.patterns, &self.regex, self.syntax)?,
+            files,
+            walk: self.walk,
+            search: self.search,
+            count: self.count || self.count_matches,
+            count_matches: self.count_matches,
+            total: false,
+            stats: false,
+            json: self.json,
+            line_number: self.line_number,
+            byte_offset: self.byte_offset,
+            column: self.column,
+            vimgrep: false,
+            format: None,
+            max_columns: None,
+            max_columns_preview: false,
+            trim: false,
+            initial_tab: false,
+            files_with_matches: self.files_with_matches,
+            list_files: false,
+            type_list: None,
+            replace: self.replace,
+            files_without_match: self.files_without_match,
+            only_matching: self.only_matching,
+            unique: false,
+            unique_count: false,
+            quiet: self.quiet,
+            no_messages: self.no_messages,
+            colors: Colors::default(),
+            heading: self.heading && !self.json,
+            group_separator: Some("--".to_string()),
+            with_filename: self.with_filename,
+            null: false,
+            field_match_separator: ":".to_string(),
+            path_separator: None,
+            hyperlink: None,
+            label: None,
+            binary_files: BinaryFiles::Binary,
+            decoders: self
+                .decoders
+                .into_iter()
+                .chain([Arc::new(Transcoder(None)) as Arc<dyn Decoder>])
+                .collect(),
+            threads: self.threads,
+            mmap: false,
+            max_memory: self.max_memory,
+            pre: None,
+            pre_glob: GlobSet::empty(),
+            line_buffered: false,
+            pager: false,
+            progress: self.progress,
+            vfs: self.vfs,
+        })
+    }
+}
+
+/// What to do with files that look like binary data
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum BinaryFiles {
+    /// Report whether the file matches without printing the lines
+    Binary,
+    /// Search the file as if it were text
+    Text,
+    /// Assume the file doesn't match
+    WithoutMatch,
+}
+
+/// Passes reads through to `inner`, adding up how many bytes were consumed
+struct Counted<'a, R> {
+    inner: R,
+    bytes: &'a Cell<u64>,
+}
+
+impl<R: Read> Read for Counted<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes.set(self.bytes.get() + n as u64);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Counted<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.bytes.set(self.bytes.get() + amt as u64);
+        self.inner.consume(amt);
+    }
+}
+
+/// What the threads of `search_parallel` send back as they search
+enum Message {
+    /// Output of the next file to report, to print as it comes
+    Output(Vec<u8>),
+    /// A file's been searched, with what it found and the output that was
+    /// held for it
+    Searched(
+        usize,
+        Result<PathBuf, String>,
+        Result<FileOutcome, String>,
+        Vec<u8>,
+    ),
+}
+
+/// One file's output, held until it can be printed in order. Once holding
+/// more would go past the memory limit, it waits for the file's turn and
+/// from then on is sent to be printed as it's written.
+struct Held<'a> {
+    bytes: Vec<u8>,
+    /// Where the file is in the order they're reported
+    index: usize,
+    memory: Option<&'a Budget>,
+    window: &'a Mutex<Window>,
+    moved: &'a Condvar,
+    sender: &'a mpsc::SyncSender<Message>,
+    /// Whether the output is sent as it's written
+    streaming: &'a Cell<bool>,
+    /// Whether the file's printed a line, which an earlier file's lines
+    /// are set apart from
+    printed_lines: &'a Cell<bool>,
+    /// What sets its lines apart from an earlier file's
+    separator: Option<String>,
+}
+
+impl Held<'_> {
+    /// Waits for the file's turn, then sends what's held on its way
+    fn stream(&mut self) -> io::Result<()> {
+        let mut state = self.window.lock().unwrap();
+        while !state.stopped && state.reported < self.index {
+            state = self.moved.wait(state).unwrap();
+        }
+        if state.stopped {
+            return Err(io::Error::other("search stopped"));
+        }
+        let printed_before = state.printed_lines;
+        drop(state);
+
+        self.streaming.set(true);
+        let mut bytes = std::mem::take(&mut self.bytes);
+        let held = bytes.len();
+        if let (true, true, Some(separator)) =
+            (printed_before, self.printed_lines.get(), &self.separator)
+        {
+            bytes.splice(..0, separator.bytes());
+        }
+        let sent = self.send(bytes);
+        if let Some(memory) = self.memory {
+            memory.release(held);
+        }
+        sent
+    }
+
+    fn send(&self, bytes: Vec<u8>) -> io::Result<()> {
+        self.sender
+            .send(Message::Output(bytes))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+impl io::Write for Held<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.streaming.get() {
+            if self.memory.is_none_or(|memory| memory.hold(buf.len())) {
+                self.bytes.extend_from_slice(buf);
+                return Ok(buf.len());
+            }
+            self.stream()?;
+        }
+        self.send(buf.to_vec())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A preprocessor's output, read as it's written. Its exit status is only
+/// known at the end, where a failure is an error reading it.
+struct Piped {
+    child: std::process::Child,
+    stdout: BufReader<std::process::ChildStdout>,
+    cmd: String,
+}
+
+impl Piped {
+    fn check_status(&mut self) -> io::Result<()> {
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("{} failed: {}", self.cmd, status)));
+        }
+        Ok(())
+    }
+}
+
+impl Read for Piped {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.check_status()?;
+        }
+        Ok(n)
+    }
+}
+
+impl BufRead for Piped {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.stdout.fill_buf()?.is_empty() {
+            self.check_status()?;
+        }
+        self.stdout.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.stdout.consume(amt);
+    }
+}
+
+impl Drop for Piped {
+    /// A search that stops early leaves the rest unread
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// How a run turned out, as grep reports it in its exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Something was found
+    Matched,
+    NoMatch,
+    /// Some file could not be searched
+    Error,
+}
+
+impl Status {
+    fn new(matched: bool, all_ok: bool) -> Status {
+        if !all_ok {
+            Status::Error
+        } else if matched {
+            Status::Matched
+        } else {
+            Status::NoMatch
+        }
+    }
+
+    pub fn code(self) -> i32 {
+        match self {
+            Status::Matched => 0,
+            Status::NoMatch => 1,
+            Status::Error => 2,
+        }
+    }
+}
+
+/// What a run found, as its status along with the totals over every file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub status: Status,
+    pub stats: SearchStats,
+}
+
+impl Summary {
+    /// A run that's found nothing yet
+    fn new() -> Summary {
+        Summary {
+            status: Status::NoMatch,
+            stats: SearchStats::default(),
+        }
+    }
+}
+
+/// Searches every configured file, printing the results
+pub fn run(config: Config) -> MyResult<Summary> {
+    // Paging is only for a person at a terminal
+    let mut pager = if config.pager && io::stdout().is_terminal() {
+        Some(spawn_pager()?)
+    } else {
+        None
+    };
+    let Some(child) = &mut pager else {
+        // Stdout is line-buffered already
+        let mut out: Box<dyn io::Write> = if config.line_buffered {
+            Box::new(io::stdout().lock())
+        } else {
+            Box::new(BufWriter::new(io::stdout().lock()))
+        };
+        // Output cut short, as by head, is no error either, and the run
+        // ends with what it found by then
+        let mut so_far = Summary::new();
+        return match search_files(config, &mut out, &mut so_far) {
+            Err(e) if is_broken_pipe(&e) => Ok(so_far),
+            summary => summary,
+        };
+    };
+
+    let stdin = child.stdin.take().expect("pager stdin is piped");
+    let mut out: Box<dyn io::Write> = if config.line_buffered {
+        Box::new(io::LineWriter::new(stdin))
+    } else {
+        Box::new(BufWriter::new(stdin))
+    };
+    let mut so_far = Summary::new();
+    let summary = search_files(config, &mut out, &mut so_far);
+    // Dropping the output closes the pipe, so the pager sees the end of it
+    drop(out);
+    child.wait()?;
+    match summary {
+        // Quitting the pager before the end is no error, and the run ends
+        // with what it found by then
+        Err(e) if is_broken_pipe(&e) => Ok(so_far),
+        summary => summary,
+    }
+}
+
+/// Whether an error came of writing to a pipe that's been closed
+fn is_broken_pipe(e: &GreprError) -> bool {
+    e.io_error().map(io::Error::kind) == Some(io::ErrorKind::BrokenPipe)
+}
+
+/// Starts $PAGER, or else less, reading from a pipe
+fn spawn_pager() -> MyResult<std::process::Child> {
+    let pager = std::env::var("PAGER").unwrap_or_default();
+    let mut words = pager.split_whitespace();
+    let program = words.next().unwrap_or("less");
+    let mut cmd = Command::new(program);
+    cmd.args(words).stdin(Stdio::piped());
+    // Like git, less is told to keep colors and to quit if the output fits
+    // on one screen, unless it's been told otherwise
+    if std::env::var_os("LESS").is_none() {
+        cmd.env("LESS", "FRX");
+    }
+    cmd.spawn()
+        .map_err(|e| From::from(format!("{}: {}", program, e)))
+}
+
+/// Searches every configured file, printing the results to `out` rather
+/// than stdout, where they can be captured
+pub fn run_with_output(config: Config, out: &mut dyn io::Write) -> MyResult<Summary> {
+    search_files(config, out, &mut Summary::new())
+}
+
+/// Does the work of `run_with_output`, keeping `so_far` up to date with
+/// the files searched, for a run whose output is cut short
+fn search_files(
+    config: Config,
+    out: &mut dyn io::Write,
+    so_far: &mut Summary,
+) -> MyResult<Summary> {
+    let started = Instant::now();
+    let mut stats = SearchStats::default();
+    if let Some(types) = &config.type_list {
+        so_far.status = Status::Matched;
+        for def in types.list() {
+            writeln!(out, "{}", def)?;
+        }
+        out.flush()?;
+        return Ok(Summary {
+            status: Status::Matched,
+            stats,
+        });
+    }
+
+    // Files are searched as they're found
+    let mut walker =
+        FileWalker::new(config.files.clone(), config.walk.clone()).threads(config.threads);
+    if let Some(vfs) = &config.vfs {
+        walker = walker.vfs(vfs.clone());
+    }
+    let files = walker.spawn();
+    // Whether there's more than one is known from the first two found
+    let first: Vec<_> = files.iter().take(2).collect();
+    let several = first.len() > 1;
+    // Walk errors are shared with the workers, so they're kept as messages
+    let file_paths = first
+        .into_iter()
+        .chain(files)
+        .map(|path| path.map_err(|e| e.to_string()));
+    // Several paths get names even if some of them were skipped
+    let show_filename = config
+        .with_filename
+        .unwrap_or(several || config.files.len() > 1);
+    // Counts stay on one line per file either way
+    let heading = config.heading && show_filename && !config.count;
+    let with_context = !config.count
+        && !config.only_matching
+        && !config.vimgrep
+        && config.format.is_none()
+        && !config.unique
+        && (config.search.before_context > 0
+            || config.search.after_context > 0
+            || config.search.passthru);
+    let search = if with_context {
+        config.search
+    } else {
+        SearchOptions {
+            before_context: 0,
+            after_context: 0,
+            passthru: false,
+            ..config.search
+        }
+    };
+    let searcher = Searcher {
+        config: &config,
+        search,
+        show_filename,
+        heading,
+        with_context,
+        memory: config.max_memory.map(Budget::new),
+    };
+
+    // Read while a file is searched as well as between files
+    let printed_any = Cell::new(false);
+    let mut all_ok = true;
+    let mut total = 0;
+    let sep = paint(&config.colors.separator, &config.field_match_separator);
+    // With -L success is listing a file
+    let matched = |stats: &SearchStats| {
+        if config.files_without_match {
+            stats.files_searched > stats.files_matched
+        } else {
+            stats.files_matched > 0
+        }
+    };
+
+    if config.list_files {
+        let mut listed = false;
+        for path in file_paths {
+            match path {
+                Err(e) => searcher.warn(&e, &mut all_ok),
+                Ok(path) => {
+                    let terminator = if config.null { "\0" } else { "\n" };
+                    listed = true;
+                    so_far.status = Status::new(true, all_ok);
+                    write!(out, "{}{}", searcher.filename(&path, None), terminator)?;
+                }
+            }
+        }
+        out.flush()?;
+        stats.files_skipped = walker.skipped();
+        stats.elapsed = started.elapsed();
+        return Ok(Summary {
+            status: Status::new(listed, all_ok),
+            stats,
+        });
+    }
+
+    let progress = config.progress.as_deref();
+
+    // Takes each file's outcome in the order the files were found, once
+    // its output is printed. Returns whether the search is over.
+    let mut report = |path: &Result<PathBuf, String>, outcome: FileOutcome| -> MyResult<bool> {
+        if let Some(warning) = &outcome.warning {
+            searcher.warn(warning, &mut all_ok);
+        }
+        // What was cut short to fit the memory limit was still searched
+        if let Some(notice) = &outcome.notice {
+            searcher.message(notice);
+        }
+        if let (Some(progress), Ok(path)) = (progress, path) {
+            let file_stats = &outcome.stats;
+            progress.files_found(walker.found());
+            progress.file_searched(
+                &path.to_string_lossy(),
+                file_stats.bytes_searched,
+                file_stats.matched_lines,
+            );
+        }
+        printed_any.set(printed_any.get() || outcome.printed_lines);
+        total += outcome.count;
+        stats.add(&outcome.stats);
+        stats.files_skipped = walker.skipped();
+        stats.elapsed = started.elapsed();
+        *so_far = Summary {
+            status: Status::new(matched(&stats), all_ok),
+            stats,
+        };
+        // The remaining files don't matter once anything matched
+        Ok(config.quiet && outcome.stats.files_matched > 0)
+    };
+
+    let searched = if !several || config.threads == 1 {
+        // Lines go straight to the output, so they can keep up with slow input
+        let search_each = || {
+            for path in file_paths {
+                let outcome = searcher.search_path(&path, out, &|| printed_any.get())?;
+                if report(&path, outcome)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        };
+        search_each()
+    } else {
+        searcher.search_parallel(file_paths, config.threads, out, &printed_any, report)
+    };
+    // The file whose output broke the pipe had lines to print, which it
+    // only has if it matched
+    let stopped = searched.inspect_err(|e| {
+        if is_broken_pipe(e) {
+            so_far.status = Status::new(true, all_ok);
+        }
+    })?;
+    if let Some(progress) = progress {
+        progress.finished();
+    }
+    stats.files_skipped = walker.skipped();
+    stats.elapsed = started.elapsed();
+    // As in grep, a match found with -q outweighs any errors
+    if stopped {
+        *so_far = Summary {
+            status: Status::Matched,
+            stats,
+        };
+        out.flush()?;
+        return Ok(*so_far);
+    }
+    *so_far = Summary {
+        status: Status::new(matched(&stats), all_ok),
+        stats,
+    };
+
+    if config.count && config.total {
+        writeln!(out, "total{}{}", sep, total)?;
+    }
+
+    #[cfg(feature = "serde")]
+    if config.json {
+        writeln!(out, "{}", json::summary(&stats))?;
+    }
+    if config.stats && !config.json {
+        stats.print(out)?;
+    }
+
+    out.flush()?;
+    debug!(
+        "searched {} files in {:?}",
+        stats.files_searched,
+        started.elapsed()
+    );
+    Ok(*so_far)
+}
+
+/// The distinct lines of output from one file, for --unique. Under a
+/// memory limit only as many are kept as there's room for.
+#[derive(Debug)]
+struct Unique<'a> {
+    /// Where each distinct line is in `lines`
+    index: HashMap<Vec<u8>, usize>,
+    /// Each distinct line with the prefix it was first printed with, and
+    /// how many times it occurred
+    lines: Vec<(String, Vec<u8>, usize)>,
+    memory: Option<&'a Budget>,
+    /// The bytes of `memory` the lines take
+    held: usize,
+}
+
+impl<'a> Unique<'a> {
+    fn new(memory: Option<&'a Budget>) -> Unique<'a> {
+        Unique {
+            index: HashMap::new(),
+            lines: vec![],
+            memory,
+            held: 0,
+        }
+    }
+
+    /// Counts a line of output, returning whether it's the first like it,
+    /// or `None` for a new one there's no room to keep
+    fn add(&mut self, prefix: &str, text: &[u8]) -> Option<bool> {
+        if let Some(&i) = self.index.get(text) {
+            self.lines[i].2 += 1;
+            return Some(false);
+        }
+        // The text is held twice, to look up and to print
+        let size = prefix.len() + 2 * text.len();
+        if !self.memory.is_none_or(|memory| memory.hold(size)) {
+            return None;
+        }
+        self.held += size;
+        self.index.insert(text.to_vec(), self.lines.len());
+        self.lines.push((prefix.to_string(), text.to_vec(), 1));
+        Some(true)
+    }
+
+    /// Prints the lines kept with their counts, for --unique-count, and
+    /// lets go of them
+    fn print_counts(&mut self, out: &mut dyn io::Write) -> io::Result<()> {
+        self.index.clear();
+        for (prefix, text, count) in self.lines.drain(..) {
+            write_count(out, &prefix, count, &text)?;
+        }
+        if let Some(memory) = self.memory {
+            memory.release(std::mem::take(&mut self.held));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Unique<'_> {
+    fn drop(&mut self) {
+        if let Some(memory) = self.memory {
+            memory.release(self.held);
+        }
+    }
+}
+
+/// Prints a line of --unique-count output
+fn write_count(out: &mut dyn io::Write, prefix: &str, count: usize, text: &[u8]) -> io::Result<()> {
+    write!(out, "{}{:>7} ", prefix, count)?;
+    out.write_all(text)
+}
+
+/// What searching a single file found
+#[derive(Debug, Default)]
+struct FileOutcome {
+    stats: SearchStats,
+    /// The number printed with --count
+    count: usize,
+    /// Whether any lines were printed, which sets off the next file's lines
+    printed_lines: bool,
+    /// Why the file couldn't be searched, for stderr
+    warning: Option<String>,
+    /// What the file was searched in spite of, for stderr
+    notice: Option<String>,
+}
+
+impl FileOutcome {
+    fn warning(msg: String) -> FileOutcome {
+        FileOutcome {
+            warning: Some(msg),
+            ..Default::default()
+        }
+    }
+}
+
+/// How far `search_parallel` has got through the files in order
+#[derive(Debug, Default)]
+struct Window {
+    /// The files reported so far
+    reported: usize,
+    /// Whether any of them printed lines
+    printed_lines: bool,
+    stopped: bool,
+}
+
+/// The settings shared by the search of every file in a run
+struct Searcher<'a> {
+    config: &'a Config,
+    /// The config's options, less any context that isn't printed
+    search: SearchOptions,
+    show_filename: bool,
+    heading: bool,
+    with_context: bool,
+    /// What's held across every file, under --max-memory
+    memory: Option<Budget>,
+}
+
+impl Searcher<'_> {
+    /// How a path is shown in the output, with any bytes that aren't UTF-8
+    /// replaced
+    fn name<'p>(&'p self, path: &'p Path) -> Cow<'p, str> {
+        match (&self.config.label, &self.config.path_separator) {
+            (Some(label), _) if is_stdin(path) => Cow::Borrowed(label),
+            (_, Some(sep)) => {
+                Cow::Owned(path.to_string_lossy().replace(std::path::is_separator, sep))
+            }
+            _ => path.to_string_lossy(),
+        }
+    }
+
+    /// How a path is shown in --json, as bytes that are kept as they are on
+    /// Unix whether or not they're UTF-8
+    #[cfg(feature = "serde")]
+    fn name_bytes<'p>(&'p self, path: &'p Path) -> Cow<'p, [u8]> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+
+            let bytes = path.as_os_str().as_bytes();
+            match (&self.config.label, &self.config.path_separator) {
+                (Some(label), _) if is_stdin(path) => Cow::Borrowed(label.as_bytes()),
+                (_, Some(sep)) => Cow::Owned(
+                    bytes
+                        .split(|&b| b == b'/')
+                        .collect::<Vec<_>>()
+                        .join(sep.as_bytes()),
+                ),
+                _ => Cow::Borrowed(bytes),
+            }
+        }
+        #[cfg(not(unix))]
+        match self.name(path) {
+            Cow::Borrowed(name) => Cow::Borrowed(name.as_bytes()),
+            Cow::Owned(name) => Cow::Owned(name.into_bytes()),
+        }
+    }
+
+    /// A path as printed before its lines, linked to the line and column
+    /// given with --hyperlink-format
+    fn filename(&self, path: &Path, position: Option<(usize, usize)>) -> String {
+        let painted = paint(&self.config.colors.filename, &self.name(path));
+        let Some(format) = &self.config.hyperlink else {
+            return painted;
+        };
+        // Standard input has nowhere to link to
+        match std::fs::canonicalize(path) {
+            Ok(absolute) if !is_stdin(path) => {
+                hyperlink::wrap(&format.url(&absolute, position), &painted)
+            }
+            _ => painted,
+        }
+    }
+
+    fn warn(&self, msg: &str, all_ok: &mut bool) {
+        *all_ok = false;
+        self.message(msg);
+    }
+
+    /// Prints to stderr, unless messages are suppressed
+    fn message(&self, msg: &str) {
+        if !self.config.no_messages {
+            eprintln!("{}", msg);
+        }
+    }
+
+    /// What goes between the lines of one file and the next. A new file
+    /// always starts a new group of context lines, and headings set files
+    /// apart with a blank line instead.
+    fn file_separator(&self) -> Option<String> {
+        if self.heading {
+            Some("\n".to_string())
+        } else if self.with_context {
+            self.group_separator()
+        } else {
+            None
+        }
+    }
+
+    /// The line between groups of context lines that aren't contiguous
+    fn group_separator(&self) -> Option<String> {
+        let sep = self.config.group_separator.as_ref()?;
+        Some(format!("{}\n", paint(&self.config.colors.separator, sep)))
+    }
+
+    /// Searches the files on `threads` threads, printing each file's output
+    /// to `out` and handing its outcome to `report` in the order the files
+    /// were found. Output is held until it's printed, so files are only
+    /// searched so far ahead of the next to report, and output that won't
+    /// fit the memory limit waits for its file's turn to be printed as it's
+    /// found. `printed_any` says whether the files reported so far printed
+    /// lines. Returns whether `report` cut the search short.
+    fn search_parallel(
+        &self,
+        paths: impl Iterator<Item = Result<PathBuf, String>> + Send,
+        threads: usize,
+        out: &mut dyn io::Write,
+        printed_any: &Cell<bool>,
+        mut report: impl FnMut(&Result<PathBuf, String>, FileOutcome) -> MyResult<bool>,
+    ) -> MyResult<bool> {
+        // Each thread takes the next file found, waiting on the walk if need be
+        let paths = Mutex::new(paths.enumerate());
+        let ahead = threads * 2;
+        let window = Mutex::new(Window::default());
+        let moved = Condvar::new();
+        let (sender, receiver) = mpsc::sync_channel(threads);
+        let memory = self.memory.as_ref();
+
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                let sender = sender.clone();
+                let (paths, window, moved) = (&paths, &window, &moved);
+                scope.spawn(move || loop {
+                    let Some((i, path)) = paths.lock().unwrap().next() else {
+                        break;
+                    };
+                    let mut state = window.lock().unwrap();
+                    while !state.stopped && i >= state.reported + ahead {
+                        state = moved.wait(state).unwrap();
+                    }
+                    if state.stopped {
+                        break;
+                    }
+                    drop(state);
+                    let (streaming, printed_lines) = (Cell::new(false), Cell::new(false));
+                    let mut output = Held {
+                        bytes: vec![],
+                        index: i,
+                        memory,
+                        window,
+                        moved,
+                        sender: &sender,
+                        streaming: &streaming,
+                        printed_lines: &printed_lines,
+                        separator: self.file_separator(),
+                    };
+                    // Which files came before is only settled once it's
+                    // this one's turn. Until then the lines are held, and
+                    // set apart when they're printed.
+                    let printed_before = || {
+                        printed_lines.set(true);
+                        streaming.get() && window.lock().unwrap().printed_lines
+                    };
+                    // Errors can't cross threads as they are, so they're
+                    // sent as messages
+                    let outcome = self
+                        .search_path(&path, &mut output, &printed_before)
+                        .map_err(|e| e.to_string());
+                    let held = std::mem::take(&mut output.bytes);
+                    if sender
+                        .send(Message::Searched(i, path, outcome, held))
+                        .is_err()
+                    {
+                        break;
+                    }
+                });
+            }
+            drop(sender);
+
+            // Files finish in any order, so the early ones wait for the rest
+            let mut pending = BTreeMap::new();
+            let mut next_report = 0;
+            // Whether the next file to report has printed any of its output
+            let mut streamed = false;
+            let res = (|| {
+                for message in &receiver {
+                    let (i, path, outcome, output) = match message {
+                        Message::Output(bytes) => {
+                            out.write_all(&bytes)?;
+                            streamed = true;
+                            continue;
+                        }
+                        Message::Searched(i, path, outcome, output) => (i, path, outcome, output),
+                    };
+                    pending.insert(i, (path, outcome, output));
+                    while let Some((path, outcome, output)) = pending.remove(&next_report) {
+                        next_report += 1;
+                        let outcome = outcome?;
+                        if printed_any.get() && outcome.printed_lines && !streamed {
+                            if let Some(separator) = self.file_separator() {
+                                out.write_all(separator.as_bytes())?;
+                            }
+                        }
+                        out.write_all(&output)?;
+                        if let Some(memory) = memory {
+                            memory.release(output.len());
+                        }
+                        streamed = false;
+                        if report(&path, outcome)? {
+                            return Ok(true);
+                        }
+                        let mut state = window.lock().unwrap();
+                        state.reported = next_report;
+                        state.printed_lines = printed_any.get();
+                        drop(state);
+                        moved.notify_all();
+                    }
+                }
+                Ok(false)
+            })();
+            // Threads blocked sending a file that won't be reported wake up
+            // to find the receiver gone, and those waiting their turn to
+            // find the search stopped
+            drop(receiver);
+            window.lock().unwrap().stopped = true;
+            moved.notify_all();
+            res
+        })
+    }
+
+    /// Searches one of the paths found, printing the results to `out`.
+    /// `printed_before` says whether lines from an earlier file came first,
+    /// asked when the file's first line is printed.
+    fn search_path(
+        &self,
+        path: &Result<PathBuf, String>,
+        out: &mut dyn io::Write,
+        printed_before: &dyn Fn() -> bool,
+    ) -> MyResult<FileOutcome> {
+        let path = match path {
+            Err(e) => return Ok(FileOutcome::warning(e.clone())),
+            Ok(path) => path,
+        };
+
+        let bytes_searched = Cell::new(0);
+        let budget = self.memory.as_ref().map(Allowance::new);
+        let outcome = self.with_input(path, |file| {
+            let file = Counted {
+                inner: file,
+                bytes: &bytes_searched,
+            };
+            self.search_file(path, file, out, printed_before, budget.as_ref())
+        });
+        // A file that can't be read to the end is warned about like one
+        // that can't be opened, while other errors end the run
+        let mut outcome = match outcome {
+            Ok(Ok(outcome)) => outcome,
+            Ok(Err(warning)) => return Ok(FileOutcome::warning(warning)),
+            Err(e @ GreprError::Io { .. }) if e.path() == Some(path) => {
+                return Ok(FileOutcome::warning(e.to_string()))
+            }
+            Err(e) => return Err(e),
+        };
+        outcome.stats.files_searched = 1;
+        outcome.stats.bytes_searched = bytes_searched.get();
+        if let Some(budget) = budget.filter(|budget| budget.truncated() > 0) {
+            let what = match budget.truncated() {
+                _ if self.search.multiline => "input".to_string(),
+                1 => "1 line".to_string(),
+                n => format!("{} lines", n),
+            };
+            outcome.notice = Some(format!(
+                "{}: {} cut short to fit the memory limit of {} bytes",
+                path.display(),
+                what,
+                budget.budget.limit()
+            ));
+        }
+        Ok(outcome)
+    }
+
+    /// Opens a file as the options say, through any preprocessor, memory
+    /// map and decoders, and hands it to `f`. A file that can't be opened
+    /// gives the message to warn with instead.
+    fn with_input<T, E>(
+        &self,
+        path: &Path,
+        f: impl FnOnce(Box<dyn BufRead + '_>) -> Result<T, E>,
+    ) -> Result<Result<T, String>, E> {
+        let warning = |e: &dyn Display| Ok(Err(format!("{}: {}", path.display(), e)));
+
+        // A preprocessor's output takes the place of the file, so it isn't
+        // mapped. Only files on disk can be given to a preprocessor or mapped.
+        let vfs = self.config.vfs.as_deref();
+        let pre = self.config.pre.as_deref().filter(|_| {
+            !is_stdin(path)
+                && vfs.is_none()
+                && (self.config.pre_glob.is_empty()
+                    || path
+                        .file_name()
+                        .is_some_and(|name| self.config.pre_glob.is_match(name)))
+        });
+
+        // A mapped file is searched in place, through the same reader
+        // interface as any other
+        let max_memory = self.config.max_memory;
+        let map = if self.config.mmap && !is_stdin(path) && vfs.is_none() && pre.is_none() {
+            map_file(path, max_memory)
+        } else {
+            Ok(None)
+        };
+        let map = match map {
+            Ok(map) => map,
+            Err(e) => return warning(&e),
+        };
+        let file = match (pre, &map) {
+            (Some(cmd), _) => preprocess(cmd, path, max_memory),
+            (None, Some(map)) => Ok(Box::new(&map[..]) as Box<dyn BufRead>),
+            (None, None) => open(vfs.unwrap_or(&RealFs), path),
+        };
+        let file = match file {
+            Ok(file) => file,
+            Err(e) => return warning(&e),
+        };
+        // A preprocessor's output isn't the file, whatever its name
+        let file = match decode(
+            &self.config.decoders,
+            Some(path).filter(|_| pre.is_none()),
+            file,
+        ) {
+            Ok(file) => file,
+            Err(e) => return warning(&e),
+        };
+
+        f(file).map(Ok)
+    }
+
+    fn search_file(
+        &self,
+        path: &Path,
+        mut file: impl BufRead,
+        out: &mut dyn io::Write,
+        printed_before: &dyn Fn() -> bool,
+        budget: Option<&Allowance>,
+    ) -> MyResult<FileOutcome> {
+        let config = self.config;
+        let search = &self.search;
+        let pattern = &*config.pattern;
+        let mut outcome = FileOutcome::default();
+        let stats = &mut outcome.stats;
+
+        // NUL is an ordinary record terminator with -z
+        let binary = config.binary_files != BinaryFiles::Text
+            && !config.search.null_data
+            && is_binary(&mut file);
+        if binary && config.binary_files == BinaryFiles::WithoutMatch {
+            return Ok(outcome);
+        }
+
+        if config.quiet || config.files_with_matches || config.files_without_match {
+            let found = has_match(file, pattern, search, budget).map_err(|e| reading(path, e))?;
+            stats.files_matched = usize::from(found);
+            if !config.quiet && found == config.files_with_matches {
+                let terminator = if config.null { "\0" } else { "\n" };
+                write!(out, "{}{}", self.filename(path, None), terminator)?;
+            }
+            return Ok(outcome);
+        }
+
+        #[cfg(feature = "serde")]
+        if config.json {
+            // Binary files are left out, as their lines would be noise
+            if binary {
+                stats.files_matched = usize::from(
+                    has_match(file, pattern, search, budget).map_err(|e| reading(path, e))?,
+                );
+                return Ok(outcome);
+            }
+
+            let bytes_searched = Cell::new(0);
+            let file = Counted {
+                inner: file,
+                bytes: &bytes_searched,
+            };
+            let mut sink = JsonSink::new(self, out, stats, &bytes_searched);
+            search_sink(path, file, pattern, search, budget, &mut sink)?;
+            stats.files_matched = usize::from(stats.matched_lines > 0);
+            return Ok(outcome);
+        }
+
+        if config.count {
+            let mut sink = CountSink::new(self, out, stats);
+            search_sink(path, file, pattern, search, budget, &mut sink)?;
+            outcome.count = sink.count();
+        } else if binary {
+            // Printing the matching lines of a binary file would just be noise
+            if has_match(file, pattern, search, budget).map_err(|e| reading(path, e))? {
+                stats.files_matched = 1;
+                writeln!(out, "Binary file {} matches", self.name(path))?;
+            }
+            return Ok(outcome);
+        } else {
+            let mut sink = StandardSink::new(self, out, stats, printed_before);
+            search_sink(path, file, pattern, search, budget, &mut sink)?;
+            outcome.printed_lines = sink.printed_lines();
+        }
+        outcome.stats.files_matched = usize::from(outcome.stats.matched_lines > 0);
+        Ok(outcome)
+    }
+
+    /// What goes before the lines of a file when filenames are shown inline
+    fn prefix(&self, path: &Path, sep: &str, position: Option<(usize, usize)>) -> String {
+        if !self.show_filename || self.heading {
+            return String::new();
+        }
+        let terminator = if self.config.null { "\0" } else { sep };
+        format!("{}{}", self.filename(path, position), terminator)
+    }
+
+    /// Fills in the --format template for every match on a line, or just
+    /// once for a line without any. Bytes that aren't UTF-8 are shown as
+    /// U+FFFD, though columns still count them.
+    fn format_line(&self, template: &Template, path: &str, line: &Line) -> Vec<String> {
+        let content = self.search.content(&line.text);
+        let text = String::from_utf8_lossy(content);
+        // Each group is expanded for every match, to be found by its span
+        let groups: Vec<HashMap<_, _>> = template
+            .groups()
+            .iter()
+            .map(|group| {
+                let expand = format!("${{{}}}", group);
+                let expanded = self.config.pattern.replacements(content, &expand);
+                expanded
+                    .into_iter()
+                    .map(|(span, value)| (span, String::from_utf8_lossy(&value).into_owned()))
+                    .collect()
+            })
+            .collect();
+
+        let spans = match line.spans.as_slice() {
+            [] => vec![None],
+            spans => spans.iter().copied().map(Some).collect(),
+        };
+        spans
+            .into_iter()
+            .map(|span| {
+                let values: Vec<String> = groups
+                    .iter()
+                    .map(|group| {
+                        span.and_then(|s| group.get(&s).cloned())
+                            .unwrap_or_default()
+                    })
+                    .collect();
+                let matched =
+                    span.map(|(start, end)| String::from_utf8_lossy(&line.text[start..end]));
+                template.render(&Fields {
+                    path,
+                    line: line.number,
+                    column: span.map(|(start, _)| start + 1),
+                    text: &text,
+                    matched: matched.as_deref().unwrap_or(""),
+                    groups: &values,
+                })
+            })
+            .collect()
+    }
+
+    /// Writes a line of output, unless --unique holds it back
+    fn emit(
+        &self,
+        out: &mut dyn io::Write,
+        unique: &mut Unique,
+        prefix: &str,
+        text: &[u8],
+    ) -> io::Result<()> {
+        if self.config.unique {
+            // With counts every line waits for the end of the file, unless
+            // there's no room left to keep it. Then the counts so far are
+            // printed to make room, and without counts the line is printed
+            // without being kept.
+            let mut first = unique.add(prefix, text);
+            if first.is_none() && self.config.unique_count {
+                unique.print_counts(out)?;
+                first = unique.add(prefix, text);
+            }
+            match first {
+                Some(false) => return Ok(()),
+                Some(true) if self.config.unique_count => return Ok(()),
+                None if self.config.unique_count => return write_count(out, prefix, 1, text),
+                _ => {}
+            }
+        }
+        write!(out, "{}", prefix)?;
+        out.write_all(text)
+    }
+}
+
+/// Text to print along with the spans to highlight within it
+type Spanned = (Vec<u8>, Vec<(usize, usize)>);
+
+/// Rewrites each matched span of `text`, returning the new text and the
+/// spans of the replacements within it
+fn replace_spans(text: &[u8], replacements: Vec<((usize, usize), Vec<u8>)>) -> Spanned {
+    let mut res = Vec::with_capacity(text.len());
+    let mut spans = Vec::with_capacity(replacements.len());
+    let mut last = 0;
+    for ((start, end), replacement) in replacements {
+        res.extend_from_slice(&text[last..start]);
+        spans.push((res.len(), res.len() + replacement.len()));
+        res.extend_from_slice(&replacement);
+        last = end;
+    }
+    res.extend_from_slice(&text[last..]);
+    (res, spans)
+}
+
+/// Strips the whitespace `text` starts with, short of its terminator and
+/// any bytes that aren't UTF-8, moving `spans` along with it
+fn trim_start<'a>(text: &'a [u8], spans: &[(usize, usize)]) -> (&'a [u8], Vec<(usize, usize)>) {
+    let valid = text.utf8_chunks().next().map_or("", |chunk| chunk.valid());
+    let trimmed = valid.trim_start_matches(|c: char| c != '\n' && c.is_whitespace());
+    let removed = valid.len() - trimmed.len();
+    let spans = spans
+        .iter()
+        .map(|&(start, end)| (start.max(removed) - removed, end.max(removed) - removed))
+        .collect();
+    (&text[removed..], spans)
+}
+
+/// Shortens `text` if it's longer than `max` bytes, returning what's printed
+/// in its place and the spans within that. With `preview` the start of the
+/// line is kept, and otherwise only the number of matches.
+fn limit_columns(
+    text: &[u8],
+    spans: &[(usize, usize)],
+    max: usize,
+    preview: bool,
+) -> Option<Spanned> {
+    if text.len() <= max {
+        return None;
+    }
+    if !preview {
+        let text = format!("[Omitted long line with {} matches]", spans.len());
+        return Some((text.into_bytes(), vec![]));
+    }
+
+    // The cut backs up to the start of a UTF-8 character it would split
+    let mut cut = max;
+    while cut > 0 && max - cut < 3 && (text[cut] as i8) < -0x40 {
+        cut -= 1;
+    }
+    let spans = spans
+        .iter()
+        .filter(|&&(start, _)| start < cut)
+        .map(|&(start, end)| (start, end.min(cut)))
+        .collect();
+    Some(([&text[..cut], b" [... omitted]"].concat(), spans))
+}
+
+/// Whether a path stands for stdin rather than a file
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+pub(crate) fn open<'a>(vfs: &'a dyn Vfs, filename: &Path) -> MyResult<Box<dyn BufRead + 'a>> {
+    if is_stdin(filename) {
+        return Ok(Box::new(BufReader::new(io::stdin())));
+    }
+    Ok(vfs.open(filename)?)
+}
+
+/// Runs `cmd` with the path of a file as its argument and the file as its
+/// input, returning what it printed. Under a memory limit that's read as
+/// it's printed rather than held whole.
+fn preprocess(cmd: &str, filename: &Path, max_memory: Option<u64>) -> MyResult<Box<dyn BufRead>> {
+    let input = std::fs::File::open(filename)?;
+    let mut command = Command::new(cmd);
+    command.arg(filename).stdin(input).stderr(Stdio::inherit());
+    if max_memory.is_some() {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("{}: {}", cmd, e))?;
+        let stdout = child.stdout.take().expect("preprocessor stdout is piped");
+        return Ok(Box::new(Piped {
+            child,
+            stdout: BufReader::new(stdout),
+            cmd: cmd.to_string(),
+        }));
+    }
+    let output = command.output().map_err(|e| format!("{}: {}", cmd, e))?;
+    if !output.status.success() {
+        return Err(From::from(format!("{} failed: {}", cmd, output.status)));
+    }
+    Ok(Box::new(Cursor::new(output.stdout)))
+}
+
+/// Maps a file into memory, unless it's too small for that to pay off
+fn map_file(filename: &Path, max_memory: Option<u64>) -> io::Result<Option<Mmap>> {
+    const MIN_LEN: u64 = 1 << 20;
+
+    let file = std::fs::File::open(filename)?;
+    let metadata = file.metadata()?;
+    // A file bigger than the memory allowed is read a block at a time
+    if !metadata.is_file()
+        || metadata.len() < MIN_LEN
+        || max_memory.is_some_and(|max| metadata.len() > max)
+    {
+        return Ok(None);
+    }
+    // SAFETY: the map is only ever read. A file that shrinks while it's
+    // searched may still bring grepr down, a risk ripgrep takes as well.
+    unsafe { Mmap::map(&file) }.map(Some)
+}
+
+/// Checks the first block of the input for a NUL byte without consuming it
+fn is_binary(file: &mut dyn BufRead) -> bool {
+    // A mapped file is all one block, but only its start is looked at
+    const BLOCK_LEN: usize = 8 * 1024;
+
+    file.fill_buf()
+        .map(|buf| memchr::memchr(0, &buf[..buf.len().min(BLOCK_LEN)]).is_some())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_binary, limit_columns, replace_spans, run_with_output, trim_start, Config, Counted,
+        Status,
+    };
+    use crate::{
+        build_pattern, find_lines, has_match, GreprError, Matcher, RegexOptions, SearchOptions,
+        Syntax,
+    };
+    use regex::bytes::Regex;
+    use std::cell::Cell;
+    use std::io::{self, Cursor};
+    use std::path::Path;
+
+    #[test]
+    fn test_config_builder() {
+        let config = Config::builder()
+            .pattern("FOX")
+            .regex_options(RegexOptions {
+                insensitive: true,
+                ..Default::default()
+            })
+            .file("tests/inputs/fox.txt")
+            .count_matches(true)
+            .build()
+            .unwrap();
+        assert!(config.count);
+        assert_eq!(config.files, [Path::new("tests/inputs/fox.txt")]);
+        let mut out = vec![];
+        let summary = run_with_output(config, &mut out).unwrap();
+        assert_eq!(summary.status, Status::Matched);
+        assert_eq!(summary.stats.files_searched, 1);
+        assert_eq!(summary.stats.matches, 1);
+        assert_eq!(out, b"1\n");
+
+        let config = Config::builder()
+            .pattern("wolf")
+            .file("tests/inputs/fox.txt")
+            .quiet(true)
+            .build()
+            .unwrap();
+        let summary = run_with_output(config, &mut io::sink()).unwrap();
+        assert_eq!(summary.status, Status::NoMatch);
+
+        // With no files the config reads stdin
+        let config = Config::builder().pattern("x").build().unwrap();
+        assert_eq!(config.files, [Path::new("-")]);
+
+        let err = Config::builder().pattern("(").build().unwrap_err();
+        assert!(matches!(&err, GreprError::InvalidPattern(p) if p == "("));
+        assert_eq!(err.to_string(), "Invalid pattern \"(\"");
+    }
+
+    #[test]
+    fn test_replacements() {
+        let re = Regex::new(r"(?P<word>\w)(\w+)").unwrap();
+        let replacements = re.replacements(b"ab cd", "$2${word}");
+        assert_eq!(
+            replacements,
+            vec![((0, 2), b"ba".to_vec()), ((3, 5), b"dc".to_vec())]
+        );
+        assert_eq!(
+            replace_spans(b"ab cd\n", replacements),
+            (b"ba dc\n".to_vec(), vec![(0, 2), (3, 5)])
+        );
+
+        // A literal only has the whole match to refer to
+        let literal =
+            build_pattern(&["b".to_string()], &RegexOptions::default(), Syntax::Fixed).unwrap();
+        let replacements = literal.replacements(b"abc", "[$0$1]");
+        assert_eq!(replacements, vec![((1, 2), b"[b]".to_vec())]);
+        assert_eq!(
+            replace_spans(b"abc", replacements),
+            (b"a[b]c".to_vec(), vec![(1, 4)])
+        );
+    }
+
+    #[test]
+    fn test_is_binary() {
+        assert!(!is_binary(&mut Cursor::new(b"Lorem\nIpsum\n")));
+        assert!(!is_binary(&mut Cursor::new(b"")));
+
+        // Detection doesn't consume any of the input
+        let mut file = Cursor::new(b"Lorem\0Ipsum\n");
+        assert!(is_binary(&mut file));
+        let re = Regex::new("Lorem").unwrap();
+        let lines = find_lines(&mut file, &re, &SearchOptions::default()).unwrap();
+        assert_eq!(lines.len(), 1);
+
+        // Only the first block counts, even when more is at hand
+        let mut text = vec![b'a'; 16 * 1024];
+        text.push(0);
+        assert!(!is_binary(&mut text.as_slice()));
+    }
+
+    #[test]
+    fn test_trim_start() {
+        assert_eq!(
+            trim_start(b"    let x = 1;\n", &[(4, 7), (8, 9)]),
+            (&b"let x = 1;\n"[..], vec![(0, 3), (4, 5)])
+        );
+        // A match within the indentation is left empty
+        assert_eq!(
+            trim_start(b"\t\tx\n", &[(1, 3)]),
+            (&b"x\n"[..], vec![(0, 1)])
+        );
+        assert_eq!(trim_start(b" \t \n", &[(0, 1)]), (&b"\n"[..], vec![(0, 0)]));
+        assert_eq!(trim_start(b"none\n", &[]), (&b"none\n"[..], vec![]));
+        // Trimming stops at bytes that aren't UTF-8
+        assert_eq!(trim_start(b" \xff x\n", &[]), (&b"\xff x\n"[..], vec![]));
+    }
+
+    #[test]
+    fn test_limit_columns() {
+        let spans = [(4, 9), (16, 19)];
+        assert_eq!(
+            limit_columns(b"the quick brown fox", &spans, 19, false),
+            None
+        );
+        assert_eq!(
+            limit_columns(b"the quick brown fox", &spans, 10, false),
+            Some((b"[Omitted long line with 2 matches]".to_vec(), vec![]))
+        );
+        assert_eq!(
+            limit_columns(b"the quick brown fox", &spans, 6, true),
+            Some((b"the qu [... omitted]".to_vec(), vec![(4, 6)]))
+        );
+
+        // A preview never splits a character
+        assert_eq!(
+            limit_columns("héllo".as_bytes(), &[], 2, true),
+            Some((b"h [... omitted]".to_vec(), vec![]))
+        );
+        assert_eq!(
+            limit_columns(b"\xff\xff\xff", &[], 2, true),
+            Some((b"\xff\xff [... omitted]".to_vec(), vec![]))
+        );
+    }
+
+    #[test]
+    fn test_counted() {
+        // Bytes are counted up to where the search stopped
+        let bytes = Cell::new(0);
+        let file = Counted {
+            inner: Cursor::new(b"Lorem\nIpsum\nDolor\n"),
+            bytes: &bytes,
+        };
+        let re = Regex::new("Ips").unwrap();
+        assert!(has_match(file, &re, &SearchOptions::default(), None).unwrap());
+        assert_eq!(bytes.get(), 12);
+
+        // Reading everything at once is counted too
+        let file = Counted {
+            inner: Cursor::new(b"Lorem\nIpsum\nDolor\n"),
+            bytes: &bytes,
+        };
+        let options = SearchOptions {
+            multiline: true,
+            ..Default::default()
+        };
+        find_lines(file, &re, &options).unwrap();
+        assert_eq!(bytes.get(), 30);
+    }
+}
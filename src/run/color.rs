@@ -0,0 +1,123 @@
+//! ANSI colors for highlighting output, configured like GNU grep's `GREP_COLORS`
+
+/// SGR sequences for each part of the output. An empty sequence leaves that
+/// part uncolored, so `Colors::default()` disables coloring entirely.
+#[derive(Debug, Default)]
+pub struct Colors {
+    pub selected_match: String,
+    pub context_match: String,
+    pub filename: String,
+    pub line_number: String,
+    pub byte_offset: String,
+    pub separator: String,
+}
+
+impl Colors {
+    /// The colors GNU grep uses when `GREP_COLORS` is not set
+    pub fn new() -> Colors {
+        Colors {
+            selected_match: "01;31".to_string(),
+            context_match: "01;31".to_string(),
+            filename: "35".to_string(),
+            line_number: "32".to_string(),
+            byte_offset: "32".to_string(),
+            separator: "36".to_string(),
+        }
+    }
+
+    /// Overrides colors from a `GREP_COLORS`-style spec such as
+    /// `ms=01;31:fn=35`. Unknown or malformed entries are ignored.
+    pub fn apply_spec(&mut self, spec: &str) {
+        for entry in spec.split(':') {
+            let (cap, sgr) = match entry.split_once('=') {
+                Some((cap, sgr)) if sgr.chars().all(|c| c.is_ascii_digit() || c == ';') => {
+                    (cap, sgr.to_string())
+                }
+                _ => continue,
+            };
+
+            match cap {
+                "mt" => {
+                    self.selected_match = sgr.clone();
+                    self.context_match = sgr;
+                }
+                "ms" => self.selected_match = sgr,
+                "mc" => self.context_match = sgr,
+                "fn" => self.filename = sgr,
+                "ln" => self.line_number = sgr,
+                "bn" => self.byte_offset = sgr,
+                "se" => self.separator = sgr,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Wraps `text` in the given SGR sequence
+pub fn paint(sgr: &str, text: &str) -> String {
+    if sgr.is_empty() || text.is_empty() {
+        text.to_string()
+    } else {
+        format!("\x1b[{}m\x1b[K{}\x1b[m\x1b[K", sgr, text)
+    }
+}
+
+/// Paints every span of `text`, which needn't be UTF-8, with the given SGR
+/// sequence
+pub fn highlight(sgr: &str, text: &[u8], spans: &[(usize, usize)]) -> Vec<u8> {
+    if sgr.is_empty() || spans.is_empty() {
+        return text.to_vec();
+    }
+
+    let mut res = Vec::with_capacity(text.len());
+    let mut last = 0;
+    for &(start, end) in spans {
+        res.extend_from_slice(&text[last..start]);
+        if start < end {
+            res.extend_from_slice(format!("\x1b[{}m\x1b[K", sgr).as_bytes());
+            res.extend_from_slice(&text[start..end]);
+            res.extend_from_slice(b"\x1b[m\x1b[K");
+        }
+        last = end;
+    }
+    res.extend_from_slice(&text[last..]);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{highlight, paint, Colors};
+
+    #[test]
+    fn test_apply_spec() {
+        let mut colors = Colors::new();
+        colors.apply_spec("ms=04;32:fn=:ln=bogus:bn=1:xx=1:se");
+        assert_eq!(colors.selected_match, "04;32");
+        assert_eq!(colors.context_match, "01;31");
+        assert_eq!(colors.filename, "");
+        assert_eq!(colors.line_number, "32");
+        assert_eq!(colors.byte_offset, "1");
+        assert_eq!(colors.separator, "36");
+
+        colors.apply_spec("mt=7");
+        assert_eq!(colors.selected_match, "7");
+        assert_eq!(colors.context_match, "7");
+    }
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(paint("", "foo"), "foo");
+        assert_eq!(paint("35", "foo"), "\x1b[35m\x1b[Kfoo\x1b[m\x1b[K");
+
+        let text = b"foo bar foo";
+        assert_eq!(highlight("", text, &[(0, 3)]), text);
+        assert_eq!(
+            highlight("1", text, &[(0, 3), (8, 11)]),
+            b"\x1b[1m\x1b[Kfoo\x1b[m\x1b[K bar \x1b[1m\x1b[Kfoo\x1b[m\x1b[K"
+        );
+        assert_eq!(
+            highlight("1", b"\xff", &[(0, 1)]),
+            b"\x1b[1m\x1b[K\xff\x1b[m\x1b[K"
+        );
+    }
+}
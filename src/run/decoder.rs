@@ -0,0 +1,124 @@
+//! The transformations input goes through before it's searched, behind the
+//! `Decoder` trait, such as decompression and transcoding
+
+use std::fmt::Debug;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+
+/// Transforms input before it's searched. Decoders are shared by the
+/// threads searching files, so they have to be `Send` and `Sync`.
+pub trait Decoder: Debug + Send + Sync {
+    /// Whether to decode the input, going by the name of the file it came
+    /// from or by `head`, its first bytes. `path` is `None` for input that
+    /// isn't the file's own contents, such as a preprocessor's output.
+    fn applies(&self, path: Option<&Path>, head: &[u8]) -> bool;
+
+    /// Wraps `input` so that reading from it gives the decoded data
+    fn decode<'a>(
+        &self,
+        path: Option<&Path>,
+        input: Box<dyn BufRead + 'a>,
+    ) -> Result<Box<dyn BufRead + 'a>, String>;
+}
+
+/// Transcodes the input to UTF-8 from an encoding, or from UTF-16 if it
+/// starts with a BOM. Other input is passed through as it is.
+#[derive(Debug, Default)]
+pub struct Transcoder(pub Option<&'static Encoding>);
+
+impl Decoder for Transcoder {
+    fn applies(&self, _path: Option<&Path>, head: &[u8]) -> bool {
+        self.0.is_some() || Encoding::for_bom(head).is_some_and(|(e, _)| e != encoding_rs::UTF_8)
+    }
+
+    fn decode<'a>(
+        &self,
+        _path: Option<&Path>,
+        input: Box<dyn BufRead + 'a>,
+    ) -> Result<Box<dyn BufRead + 'a>, String> {
+        Ok(Box::new(BufReader::new(
+            DecodeReaderBytesBuilder::new()
+                .encoding(self.0)
+                .build(input),
+        )))
+    }
+}
+
+/// Runs the input through each of `decoders` in turn that applies to what
+/// the ones before it gave
+pub fn decode<'a>(
+    decoders: &[Arc<dyn Decoder>],
+    path: Option<&Path>,
+    mut input: Box<dyn BufRead + 'a>,
+) -> Result<Box<dyn BufRead + 'a>, String> {
+    for decoder in decoders {
+        // Input that can't be read is left for the search to report
+        if input
+            .fill_buf()
+            .is_ok_and(|head| decoder.applies(path, head))
+        {
+            input = decoder.decode(path, input)?;
+        }
+    }
+    Ok(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, Decoder, Transcoder};
+    use std::io::{BufRead, Read};
+    use std::path::Path;
+    use std::sync::Arc;
+
+    /// Swaps the case of ASCII letters in files named *.up
+    #[derive(Debug)]
+    struct SwapCase;
+
+    impl Decoder for SwapCase {
+        fn applies(&self, path: Option<&Path>, _head: &[u8]) -> bool {
+            path.and_then(Path::extension)
+                .is_some_and(|ext| ext == "up")
+        }
+
+        fn decode<'a>(
+            &self,
+            _path: Option<&Path>,
+            mut input: Box<dyn BufRead + 'a>,
+        ) -> Result<Box<dyn BufRead + 'a>, String> {
+            let mut buf = vec![];
+            input.read_to_end(&mut buf).map_err(|e| e.to_string())?;
+            buf.iter_mut()
+                .for_each(|b| *b ^= 0x20 * b.is_ascii_alphabetic() as u8);
+            Ok(Box::new(std::io::Cursor::new(buf)))
+        }
+    }
+
+    fn decoded(decoders: &[Arc<dyn Decoder>], path: Option<&Path>, input: &[u8]) -> String {
+        let mut res = String::new();
+        decode(decoders, path, Box::new(input))
+            .unwrap()
+            .read_to_string(&mut res)
+            .unwrap();
+        res
+    }
+
+    #[test]
+    fn test_decode() {
+        let decoders: [Arc<dyn Decoder>; 2] = [Arc::new(SwapCase), Arc::new(Transcoder(None))];
+        let path = Some(Path::new("a.up"));
+        assert_eq!(decoded(&decoders, path, b"Fox"), "fOX");
+        assert_eq!(decoded(&decoders, Some(Path::new("a.txt")), b"Fox"), "Fox");
+        assert_eq!(decoded(&decoders, None, b"Fox"), "Fox");
+
+        // UTF-16 is found by its BOM, after what comes before
+        assert_eq!(decoded(&decoders, None, b"\xff\xfeF\0o\0x\0"), "Fox");
+        assert_eq!(decoded(&decoders, path, b"\xff\xfef\0O\0X\0"), "Fox");
+
+        let latin1 = [Arc::new(Transcoder(encoding_rs::Encoding::for_label(b"latin1"))) as _];
+        assert_eq!(decoded(&latin1, None, b"caf\xe9"), "café");
+    }
+}
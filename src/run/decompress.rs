@@ -0,0 +1,113 @@
+//! Decompression of the files --search-zip looks inside. Each format is
+//! behind a feature of its own, as each brings in a crate.
+
+use std::io::BufRead;
+#[cfg(any(feature = "gzip", feature = "bzip2", feature = "xz", feature = "zstd"))]
+use std::io::BufReader;
+use std::path::Path;
+
+use super::Decoder;
+
+/// The compression formats recognized
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Format {
+    /// The format a file is compressed in, going by its extension
+    pub fn of(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()? {
+            "gz" | "tgz" => Some(Format::Gzip),
+            "bz2" | "tbz2" => Some(Format::Bzip2),
+            "xz" | "txz" => Some(Format::Xz),
+            "zst" | "zstd" => Some(Format::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Decompresses files named as in one of the formats, for --search-zip
+#[derive(Debug, Default)]
+pub struct Decompressor;
+
+impl Decoder for Decompressor {
+    fn applies(&self, path: Option<&Path>, _head: &[u8]) -> bool {
+        path.and_then(Format::of).is_some()
+    }
+
+    fn decode<'a>(
+        &self,
+        path: Option<&Path>,
+        input: Box<dyn BufRead + 'a>,
+    ) -> Result<Box<dyn BufRead + 'a>, String> {
+        match path.and_then(Format::of) {
+            Some(format) => decompress(format, input),
+            None => Ok(input),
+        }
+    }
+}
+
+/// Wraps `file` so that reading from it gives the decompressed data
+#[cfg_attr(
+    not(any(feature = "gzip", feature = "bzip2", feature = "xz", feature = "zstd")),
+    allow(unused_variables)
+)]
+fn decompress<'a>(
+    format: Format,
+    file: Box<dyn BufRead + 'a>,
+) -> Result<Box<dyn BufRead + 'a>, String> {
+    match format {
+        // Concatenated streams, as left by appending to a file, are read whole
+        #[cfg(feature = "gzip")]
+        Format::Gzip => Ok(Box::new(BufReader::new(
+            flate2::bufread::MultiGzDecoder::new(file),
+        ))),
+        #[cfg(feature = "bzip2")]
+        Format::Bzip2 => Ok(Box::new(BufReader::new(
+            bzip2::bufread::MultiBzDecoder::new(file),
+        ))),
+        #[cfg(feature = "xz")]
+        Format::Xz => Ok(Box::new(BufReader::new(
+            xz2::bufread::XzDecoder::new_multi_decoder(file),
+        ))),
+        #[cfg(feature = "zstd")]
+        Format::Zstd => match zstd::stream::read::Decoder::with_buffer(file) {
+            Ok(decoder) => Ok(Box::new(BufReader::new(decoder))),
+            Err(e) => Err(e.to_string()),
+        },
+        #[cfg(not(feature = "gzip"))]
+        Format::Gzip => Err(unsupported("gzip")),
+        #[cfg(not(feature = "bzip2"))]
+        Format::Bzip2 => Err(unsupported("bzip2")),
+        #[cfg(not(feature = "xz"))]
+        Format::Xz => Err(unsupported("xz")),
+        #[cfg(not(feature = "zstd"))]
+        Format::Zstd => Err(unsupported("zstd")),
+    }
+}
+
+#[cfg(not(all(feature = "gzip", feature = "bzip2", feature = "xz", feature = "zstd")))]
+fn unsupported(feature: &str) -> String {
+    format!("grepr was built without the \"{}\" feature", feature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Format;
+    use std::path::Path;
+
+    #[test]
+    fn test_format_of() {
+        assert_eq!(Format::of(Path::new("app.log.gz")), Some(Format::Gzip));
+        assert_eq!(Format::of(Path::new("src.tgz")), Some(Format::Gzip));
+        assert_eq!(Format::of(Path::new("dump.bz2")), Some(Format::Bzip2));
+        assert_eq!(Format::of(Path::new("a/b.xz")), Some(Format::Xz));
+        assert_eq!(Format::of(Path::new("data.zst")), Some(Format::Zstd));
+        assert_eq!(Format::of(Path::new("notes.txt")), None);
+        assert_eq!(Format::of(Path::new("gz")), None);
+    }
+}
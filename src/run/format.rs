@@ -0,0 +1,160 @@
+//! Templates for --format, which lay out each match as the user asks, such
+//! as `{path}:{line}: {match}`
+
+/// One part of a template
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Literal(String),
+    Path,
+    Line,
+    Column,
+    Text,
+    Match,
+    /// The index of a capture group in `Template::groups`
+    Group(usize),
+}
+
+/// A parsed --format template
+#[derive(Debug, Clone)]
+pub struct Template {
+    pieces: Vec<Piece>,
+    /// The capture groups referred to, by number or name
+    groups: Vec<String>,
+}
+
+/// What a template is filled in with, for one match
+#[derive(Debug)]
+pub struct Fields<'a> {
+    pub path: &'a str,
+    pub line: usize,
+    /// The 1-based column of the match, if the line has one
+    pub column: Option<usize>,
+    /// The whole line, without its terminator
+    pub text: &'a str,
+    pub matched: &'a str,
+    /// The text of each group in `Template::groups`
+    pub groups: &'a [String],
+}
+
+impl Template {
+    /// Parses a template. `{{` and `}}` stand for literal braces, and any
+    /// placeholder other than the fields is a capture group.
+    pub fn parse(template: &str) -> Result<Template, String> {
+        let invalid = || format!("Invalid format \"{}\"", template);
+        let mut pieces = vec![];
+        let mut groups: Vec<String> = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.next_if_eq(&'{').is_some() => literal.push('{'),
+                '}' if chars.next_if_eq(&'}').is_some() => literal.push('}'),
+                '}' => return Err(invalid()),
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                    if !closed || name.is_empty() || !valid {
+                        return Err(invalid());
+                    }
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(match name.as_str() {
+                        "path" => Piece::Path,
+                        "line" => Piece::Line,
+                        "column" => Piece::Column,
+                        "text" => Piece::Text,
+                        "match" => Piece::Match,
+                        _ => match groups.iter().position(|g| *g == name) {
+                            Some(i) => Piece::Group(i),
+                            None => {
+                                groups.push(name);
+                                Piece::Group(groups.len() - 1)
+                            }
+                        },
+                    });
+                }
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+
+        Ok(Template { pieces, groups })
+    }
+
+    /// The capture groups the template refers to
+    pub fn groups(&self) -> &[String] {
+        &self.groups
+    }
+
+    /// Fills in the template for one match
+    pub fn render(&self, fields: &Fields) -> String {
+        let mut res = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Literal(text) => res.push_str(text),
+                Piece::Path => res.push_str(fields.path),
+                Piece::Line => res.push_str(&fields.line.to_string()),
+                Piece::Column => {
+                    if let Some(column) = fields.column {
+                        res.push_str(&column.to_string());
+                    }
+                }
+                Piece::Text => res.push_str(fields.text),
+                Piece::Match => res.push_str(fields.matched),
+                Piece::Group(i) => res.push_str(&fields.groups[*i]),
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fields, Template};
+
+    #[test]
+    fn test_parse() {
+        let template = Template::parse("{path}:{1}-{name}:{1}").unwrap();
+        assert_eq!(template.groups(), ["1", "name"]);
+
+        for bad in ["{", "{path", "}", "{}", "{a b}", "x{path}}y"] {
+            assert_eq!(
+                Template::parse(bad).unwrap_err(),
+                format!("Invalid format \"{}\"", bad)
+            );
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let fields = Fields {
+            path: "src/main.rs",
+            line: 12,
+            column: Some(5),
+            text: "let x = 42;",
+            matched: "x = 42",
+            groups: &["x".to_string(), "42".to_string()],
+        };
+        let render = |template| Template::parse(template).unwrap().render(&fields);
+
+        assert_eq!(
+            render("{path}:{line}:{column}: {text}"),
+            "src/main.rs:12:5: let x = 42;"
+        );
+        assert_eq!(render("{{{match}}}"), "{x = 42}");
+        assert_eq!(render("{1}:{2} {1}"), "x:42 x");
+        assert_eq!(render("{var}={val}"), "x=42");
+    }
+}
@@ -0,0 +1,132 @@
+//! Hyperlinks from the file names in the output, for --hyperlink-format.
+//! Terminals that understand OSC 8 escapes make them clickable.
+
+use std::fmt::Write;
+use std::path::Path;
+
+/// The named formats, which save spelling out the common templates
+const NAMED_FORMATS: &[(&str, &str)] = &[
+    ("default", "file://{path}"),
+    ("file", "file://{path}"),
+    ("vscode", "vscode://file{path}:{line}:{column}"),
+    ("vscodium", "vscodium://file{path}:{line}:{column}"),
+    ("cursor", "cursor://file{path}:{line}:{column}"),
+];
+
+/// A URL template with `{path}`, `{line}` and `{column}` placeholders
+#[derive(Debug, Clone)]
+pub struct HyperlinkFormat {
+    template: String,
+}
+
+impl HyperlinkFormat {
+    /// Parses one of the named formats or a template, which has to refer
+    /// to the path. `none` turns hyperlinks off.
+    pub fn new(format: &str) -> Result<Option<HyperlinkFormat>, String> {
+        if format == "none" {
+            return Ok(None);
+        }
+        let template = NAMED_FORMATS
+            .iter()
+            .find(|(name, _)| *name == format)
+            .map_or(format, |(_, template)| template);
+
+        let invalid = || format!("Invalid hyperlink format \"{}\"", format);
+        let mut rest = template;
+        let mut has_path = false;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}').ok_or_else(invalid)? + start;
+            match &rest[start + 1..end] {
+                "path" => has_path = true,
+                "line" | "column" => {}
+                _ => return Err(invalid()),
+            }
+            rest = &rest[end + 1..];
+        }
+        if !has_path {
+            return Err(invalid());
+        }
+
+        Ok(Some(HyperlinkFormat {
+            template: template.to_string(),
+        }))
+    }
+
+    /// The URL of an absolute path, at a line and column if there is one
+    pub fn url(&self, path: &Path, position: Option<(usize, usize)>) -> String {
+        let (line, column) = position.unwrap_or((1, 1));
+        self.template
+            .replace("{path}", &encode_path(path))
+            .replace("{line}", &line.to_string())
+            .replace("{column}", &column.to_string())
+    }
+}
+
+/// Wraps `text` in the OSC 8 escapes that link it to `url`
+pub fn wrap(url: &str, text: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
+/// Percent-encodes a path for a URL, starting it with a slash as Windows
+/// paths don't
+fn encode_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let mut res = String::with_capacity(path.len() + 1);
+    if !path.starts_with('/') {
+        res.push('/');
+    }
+    for b in path.bytes() {
+        match b {
+            b'\\' => res.push('/'),
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                res.push(b as char)
+            }
+            _ => write!(res, "%{:02X}", b).unwrap(),
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{wrap, HyperlinkFormat};
+    use std::path::Path;
+
+    #[test]
+    fn test_url() {
+        let path = Path::new("/home/me/my notes.txt");
+        let file = HyperlinkFormat::new("default").unwrap().unwrap();
+        assert_eq!(
+            file.url(path, Some((3, 7))),
+            "file:///home/me/my%20notes.txt"
+        );
+
+        let vscode = HyperlinkFormat::new("vscode").unwrap().unwrap();
+        assert_eq!(
+            vscode.url(path, Some((3, 7))),
+            "vscode://file/home/me/my%20notes.txt:3:7"
+        );
+        assert_eq!(
+            vscode.url(path, None),
+            "vscode://file/home/me/my%20notes.txt:1:1"
+        );
+
+        let custom = HyperlinkFormat::new("x://{path}#L{line}").unwrap().unwrap();
+        assert_eq!(custom.url(Path::new("/a/b"), Some((9, 1))), "x:///a/b#L9");
+    }
+
+    #[test]
+    fn test_new() {
+        assert!(HyperlinkFormat::new("none").unwrap().is_none());
+        for bad in ["file://", "x://{path}:{row}", "x://{path", "nope"] {
+            assert_eq!(
+                HyperlinkFormat::new(bad).unwrap_err(),
+                format!("Invalid hyperlink format \"{}\"", bad)
+            );
+        }
+        assert_eq!(
+            wrap("file:///a", "a"),
+            "\x1b]8;;file:///a\x1b\\a\x1b]8;;\x1b\\"
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! Messages in ripgrep's JSON Lines format, for --json. Each function
+//! returns one message without its trailing newline.
+
+use std::borrow::Cow;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::stats::elapsed;
+use crate::SearchStats;
+
+/// A message as ripgrep lays it out, with its kind beside its data
+#[derive(Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+enum Message<'a> {
+    Begin {
+        path: Data<'a>,
+    },
+    Match(LineData<'a>),
+    Context(LineData<'a>),
+    End {
+        path: Data<'a>,
+        binary_offset: Option<u64>,
+        stats: &'a SearchStats,
+    },
+    Summary {
+        #[serde(with = "elapsed")]
+        elapsed_total: Duration,
+        stats: &'a SearchStats,
+    },
+}
+
+/// Arbitrary data is wrapped in an object, which ripgrep uses to tell text
+/// apart from base64-encoded bytes
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Data<'a> {
+    Text { text: Cow<'a, str> },
+    Bytes { bytes: String },
+}
+
+impl<'a> Data<'a> {
+    /// Text if `bytes` are UTF-8, so that they can be shown as they are
+    fn new(bytes: &'a [u8]) -> Data<'a> {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Data::Text {
+                text: Cow::Borrowed(text),
+            },
+            Err(_) => Data::Bytes {
+                bytes: base64(bytes),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LineData<'a> {
+    path: Data<'a>,
+    lines: Data<'a>,
+    line_number: usize,
+    absolute_offset: usize,
+    submatches: Vec<Submatch<'a>>,
+}
+
+#[derive(Serialize)]
+struct Submatch<'a> {
+    #[serde(rename = "match")]
+    matched: Data<'a>,
+    start: usize,
+    end: usize,
+}
+
+/// Starts the results for one file
+pub fn begin(path: &[u8]) -> String {
+    to_string(&Message::Begin {
+        path: Data::new(path),
+    })
+}
+
+/// A matching line, or with `is_match` false a line of context. `spans`
+/// are byte ranges within `text`, which includes the line terminator.
+pub fn line(
+    path: &[u8],
+    text: &[u8],
+    is_match: bool,
+    line_number: usize,
+    offset: usize,
+    spans: &[(usize, usize)],
+) -> String {
+    let submatches = spans
+        .iter()
+        .map(|&(start, end)| Submatch {
+            matched: Data::new(&text[start..end]),
+            start,
+            end,
+        })
+        .collect();
+    let data = LineData {
+        path: Data::new(path),
+        lines: Data::new(text),
+        line_number,
+        absolute_offset: offset,
+        submatches,
+    };
+    to_string(&if is_match {
+        Message::Match(data)
+    } else {
+        Message::Context(data)
+    })
+}
+
+/// Finishes the results for one file
+pub fn end(path: &[u8], stats: &SearchStats) -> String {
+    to_string(&Message::End {
+        path: Data::new(path),
+        binary_offset: None,
+        stats,
+    })
+}
+
+/// The totals over every file, sent last
+pub fn summary(stats: &SearchStats) -> String {
+    to_string(&Message::Summary {
+        elapsed_total: stats.elapsed,
+        stats,
+    })
+}
+
+fn to_string(message: &Message) -> String {
+    // Nothing in a message can fail to serialize
+    serde_json::to_string(message).unwrap()
+}
+
+/// Standard base64 with padding, as ripgrep encodes bytes
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0, |group, (i, &byte)| {
+            group | u32::from(byte) << (16 - 8 * i)
+        });
+        // Each byte in the chunk fills at least one more character
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base64, begin, end, line, summary};
+    use crate::SearchStats;
+    use std::time::Duration;
+
+    #[test]
+    fn test_messages() {
+        assert_eq!(
+            begin(b"a.txt"),
+            r#"{"type":"begin","data":{"path":{"text":"a.txt"}}}"#
+        );
+        assert_eq!(
+            begin(b"a \"b\"\t\0.txt"),
+            r#"{"type":"begin","data":{"path":{"text":"a \"b\"\t\u0000.txt"}}}"#
+        );
+        assert_eq!(
+            line(b"a.txt", b"the fox\n", true, 3, 40, &[(4, 7)]),
+            concat!(
+                r#"{"type":"match","data":{"path":{"text":"a.txt"},"lines":{"text":"the fox\n"},"#,
+                r#""line_number":3,"absolute_offset":40,"#,
+                r#""submatches":[{"match":{"text":"fox"},"start":4,"end":7}]}}"#
+            )
+        );
+        assert!(line(b"a.txt", b"dog\n", false, 4, 48, &[]).starts_with(r#"{"type":"context""#));
+        // Bytes that aren't UTF-8 are encoded, whether in the line, a match
+        // or the path, so offsets still line up with them
+        assert_eq!(
+            line(b"a\xff.txt", b"a\xffb fox\n", true, 1, 0, &[(1, 3), (4, 7)]),
+            concat!(
+                r#"{"type":"match","data":{"path":{"bytes":"Yf8udHh0"},"#,
+                r#""lines":{"bytes":"Yf9iIGZveAo="},"line_number":1,"absolute_offset":0,"#,
+                r#""submatches":[{"match":{"bytes":"/2I="},"start":1,"end":3},"#,
+                r#"{"match":{"text":"fox"},"start":4,"end":7}]}}"#
+            )
+        );
+
+        let stats = SearchStats {
+            elapsed: Duration::from_millis(1500),
+            files_searched: 1,
+            matches: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            end(b"a.txt", &stats),
+            concat!(
+                r#"{"type":"end","data":{"path":{"text":"a.txt"},"binary_offset":null,"#,
+                r#""stats":{"elapsed":{"secs":1,"nanos":500000000,"human":"1.500000s"},"#,
+                r#""searches":1,"searches_with_match":0,"bytes_searched":0,"bytes_printed":0,"#,
+                r#""matched_lines":0,"matches":2}}}"#
+            )
+        );
+        assert!(summary(&stats).starts_with(
+            r#"{"type":"summary","data":{"elapsed_total":{"secs":1,"nanos":500000000,"#
+        ));
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+        assert_eq!(base64(b"\xff\xfe\xfd"), "//79");
+    }
+}
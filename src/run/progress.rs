@@ -0,0 +1,88 @@
+//! Hooks for following a search over many files, and the meter --progress
+//! draws with them
+
+use std::fmt::Debug;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Told how a run is going as it goes, so that a front end can show its
+/// progress. Every hook does nothing by default.
+pub trait Progress: Debug + Send + Sync {
+    /// The walk has found this many files to search so far. Files are
+    /// searched while it goes on, so the count grows until it's over.
+    fn files_found(&self, _count: usize) {}
+
+    /// A file has been searched, reading `bytes` and selecting `matches` lines
+    fn file_searched(&self, _path: &str, _bytes: u64, _matches: usize) {}
+
+    /// The run is over
+    fn finished(&self) {}
+}
+
+/// How often the meter is redrawn at most
+const REDRAW: Duration = Duration::from_millis(100);
+
+/// Draws --progress on stderr, rewriting the same line as files are searched
+#[derive(Debug, Default)]
+pub struct Meter {
+    counts: Mutex<Counts>,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    found: usize,
+    searched: usize,
+    bytes: u64,
+    matches: usize,
+    drawn: Option<Instant>,
+}
+
+impl Counts {
+    fn line(&self) -> String {
+        format!(
+            "\r{}/{} files searched, {} bytes, {} matched lines",
+            self.searched, self.found, self.bytes, self.matches
+        )
+    }
+}
+
+impl Progress for Meter {
+    fn files_found(&self, count: usize) {
+        self.counts.lock().unwrap().found = count;
+    }
+
+    fn file_searched(&self, _path: &str, bytes: u64, matches: usize) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.searched += 1;
+        counts.bytes += bytes;
+        counts.matches += matches;
+        if counts.drawn.is_none_or(|drawn| drawn.elapsed() >= REDRAW) {
+            counts.drawn = Some(Instant::now());
+            let _ = io::stderr().write_all(counts.line().as_bytes());
+        }
+    }
+
+    fn finished(&self) {
+        let counts = self.counts.lock().unwrap();
+        let _ = writeln!(io::stderr(), "{}", counts.line());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Meter, Progress};
+
+    #[test]
+    fn test_meter() {
+        let meter = Meter::default();
+        meter.files_found(2);
+        meter.file_searched("a", 10, 1);
+        meter.file_searched("b", 5, 0);
+        let counts = meter.counts.lock().unwrap();
+        assert_eq!(
+            counts.line(),
+            "\r2/2 files searched, 15 bytes, 1 matched lines"
+        );
+    }
+}
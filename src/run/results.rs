@@ -0,0 +1,272 @@
+//! Searching as the command would, but handing back what each file held
+//! instead of printing it
+
+use std::cell::Cell;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use super::{Config, Counted, Searcher};
+use crate::budget::{Allowance, Budget};
+use crate::{search_sink, GreprError, Line, SearchStats};
+
+/// What the search of one file found. Serialized, its error is only a
+/// message.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileResult {
+    pub path: PathBuf,
+    /// The selected lines and their context, in order
+    pub lines: Vec<Line>,
+    /// How many lines were selected, or with `count_matches` how many
+    /// matches they held
+    pub count: usize,
+    pub stats: SearchStats,
+    /// Why the file couldn't be searched, or not to the end. Errors found
+    /// while walking a directory name the path at fault if they can.
+    #[cfg_attr(feature = "serde", serde(with = "error_message"))]
+    pub error: Option<GreprError>,
+}
+
+/// Serializes an error as its message, which is all that can be read back
+#[cfg(feature = "serde")]
+mod error_message {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::GreprError;
+
+    pub fn serialize<S: Serializer>(
+        error: &Option<GreprError>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        error
+            .as_ref()
+            .map(ToString::to_string)
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<GreprError>, D::Error> {
+        let message = Option::<String>::deserialize(deserializer)?;
+        Ok(message.map(|message| GreprError::Other(message.into())))
+    }
+}
+
+impl FileResult {
+    fn new(path: PathBuf) -> FileResult {
+        FileResult {
+            path,
+            lines: vec![],
+            count: 0,
+            stats: SearchStats::default(),
+            error: None,
+        }
+    }
+}
+
+/// Searches the configured files as `run` would, yielding each one's
+/// results in the order the files were found. Files are found up front,
+/// but each is only searched as it's reached.
+pub fn search(config: &Config) -> Search<'_> {
+    let started = Instant::now();
+    let (files, skipped) = config.find_files();
+    Search {
+        searcher: Searcher {
+            config,
+            search: config.search,
+            show_filename: false,
+            heading: false,
+            with_context: true,
+            memory: config.max_memory.map(Budget::new),
+        },
+        files: files.into_iter(),
+        stats: SearchStats {
+            files_skipped: skipped,
+            ..Default::default()
+        },
+        started,
+    }
+}
+
+/// The results of `search`, file by file, which add up to its totals
+pub struct Search<'a> {
+    searcher: Searcher<'a>,
+    files: std::vec::IntoIter<Result<PathBuf, GreprError>>,
+    stats: SearchStats,
+    started: Instant,
+}
+
+impl Search<'_> {
+    /// The totals over the files yielded so far, timed from the start of
+    /// the search
+    pub fn stats(&self) -> SearchStats {
+        SearchStats {
+            elapsed: self.started.elapsed(),
+            ..self.stats
+        }
+    }
+}
+
+impl Iterator for Search<'_> {
+    type Item = FileResult;
+
+    fn next(&mut self) -> Option<FileResult> {
+        let res = match self.files.next()? {
+            Ok(path) => self.searcher.file_result(path),
+            Err(e) => {
+                let path = e.path().map(PathBuf::from).unwrap_or_default();
+                FileResult {
+                    error: Some(e),
+                    ..FileResult::new(path)
+                }
+            }
+        };
+        self.stats.add(&res.stats);
+        Some(res)
+    }
+}
+
+impl Searcher<'_> {
+    fn file_result(&self, path: PathBuf) -> FileResult {
+        let started = Instant::now();
+        let mut res = FileResult::new(path);
+        let (pattern, search) = (&*self.config.pattern, &self.search);
+        let bytes_searched = Cell::new(0);
+        let budget = self.memory.as_ref().map(Allowance::new);
+        let searched = self.with_input(&res.path, |file| {
+            let file = Counted {
+                inner: file,
+                bytes: &bytes_searched,
+            };
+            search_sink(
+                &res.path,
+                file,
+                pattern,
+                search,
+                budget.as_ref(),
+                &mut res.lines,
+            )
+        });
+        res.error = match searched {
+            Ok(Ok(())) => None,
+            // The file was never opened, so there's nothing to count
+            Ok(Err(warning)) => {
+                return FileResult {
+                    error: Some(GreprError::Other(warning.into())),
+                    ..res
+                }
+            }
+            Err(e) => Some(e),
+        };
+
+        let stats = &mut res.stats;
+        for line in &res.lines {
+            stats.add_line(line, self.config.search.invert_match);
+        }
+        stats.files_searched = 1;
+        stats.files_matched = usize::from(stats.matched_lines > 0);
+        stats.bytes_searched = bytes_searched.get();
+        stats.elapsed = started.elapsed();
+        res.count = if self.config.count_matches {
+            stats.matches
+        } else {
+            stats.matched_lines
+        };
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::search;
+    use crate::{Config, MemoryFs, SearchOptions, WalkOptions};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_search() {
+        let search_options = SearchOptions {
+            after_context: 1,
+            ..Default::default()
+        };
+        let config = Config::builder()
+            .pattern("o")
+            .file("tests/inputs/fox.txt")
+            .file("tests/inputs/empty.txt")
+            .file("tests/inputs/nonexistent.txt")
+            .search_options(search_options)
+            .count_matches(true)
+            .build()
+            .unwrap();
+        let mut files = search(&config);
+        let results: Vec<_> = files.by_ref().collect();
+        assert_eq!(results.len(), 3);
+
+        let fox = &results[0];
+        assert_eq!(fox.path.to_str(), Some("tests/inputs/fox.txt"));
+        assert_eq!(fox.lines.len(), 1);
+        assert_eq!(fox.count, 4);
+        assert_eq!(fox.stats.bytes_searched, 45);
+        assert!(fox.error.is_none());
+
+        assert!(results[1].lines.is_empty() && results[1].error.is_none());
+
+        let missing = &results[2];
+        assert_eq!(missing.path.to_str(), Some("tests/inputs/nonexistent.txt"));
+        assert!(missing.error.is_some());
+
+        // The totals are those of the files that could be searched
+        let stats = files.stats();
+        assert_eq!(stats.files_searched, 2);
+        assert_eq!(stats.files_matched, 1);
+        assert_eq!(stats.matches, 4);
+        assert_eq!(stats.bytes_searched, 45);
+        assert!(stats.elapsed >= fox.stats.elapsed);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use super::FileResult;
+
+        let config = Config::builder()
+            .pattern("fox")
+            .file("tests/inputs/fox.txt")
+            .file("tests/inputs/nonexistent.txt")
+            .build()
+            .unwrap();
+        for result in search(&config) {
+            let json = serde_json::to_string(&result).unwrap();
+            let read: FileResult = serde_json::from_str(&json).unwrap();
+            assert_eq!(read.path, result.path);
+            assert_eq!(read.lines, result.lines);
+            assert_eq!(read.stats, result.stats);
+            // Only the message of an error survives
+            assert_eq!(
+                read.error.map(|e| e.to_string()),
+                result.error.map(|e| e.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_search_vfs() {
+        let mut vfs = MemoryFs::new();
+        vfs.insert("src/a.rs", "fn fox() {}\n");
+        vfs.insert("src/b.rs", "fn dog() {}\n");
+        let config = Config::builder()
+            .pattern("fox")
+            .file("src")
+            .walk_options(WalkOptions {
+                recursive: true,
+                ..Default::default()
+            })
+            .vfs(Arc::new(vfs))
+            .build()
+            .unwrap();
+        let results: Vec<_> = search(&config).collect();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].path.to_str(), Some("src/a.rs"));
+        assert_eq!(results[0].lines[0].text, b"fn fox() {}\n");
+        assert!(results[1].lines.is_empty());
+    }
+}
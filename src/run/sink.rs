@@ -0,0 +1,382 @@
+//! The sinks the command prints its results through, one for each way of
+//! reporting on a file
+
+#[cfg(feature = "serde")]
+use std::cell::Cell;
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "serde")]
+use std::time::Instant;
+
+use super::color::{highlight, paint};
+#[cfg(feature = "serde")]
+use super::json;
+use super::{limit_columns, replace_spans, trim_start, SearchStats, Searcher, Unique};
+use crate::{GreprError, Line, Sink};
+
+/// Prints lines as grep does, with their prefixes, colors and separators
+pub struct StandardSink<'a> {
+    searcher: &'a Searcher<'a>,
+    out: &'a mut dyn io::Write,
+    stats: &'a mut SearchStats,
+    /// Whether lines from an earlier file came first, asked once this
+    /// file's first line is printed
+    printed_before: &'a dyn Fn() -> bool,
+    path: PathBuf,
+    /// The number of the last line printed from this file
+    last_printed: Option<usize>,
+    unique: Unique<'a>,
+    match_sep: String,
+    context_sep: String,
+}
+
+impl<'a> StandardSink<'a> {
+    pub fn new(
+        searcher: &'a Searcher<'a>,
+        out: &'a mut dyn io::Write,
+        stats: &'a mut SearchStats,
+        printed_before: &'a dyn Fn() -> bool,
+    ) -> StandardSink<'a> {
+        let config = searcher.config;
+        // As in grep, context lines are told apart by their separators
+        let match_sep = paint(&config.colors.separator, &config.field_match_separator);
+        let context_sep = paint(&config.colors.separator, "-");
+        StandardSink {
+            searcher,
+            out,
+            stats,
+            printed_before,
+            path: PathBuf::new(),
+            last_printed: None,
+            unique: Unique::new(searcher.memory.as_ref()),
+            match_sep,
+            context_sep,
+        }
+    }
+
+    /// Whether any lines were printed
+    pub fn printed_lines(&self) -> bool {
+        self.last_printed.is_some()
+    }
+
+    fn line(&mut self, line: Line) -> Result<(), GreprError> {
+        let searcher = self.searcher;
+        let config = searcher.config;
+        let search = &searcher.search;
+        let colors = &config.colors;
+        let path = self.path.as_path();
+        let out = &mut *self.out;
+        let unique = &mut self.unique;
+        let record_end: &[u8] = if config.search.null_data {
+            b"\0"
+        } else {
+            b"\n"
+        };
+
+        let first_line = self.last_printed.is_none();
+        if first_line && (self.printed_before)() {
+            if let Some(separator) = searcher.file_separator() {
+                write!(out, "{}", separator)?;
+            }
+        }
+        if searcher.heading && first_line {
+            let terminator = if config.null { "\0" } else { "\n" };
+            write!(out, "{}{}", searcher.filename(path, None), terminator)?;
+        }
+        let contiguous = self
+            .last_printed
+            .is_some_and(|last| line.number == last + 1);
+        if searcher.with_context && !first_line && !contiguous {
+            if let Some(separator) = searcher.group_separator() {
+                write!(out, "{}", separator)?;
+            }
+        }
+        self.last_printed = Some(line.number);
+        self.stats.add_line(&line, config.search.invert_match);
+
+        if let Some(template) = &config.format {
+            let name = searcher.name(path);
+            for record in searcher.format_line(template, &name, &line) {
+                searcher.emit(out, unique, "", &[record.as_bytes(), record_end].concat())?;
+            }
+            return Ok(());
+        }
+
+        let sep = if line.is_match {
+            &self.match_sep
+        } else {
+            &self.context_sep
+        };
+        let column = line.spans.first().map_or(1, |&(start, _)| start + 1);
+        let mut line_prefix = searcher.prefix(path, sep, Some((line.number, column)));
+        if config.line_number {
+            let number = line.number.to_string();
+            write!(
+                line_prefix,
+                "{}{}",
+                paint(&colors.line_number, &number),
+                sep
+            )
+            .unwrap();
+        }
+
+        let match_color = if line.is_match {
+            &colors.selected_match
+        } else {
+            &colors.context_match
+        };
+
+        // Columns are 1-based and only exist for lines with a match
+        let position_prefix = |column: Option<usize>, offset: usize| {
+            let mut res = String::new();
+            if let (true, Some(column)) = (config.column, column) {
+                let column = (column + 1).to_string();
+                res += &paint(&colors.line_number, &column);
+                res += sep;
+            }
+            if config.byte_offset {
+                res += &paint(&colors.byte_offset, &offset.to_string());
+                res += sep;
+            }
+            res
+        };
+
+        // With -T the text lines up at the tab stop after any prefix
+        let tabbed = |mut prefix: String| {
+            if config.initial_tab && !prefix.is_empty() {
+                prefix.push('\t');
+            }
+            prefix
+        };
+
+        // Long lines are cut short, keeping whatever terminates them
+        let limit = |text: &[u8], spans: &[(usize, usize)]| {
+            let content = search.content(text).len();
+            let short = config.max_columns.and_then(|max| {
+                limit_columns(&text[..content], spans, max, config.max_columns_preview)
+            });
+            match short {
+                Some((short, spans)) => {
+                    [&highlight(match_color, &short, &spans), &text[content..]].concat()
+                }
+                None => highlight(match_color, text, spans),
+            }
+        };
+
+        // With --replace each match gives way to its expanded template
+        let replacements = match &config.replace {
+            Some(template) if !line.spans.is_empty() => Some(
+                config
+                    .pattern
+                    .replacements(search.content(&line.text), template),
+            ),
+            _ => None,
+        };
+
+        // With -o the position is that of each match rather than the line
+        if config.only_matching {
+            let matches = replacements.unwrap_or_else(|| {
+                let text = &line.text;
+                line.spans
+                    .iter()
+                    .map(|&(start, end)| ((start, end), text[start..end].to_vec()))
+                    .collect()
+            });
+            for ((start, _), text) in matches.iter().filter(|((s, e), _)| s != e) {
+                let text = limit(text, &[(0, text.len())]);
+                let position = position_prefix(Some(*start), line.offset + start);
+                let prefix = tabbed(line_prefix.clone() + &position);
+                searcher.emit(out, unique, &prefix, &[&text, record_end].concat())?;
+            }
+        } else {
+            let replaced = replacements.map(|r| replace_spans(&line.text, r));
+            let (text, spans) = match &replaced {
+                Some((text, spans)) => (text.as_slice(), spans.as_slice()),
+                None => (line.text.as_slice(), line.spans.as_slice()),
+            };
+            let text = if config.trim {
+                let (text, spans) = trim_start(text, spans);
+                limit(text, &spans)
+            } else {
+                limit(text, spans)
+            };
+            let columns: Vec<_> = if config.vimgrep && !line.spans.is_empty() {
+                line.spans.iter().map(|&(start, _)| Some(start)).collect()
+            } else {
+                vec![line.spans.first().map(|&(start, _)| start)]
+            };
+            for column in columns {
+                let position = position_prefix(column, line.offset);
+                let prefix = tabbed(line_prefix.clone() + &position);
+                searcher.emit(out, unique, &prefix, &text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Sink for StandardSink<'_> {
+    fn begin(&mut self, path: &Path) -> Result<(), GreprError> {
+        self.path = path.to_path_buf();
+        Ok(())
+    }
+
+    fn matched(&mut self, line: Line) -> Result<(), GreprError> {
+        self.line(line)
+    }
+
+    fn context(&mut self, line: Line) -> Result<(), GreprError> {
+        self.line(line)
+    }
+
+    /// With --unique --count the distinct lines wait for the end of the file
+    fn end(&mut self, _path: &Path) -> Result<(), GreprError> {
+        if self.searcher.config.unique_count {
+            self.unique.print_counts(self.out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints a message for each line in ripgrep's JSON Lines format, for --json
+#[cfg(feature = "serde")]
+pub struct JsonSink<'a> {
+    searcher: &'a Searcher<'a>,
+    out: &'a mut dyn io::Write,
+    stats: &'a mut SearchStats,
+    /// The bytes read from the file so far, for the end message
+    bytes_searched: &'a Cell<u64>,
+    name: Vec<u8>,
+    started: Instant,
+    /// Whether the begin message has been printed
+    begun: bool,
+}
+
+#[cfg(feature = "serde")]
+impl<'a> JsonSink<'a> {
+    pub fn new(
+        searcher: &'a Searcher<'a>,
+        out: &'a mut dyn io::Write,
+        stats: &'a mut SearchStats,
+        bytes_searched: &'a Cell<u64>,
+    ) -> JsonSink<'a> {
+        JsonSink {
+            searcher,
+            out,
+            stats,
+            bytes_searched,
+            name: Vec::new(),
+            started: Instant::now(),
+            begun: false,
+        }
+    }
+
+    fn print_message(&mut self, message: String) -> io::Result<()> {
+        writeln!(self.out, "{}", message)?;
+        self.stats.bytes_printed += message.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn line(&mut self, line: Line) -> Result<(), GreprError> {
+        // Files without matches aren't mentioned at all
+        if !self.begun {
+            self.print_message(json::begin(&self.name))?;
+            self.begun = true;
+        }
+        let invert_match = self.searcher.config.search.invert_match;
+        self.stats.add_line(&line, invert_match);
+        let message = json::line(
+            &self.name,
+            &line.text,
+            line.is_match,
+            line.number,
+            line.offset,
+            &line.spans,
+        );
+        Ok(self.print_message(message)?)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Sink for JsonSink<'_> {
+    fn begin(&mut self, path: &Path) -> Result<(), GreprError> {
+        self.name = self.searcher.name_bytes(path).into_owned();
+        self.started = Instant::now();
+        Ok(())
+    }
+
+    fn matched(&mut self, line: Line) -> Result<(), GreprError> {
+        self.line(line)
+    }
+
+    fn context(&mut self, line: Line) -> Result<(), GreprError> {
+        self.line(line)
+    }
+
+    fn end(&mut self, _path: &Path) -> Result<(), GreprError> {
+        if self.begun {
+            // The end message reports on the file as a whole
+            let file_stats = SearchStats {
+                elapsed: self.started.elapsed(),
+                files_searched: 1,
+                files_matched: usize::from(self.stats.matched_lines > 0),
+                bytes_searched: self.bytes_searched.get(),
+                ..*self.stats
+            };
+            let message = json::end(&self.name, &file_stats);
+            self.print_message(message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints how many lines, or with --count-matches matches, were selected
+pub struct CountSink<'a> {
+    searcher: &'a Searcher<'a>,
+    out: &'a mut dyn io::Write,
+    stats: &'a mut SearchStats,
+    count: usize,
+}
+
+impl<'a> CountSink<'a> {
+    pub fn new(
+        searcher: &'a Searcher<'a>,
+        out: &'a mut dyn io::Write,
+        stats: &'a mut SearchStats,
+    ) -> CountSink<'a> {
+        CountSink {
+            searcher,
+            out,
+            stats,
+            count: 0,
+        }
+    }
+
+    /// The number printed
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl Sink for CountSink<'_> {
+    fn matched(&mut self, line: Line) -> Result<(), GreprError> {
+        let config = self.searcher.config;
+        self.stats.add_line(&line, config.search.invert_match);
+        // Inverted lines have no matches of their own, so each counts once
+        self.count += if config.count_matches && !config.search.invert_match {
+            line.spans.len()
+        } else {
+            1
+        };
+        Ok(())
+    }
+
+    fn end(&mut self, path: &Path) -> Result<(), GreprError> {
+        let config = self.searcher.config;
+        let sep = paint(&config.colors.separator, &config.field_match_separator);
+        let prefix = self.searcher.prefix(path, &sep, None);
+        writeln!(self.out, "{}{}", prefix, self.count)?;
+        Ok(())
+    }
+}
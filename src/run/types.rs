@@ -0,0 +1,140 @@
+//! Named groups of file name globs, such as `rust` for `*.rs`, for
+//! restricting a recursive search to one kind of file
+
+use std::collections::BTreeMap;
+
+/// The types known without any `--type-add`
+const DEFAULT_TYPES: &[(&str, &[&str])] = &[
+    ("c", &["*.c", "*.h"]),
+    (
+        "cpp",
+        &["*.cpp", "*.cc", "*.cxx", "*.hpp", "*.hh", "*.hxx", "*.h"],
+    ),
+    ("cs", &["*.cs"]),
+    ("css", &["*.css", "*.scss", "*.sass", "*.less"]),
+    ("go", &["*.go"]),
+    ("html", &["*.html", "*.htm"]),
+    ("java", &["*.java"]),
+    ("js", &["*.js", "*.jsx", "*.mjs", "*.cjs"]),
+    ("json", &["*.json"]),
+    ("lua", &["*.lua"]),
+    (
+        "make",
+        &["Makefile", "makefile", "GNUmakefile", "*.mk", "*.mak"],
+    ),
+    ("md", &["*.md", "*.markdown"]),
+    ("php", &["*.php"]),
+    ("py", &["*.py", "*.pyi"]),
+    ("rb", &["*.rb", "Gemfile", "Rakefile"]),
+    ("rust", &["*.rs"]),
+    ("sh", &["*.sh", "*.bash", "*.zsh"]),
+    ("sql", &["*.sql"]),
+    ("swift", &["*.swift"]),
+    ("toml", &["*.toml", "Cargo.lock"]),
+    ("ts", &["*.ts", "*.tsx", "*.mts", "*.cts"]),
+    ("txt", &["*.txt"]),
+    ("xml", &["*.xml", "*.xsd", "*.xsl"]),
+    ("yaml", &["*.yaml", "*.yml"]),
+];
+
+/// The file types by name, each with the globs its file names match
+#[derive(Debug)]
+pub struct Types {
+    defs: BTreeMap<String, Vec<String>>,
+}
+
+impl Types {
+    /// The built-in types
+    pub fn new() -> Types {
+        let defs = DEFAULT_TYPES
+            .iter()
+            .map(|(name, globs)| {
+                (
+                    name.to_string(),
+                    globs.iter().map(|g| g.to_string()).collect(),
+                )
+            })
+            .collect();
+        Types { defs }
+    }
+
+    /// Adds a glob to a type from a definition such as `tf:*.tf`, creating
+    /// the type if it's new
+    pub fn add(&mut self, def: &str) -> Result<(), String> {
+        let invalid = || format!("Invalid file type definition \"{}\"", def);
+        let (name, glob) = def.split_once(':').ok_or_else(invalid)?;
+        let valid_name = name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if name.is_empty() || !valid_name || glob.is_empty() {
+            return Err(invalid());
+        }
+        self.defs
+            .entry(name.to_string())
+            .or_default()
+            .push(glob.to_string());
+        Ok(())
+    }
+
+    /// The globs of every named type
+    pub fn globs<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> Result<Vec<&str>, String> {
+        let mut res = vec![];
+        for name in names {
+            let globs = self
+                .defs
+                .get(name)
+                .ok_or_else(|| format!("Unknown file type \"{}\"", name))?;
+            res.extend(globs.iter().map(String::as_str));
+        }
+        Ok(res)
+    }
+
+    /// Each type's definition as printed by --type-list, in order of name
+    pub fn list(&self) -> impl Iterator<Item = String> + '_ {
+        self.defs
+            .iter()
+            .map(|(name, globs)| format!("{}: {}", name, globs.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Types;
+
+    #[test]
+    fn test_globs() {
+        let types = Types::new();
+        assert_eq!(types.globs(["rust"]).unwrap(), ["*.rs"]);
+        assert_eq!(types.globs(["go", "json"]).unwrap(), ["*.go", "*.json"]);
+        assert_eq!(
+            types.globs(["nope"]).unwrap_err(),
+            r#"Unknown file type "nope""#
+        );
+    }
+
+    #[test]
+    fn test_add() {
+        let mut types = Types::new();
+        types.add("tf:*.tf").unwrap();
+        types.add("tf:*.tfvars").unwrap();
+        types.add("rust:*.rs.in").unwrap();
+        assert_eq!(types.globs(["tf"]).unwrap(), ["*.tf", "*.tfvars"]);
+        assert_eq!(types.globs(["rust"]).unwrap(), ["*.rs", "*.rs.in"]);
+
+        for bad in ["tf", "tf:", ":*.tf", "t f:*.tf"] {
+            assert_eq!(
+                types.add(bad).unwrap_err(),
+                format!("Invalid file type definition \"{}\"", bad)
+            );
+        }
+    }
+
+    #[test]
+    fn test_list() {
+        let mut types = Types::new();
+        types.add("aaa:*.a").unwrap();
+        let list: Vec<_> = types.list().collect();
+        assert_eq!(list[0], "aaa: *.a");
+        assert!(list.contains(&"py: *.py, *.pyi".to_string()));
+    }
+}
@@ -0,0 +1,154 @@
+//! The `Sink` trait, which is told about a search as it goes
+
+use std::path::Path;
+
+use crate::{GreprError, Line};
+
+/// Receives the lines wanted from an input as they're found, between the
+/// start and the end of its search. Only `matched` has to be written; the
+/// rest do nothing by default. An error from any of them ends the search.
+pub trait Sink {
+    /// The search of `path` is about to start
+    fn begin(&mut self, _path: &Path) -> Result<(), GreprError> {
+        Ok(())
+    }
+
+    /// A selected line: one that matches, or with an inverted search one
+    /// that doesn't
+    fn matched(&mut self, line: Line) -> Result<(), GreprError>;
+
+    /// A line of context around the selected ones
+    fn context(&mut self, _line: Line) -> Result<(), GreprError> {
+        Ok(())
+    }
+
+    /// The search of `path` is over
+    fn end(&mut self, _path: &Path) -> Result<(), GreprError> {
+        Ok(())
+    }
+
+    /// `path` couldn't be searched at all
+    fn error(&mut self, _path: &Path, _error: &GreprError) {}
+}
+
+/// Collects every line, selected or context, in the order they were found
+impl Sink for Vec<Line> {
+    fn matched(&mut self, line: Line) -> Result<(), GreprError> {
+        self.push(line);
+        Ok(())
+    }
+
+    fn context(&mut self, line: Line) -> Result<(), GreprError> {
+        self.push(line);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sink;
+    use crate::{GreprError, Line, SearchOptions, Searcher};
+    use regex::bytes::Regex;
+    use std::path::Path;
+
+    /// Writes down each event it's told about
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl Sink for Events {
+        fn begin(&mut self, path: &Path) -> Result<(), GreprError> {
+            self.0.push(format!("begin {}", path.display()));
+            Ok(())
+        }
+
+        fn matched(&mut self, line: Line) -> Result<(), GreprError> {
+            self.0.push(format!("match {}", line.number));
+            Ok(())
+        }
+
+        fn context(&mut self, line: Line) -> Result<(), GreprError> {
+            self.0.push(format!("context {}", line.number));
+            Ok(())
+        }
+
+        fn end(&mut self, path: &Path) -> Result<(), GreprError> {
+            self.0.push(format!("end {}", path.display()));
+            Ok(())
+        }
+
+        fn error(&mut self, path: &Path, _error: &GreprError) {
+            self.0.push(format!("error {}", path.display()));
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let options = SearchOptions {
+            after_context: 1,
+            ..Default::default()
+        };
+        let searcher = Searcher::with_matcher(Regex::new("fox").unwrap(), options);
+        let mut events = Events::default();
+        searcher
+            .search(
+                Path::new("in"),
+                "a fox\nran\naway\n".as_bytes(),
+                &mut events,
+            )
+            .unwrap();
+        assert_eq!(events.0, ["begin in", "match 1", "context 2", "end in"]);
+
+        // A vector of lines collects the lines of either kind
+        let mut lines: Vec<Line> = vec![];
+        searcher
+            .search(Path::new("in"), "a fox\nran\n".as_bytes(), &mut lines)
+            .unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].is_match && !lines[1].is_match);
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_search_path() {
+        // A file that can't be opened is reported to the sink
+        let searcher = Searcher::with_matcher(Regex::new("fox").unwrap(), SearchOptions::default());
+        let mut events = Events::default();
+        searcher
+            .search_path(Path::new("tests/inputs/nonexistent.txt"), &mut events)
+            .unwrap();
+        assert_eq!(events.0, ["error tests/inputs/nonexistent.txt"]);
+    }
+
+    #[test]
+    fn test_search_slice() {
+        let searcher = Searcher::with_matcher(Regex::new("o").unwrap(), SearchOptions::default());
+        let mut lines: Vec<Line> = vec![];
+        searcher
+            .search_slice("buffer", b"one\ntwo\nsix\n", &mut lines)
+            .unwrap();
+        let numbers: Vec<_> = lines.iter().map(|line| line.number).collect();
+        assert_eq!(numbers, [1, 2]);
+
+        // A multiline pattern finds the same lines in the slice
+        let options = SearchOptions {
+            multiline: true,
+            ..Default::default()
+        };
+        let searcher = Searcher::with_matcher(Regex::new(r"e\nt").unwrap(), options);
+        let mut lines: Vec<Line> = vec![];
+        searcher
+            .search_slice("buffer", b"one\ntwo\nsix\n", &mut lines)
+            .unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].spans, [(0, 1)]);
+
+        // Bytes that aren't UTF-8 are kept, and don't keep the rest from
+        // matching
+        let mut lines: Vec<Line> = vec![];
+        searcher
+            .search_slice("buffer", b"\xffe\nt\n", &mut lines)
+            .unwrap();
+        assert_eq!(lines[0].text, b"\xffe\n");
+        assert_eq!(lines[0].spans, [(1, 2)]);
+    }
+}
@@ -0,0 +1,139 @@
+//! The counts a search keeps of what it searched and found
+
+use std::io;
+use std::time::Duration;
+
+use crate::Line;
+
+/// What one file or the whole run searched and found, printed by --stats.
+/// It serializes as the stats of ripgrep's JSON.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchStats {
+    /// How long the search took, from the walk to the last file for a run
+    #[cfg_attr(feature = "serde", serde(with = "elapsed"))]
+    pub elapsed: Duration,
+    #[cfg_attr(feature = "serde", serde(rename = "searches"))]
+    pub files_searched: usize,
+    #[cfg_attr(feature = "serde", serde(rename = "searches_with_match"))]
+    pub files_matched: usize,
+    pub bytes_searched: u64,
+    /// Only tracked for --json, where it's reported with each file
+    pub bytes_printed: u64,
+    pub matched_lines: usize,
+    pub matches: usize,
+    /// Files left out by --max-filesize, which ripgrep doesn't report
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_zero"))]
+    pub files_skipped: usize,
+}
+
+#[cfg(feature = "serde")]
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+impl SearchStats {
+    /// Counts a line selected by the search. Inverted lines have no matches
+    /// of their own, so each counts once.
+    pub fn add_line(&mut self, line: &Line, invert_match: bool) {
+        if line.is_match {
+            self.matched_lines += 1;
+            self.matches += if invert_match { 1 } else { line.spans.len() };
+        }
+    }
+
+    /// Adds another file's counts to these. The time taken is left alone,
+    /// since files may be searched at once.
+    pub fn add(&mut self, other: &SearchStats) {
+        self.files_searched += other.files_searched;
+        self.files_skipped += other.files_skipped;
+        self.files_matched += other.files_matched;
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+        self.bytes_searched += other.bytes_searched;
+        self.bytes_printed += other.bytes_printed;
+    }
+
+    /// Writes the totals as --stats prints them, after a blank line
+    pub fn print(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        writeln!(out)?;
+        writeln!(out, "{} matches", self.matches)?;
+        writeln!(out, "{} matched lines", self.matched_lines)?;
+        writeln!(out, "{} files contained matches", self.files_matched)?;
+        writeln!(out, "{} files searched", self.files_searched)?;
+        if self.files_skipped > 0 {
+            writeln!(out, "{} files skipped for their size", self.files_skipped)?;
+        }
+        writeln!(out, "{} bytes searched", self.bytes_searched)?;
+        writeln!(out, "{:.6} seconds", self.elapsed.as_secs_f64())
+    }
+}
+
+/// A duration as ripgrep writes it, in a form for people as well. Only
+/// the seconds and nanoseconds are read back.
+#[cfg(feature = "serde")]
+pub mod elapsed {
+    use std::time::Duration;
+
+    use serde::ser::SerializeStruct;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    struct Elapsed {
+        secs: u64,
+        nanos: u32,
+    }
+
+    pub fn serialize<S: Serializer>(elapsed: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Elapsed", 3)?;
+        state.serialize_field("secs", &elapsed.as_secs())?;
+        state.serialize_field("nanos", &elapsed.subsec_nanos())?;
+        state.serialize_field("human", &format!("{:.6}s", elapsed.as_secs_f64()))?;
+        state.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let elapsed = Elapsed::deserialize(deserializer)?;
+        Ok(Duration::new(elapsed.secs, elapsed.nanos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SearchStats;
+    use crate::Line;
+    use std::time::Duration;
+
+    #[test]
+    fn test_add() {
+        let line = Line {
+            number: 1,
+            offset: 0,
+            text: b"fox fox\n".to_vec(),
+            is_match: true,
+            spans: vec![(0, 3), (4, 7)],
+        };
+        let mut file = SearchStats {
+            files_searched: 1,
+            elapsed: Duration::from_secs(1),
+            ..Default::default()
+        };
+        file.add_line(&line, false);
+        file.add_line(&line, true);
+        assert_eq!((file.matched_lines, file.matches), (2, 3));
+
+        // Totals keep their own time
+        let mut total = SearchStats::default();
+        total.add(&file);
+        total.add(&file);
+        assert_eq!(total.files_searched, 2);
+        assert_eq!(total.matches, 6);
+        assert_eq!(total.elapsed, Duration::ZERO);
+
+        let mut out = vec![];
+        file.print(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("\n3 matches\n2 matched lines\n"));
+        assert!(out.ends_with("1.000000 seconds\n"));
+    }
+}
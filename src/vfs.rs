@@ -0,0 +1,266 @@
+//! The file systems a search can read from, behind the `Vfs` trait, so
+//! that trees other than the disk can be walked and searched
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+/// What a search needs of a file system. One is shared by the threads
+/// searching files, so it has to be `Send` and `Sync`.
+pub trait Vfs: Debug + Send + Sync {
+    /// A reader for the contents of the file at `path`
+    fn open(&self, path: &Path) -> io::Result<Box<dyn BufRead + '_>>;
+
+    /// What's known about the file at `path`, following any links
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// The paths of the entries in the directory at `path`, each joined to it
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Like `metadata`, but about a link itself rather than where it leads.
+    /// The same for file systems without links.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.metadata(path)
+    }
+
+    /// The path the file at `path` is known by once links and . and ..
+    /// are resolved. Ignore files are looked for in the directories above it.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.metadata(path)?;
+        Ok(path.to_path_buf())
+    }
+}
+
+/// What a walk needs to know about a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub is_dir: bool,
+    /// A file to search rather than a directory, link or device
+    pub is_file: bool,
+    pub is_symlink: bool,
+    pub len: u64,
+    /// Which file system the file is on, for staying on one. `None` where
+    /// there's only the one, or where it can't be told, as on Windows.
+    pub device: Option<u64>,
+    /// `None` where the file system doesn't keep the time
+    pub modified: Option<SystemTime>,
+    pub created: Option<SystemTime>,
+}
+
+/// The disk, through `std::fs`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl Vfs for RealFs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn BufRead + '_>> {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(to_metadata(&std::fs::metadata(path)?))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        Ok(to_metadata(&std::fs::symlink_metadata(path)?))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        std::fs::canonicalize(path)
+    }
+}
+
+fn to_metadata(metadata: &std::fs::Metadata) -> Metadata {
+    #[cfg(unix)]
+    let device = Some(std::os::unix::fs::MetadataExt::dev(metadata));
+    #[cfg(not(unix))]
+    let device = None;
+    Metadata {
+        is_dir: metadata.is_dir(),
+        is_file: metadata.is_file(),
+        is_symlink: metadata.is_symlink(),
+        len: metadata.len(),
+        device,
+        modified: metadata.modified().ok(),
+        created: metadata.created().ok(),
+    }
+}
+
+/// Files held in memory, as for editor buffers or test fixtures. The
+/// directories are those the files' paths imply, and entries are listed
+/// in order of name. A leading ./ makes no difference to a path, and "."
+/// is the root.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+    /// The directories given a device of their own, and what's under them
+    devices: BTreeMap<PathBuf, u64>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file, replacing any already at `path`
+    pub fn insert(&mut self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) {
+        self.files.insert(normalize(path.as_ref()), contents.into());
+    }
+
+    /// Puts `dir` and everything under it on `device`, as though another
+    /// file system were mounted there. Anything not under a mount has no
+    /// device.
+    pub fn mount(&mut self, dir: impl AsRef<Path>, device: u64) {
+        self.devices.insert(normalize(dir.as_ref()), device);
+    }
+
+    /// The device of the innermost mount `path` is under
+    fn device(&self, path: &Path) -> Option<u64> {
+        path.ancestors()
+            .find_map(|dir| self.devices.get(dir))
+            .copied()
+    }
+
+    /// The files under the directory `dir`, which may be the root
+    fn files_under<'a>(&'a self, dir: &'a Path) -> impl Iterator<Item = &'a Path> + 'a {
+        self.files
+            .keys()
+            .filter(move |path| path.starts_with(dir) && *path != dir)
+            .map(PathBuf::as_path)
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn BufRead + '_>> {
+        match self.files.get(&normalize(path)) {
+            Some(contents) => Ok(Box::new(&contents[..])),
+            None if self.metadata(path).is_ok() => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                "Is a directory",
+            )),
+            None => Err(not_found()),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let key = normalize(path);
+        let (is_dir, len) = match self.files.get(&key) {
+            Some(contents) => (false, contents.len() as u64),
+            None if self.files_under(&key).next().is_some() => (true, 0),
+            None => return Err(not_found()),
+        };
+        Ok(Metadata {
+            is_dir,
+            is_file: !is_dir,
+            is_symlink: false,
+            len,
+            device: self.device(&key),
+            modified: None,
+            created: None,
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let key = normalize(path);
+        if !self.metadata(&key)?.is_dir {
+            return Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                "Not a directory",
+            ));
+        }
+        // The first component below the directory names each entry
+        let mut names: Vec<_> = self
+            .files_under(&key)
+            .filter_map(|file| file.strip_prefix(&key).ok()?.components().next())
+            .collect();
+        names.dedup();
+        Ok(names.into_iter().map(|name| path.join(name)).collect())
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.metadata(path)?;
+        Ok(normalize(path))
+    }
+}
+
+/// `path` without any . components
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "No such file or directory")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryFs, RealFs, Vfs};
+    use std::io::{ErrorKind, Read};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_memory_fs() {
+        let mut vfs = MemoryFs::new();
+        vfs.insert("a/b.txt", "fox\n");
+        vfs.insert("./a/c/d.txt", "dog\n");
+        vfs.insert("e.txt", "");
+
+        let mut contents = String::new();
+        vfs.open(Path::new("./a/b.txt"))
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "fox\n");
+
+        assert_eq!(vfs.metadata(Path::new("a/b.txt")).unwrap().len, 4);
+        assert!(vfs.metadata(Path::new("a/c")).unwrap().is_dir);
+        assert!(vfs.metadata(Path::new(".")).unwrap().is_dir);
+        let e = vfs.metadata(Path::new("a/b")).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+        let e = vfs.open(Path::new("a")).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::IsADirectory);
+
+        // Entries are named after the path they're listed under
+        assert_eq!(
+            vfs.read_dir(Path::new(".")).unwrap(),
+            [PathBuf::from("./a"), PathBuf::from("./e.txt")]
+        );
+        assert_eq!(
+            vfs.read_dir(Path::new("a")).unwrap(),
+            [PathBuf::from("a/b.txt"), PathBuf::from("a/c")]
+        );
+        let e = vfs.read_dir(Path::new("e.txt")).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotADirectory);
+
+        // A mount's device covers everything under it
+        vfs.mount("a", 1);
+        vfs.mount("./a/c", 2);
+        let device = |path| vfs.metadata(Path::new(path)).unwrap().device;
+        assert_eq!(device("e.txt"), None);
+        assert_eq!(device("a"), Some(1));
+        assert_eq!(device("a/b.txt"), Some(1));
+        assert_eq!(device("a/c/d.txt"), Some(2));
+    }
+
+    #[test]
+    fn test_real_fs() {
+        let metadata = RealFs.metadata(Path::new("tests/inputs/fox.txt")).unwrap();
+        assert!(!metadata.is_dir);
+        assert!(metadata.modified.is_some());
+        assert!(RealFs
+            .read_dir(Path::new("tests/inputs"))
+            .unwrap()
+            .contains(&PathBuf::from("tests/inputs/fox.txt")));
+        assert!(RealFs.open(Path::new("tests/inputs/nonexistent")).is_err());
+    }
+}
@@ -0,0 +1,811 @@
+//! Finding the files to search on disk or another `Vfs`, as the command
+//! does, which only builds with the "fs" feature
+
+use std::ffi::OsStr;
+use std::io;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use tracing::{debug, trace};
+
+use crate::ignore::Ignores;
+use crate::vfs::{Metadata, RealFs, Vfs};
+use crate::{is_cancelled, GreprError, MyResult};
+
+/// Controls which files `find_files` yields while walking directories
+#[derive(Debug, Default, Clone)]
+pub struct WalkOptions {
+    /// Walk the directories among the paths rather than report them
+    pub recursive: bool,
+    /// Pass over directories silently rather than report them when not recursing
+    pub skip_dirs: bool,
+    /// The current directory is searched for want of any paths, so what's
+    /// found is named without its leading ./
+    pub implicit_cwd: bool,
+    /// Walk into the directories symbolic links point to
+    pub follow_links: bool,
+    /// Stay on the file system of each path rather than cross mount points
+    pub one_file_system: bool,
+    /// Descend into hidden directories and search hidden files
+    pub hidden: bool,
+    /// Search paths listed in .gitignore files
+    pub no_ignore_vcs: bool,
+    /// Search paths listed in .ignore files
+    pub no_ignore_dot: bool,
+    /// How many levels below each path to descend; the path itself is depth 0
+    pub max_depth: Option<usize>,
+    /// Files larger than this many bytes are skipped
+    pub max_filesize: Option<u64>,
+    /// Only files whose names match are searched, unless this is empty
+    pub include: GlobSet,
+    pub exclude: GlobSet,
+    /// Directories whose names match are never walked into
+    pub exclude_dir: GlobSet,
+    /// The globs of the file types selected with --type
+    pub types: GlobSet,
+    /// The globs of the file types left out with --type-not
+    pub types_not: GlobSet,
+    /// What to order the files by, rather than the order they're found in
+    pub sort: Option<SortBy>,
+    pub sort_reverse: bool,
+    /// Stops the walk once set, leaving a `Cancelled` error last
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+/// The orders --sort can put files in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortBy {
+    Path,
+    Modified,
+    Created,
+    Size,
+}
+
+/// A file's place in the order given by a `SortBy`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    Path(PathBuf),
+    Time(SystemTime),
+    Size(u64),
+}
+
+impl SortBy {
+    /// `None` if the file's metadata can't be read
+    fn key(self, vfs: &dyn Vfs, path: &Path) -> Option<SortKey> {
+        let metadata = || vfs.metadata(path).ok();
+        Some(match self {
+            // Paths compare by component, so "a/b" comes before "a-b"
+            SortBy::Path => SortKey::Path(path.to_path_buf()),
+            SortBy::Modified => SortKey::Time(metadata()?.modified?),
+            SortBy::Created => SortKey::Time(metadata()?.created?),
+            SortBy::Size => SortKey::Size(metadata()?.len),
+        })
+    }
+}
+
+/// Builds the set of globs that a file name is matched against, as in
+/// `WalkOptions`
+pub fn build_globs<'a>(globs: impl IntoIterator<Item = &'a str>) -> MyResult<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).map_err(|_| GreprError::InvalidGlob(glob.to_string()))?);
+    }
+    builder
+        .build()
+        .map_err(|e| GreprError::InvalidGlob(e.glob().unwrap_or_default().to_string()))
+}
+
+/// Returns the files to search on disk, along with how many were left out
+/// for their size
+pub fn find_files(paths: &[PathBuf], walk: &WalkOptions) -> (Vec<MyResult<PathBuf>>, usize) {
+    find_files_in(&RealFs, paths, walk)
+}
+
+/// Like `find_files`, but walks `vfs` rather than the disk
+pub fn find_files_in(
+    vfs: &dyn Vfs,
+    paths: &[PathBuf],
+    walk: &WalkOptions,
+) -> (Vec<MyResult<PathBuf>>, usize) {
+    let mut res = vec![];
+    let mut skipped = 0;
+    for path in paths {
+        let mut emit = |file| {
+            res.push(file);
+            true
+        };
+        if !walk_path(vfs, path, walk, &mut skipped, &mut emit) {
+            break;
+        }
+    }
+
+    if let Some(sort) = walk.sort {
+        res = sort_files(vfs, res, sort, walk.sort_reverse);
+    }
+    (res, skipped)
+}
+
+/// Whether a file or directory is left out for its name starting with a dot
+fn is_hidden(walk: &WalkOptions, name: &OsStr) -> bool {
+    !walk.hidden && name.to_string_lossy().starts_with('.')
+}
+
+/// Whether a file found in a directory is selected by the globs
+fn is_included(walk: &WalkOptions, name: &OsStr) -> bool {
+    (walk.include.is_empty() || walk.include.is_match(name))
+        && !walk.exclude.is_match(name)
+        && (walk.types.is_empty() || walk.types.is_match(name))
+        && !walk.types_not.is_match(name)
+}
+
+/// Hands `emit` the files to search under one path of `vfs`, in the order
+/// they're found, counting those left out for their size. The walk stops
+/// early if `emit` returns false or it's cancelled. Returns whether to go
+/// on to the next path.
+pub fn walk_path(
+    vfs: &dyn Vfs,
+    path: &Path,
+    walk: &WalkOptions,
+    skipped: &mut usize,
+    emit: &mut dyn FnMut(MyResult<PathBuf>) -> bool,
+) -> bool {
+    let root = match Root::new(vfs, path, walk, skipped, emit) {
+        ControlFlow::Continue(root) => root,
+        ControlFlow::Break(more) => return more,
+    };
+    let mut walker = Walker {
+        root: &root,
+        ancestors: vec![],
+        skipped,
+        emit,
+        defer: None,
+    };
+    walker.walk_dir(path, 0)
+}
+
+/// What the walk under a directory named among the paths shares, between
+/// threads when it's walked on more than one
+pub(crate) struct Root<'a> {
+    vfs: &'a dyn Vfs,
+    walk: &'a WalkOptions,
+    path: &'a Path,
+    /// The file system the root is on
+    device: Option<u64>,
+    ignores: Option<(Mutex<Ignores<'a>>, PathBuf)>,
+}
+
+impl<'a> Root<'a> {
+    /// Starts the walk under `path`, or emits `path` itself if there's no
+    /// directory to walk there, breaking with whether to go on to the next
+    pub(crate) fn new(
+        vfs: &'a dyn Vfs,
+        path: &'a Path,
+        walk: &'a WalkOptions,
+        skipped: &mut usize,
+        emit: &mut dyn FnMut(MyResult<PathBuf>) -> bool,
+    ) -> ControlFlow<bool, Root<'a>> {
+        if path == Path::new("-") {
+            return ControlFlow::Break(emit(Ok(path.to_path_buf())));
+        }
+        // A path given directly is followed if it's a link, and searched
+        // whatever its name
+        let metadata = match vfs.metadata(path) {
+            Ok(metadata) => metadata,
+            Err(source) => return ControlFlow::Break(emit(Err(io_error(path, source)))),
+        };
+        if !metadata.is_dir {
+            return ControlFlow::Break(select(walk, path, &metadata, skipped, emit));
+        }
+        // A directory can't be read as a file, so it's reported unless it's
+        // to be skipped
+        if !walk.recursive {
+            if !walk.skip_dirs {
+                let e = GreprError::IsADirectory(path.to_path_buf());
+                return ControlFlow::Break(emit(Err(e)));
+            }
+            return ControlFlow::Break(true);
+        }
+
+        // Ignore files are matched against canonical paths
+        let mut ignores = None;
+        if !walk.no_ignore_dot || !walk.no_ignore_vcs {
+            ignores = vfs.canonicalize(path).ok().map(|root| {
+                let ignores = Ignores::new(vfs, &root, !walk.no_ignore_dot, !walk.no_ignore_vcs);
+                (Mutex::new(ignores), root)
+            });
+        }
+        ControlFlow::Continue(Root {
+            vfs,
+            walk,
+            path,
+            device: metadata.device,
+            ignores,
+        })
+    }
+
+    /// Whether a path found under the root is left out by an ignore file
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let Some((ignores, root)) = &self.ignores else {
+            return false;
+        };
+        path.strip_prefix(self.path).is_ok_and(|relative| {
+            let mut ignores = ignores.lock().unwrap();
+            ignores.is_ignored(&root.join(relative), is_dir)
+        })
+    }
+}
+
+/// The state of a walk through directories under a root
+pub(crate) struct Walker<'r, 'e> {
+    pub(crate) root: &'e Root<'r>,
+    /// The canonical paths of the directories being walked, from the root
+    /// down, when following links, which could lead back to one of them
+    pub(crate) ancestors: Vec<PathBuf>,
+    pub(crate) skipped: &'e mut usize,
+    pub(crate) emit: &'e mut dyn FnMut(MyResult<PathBuf>) -> bool,
+    /// Takes the directories found, with their depth and ancestors, to be
+    /// walked on other threads rather than in turn
+    pub(crate) defer: Option<&'e mut dyn FnMut(PathBuf, usize, Vec<PathBuf>)>,
+}
+
+impl Walker<'_, '_> {
+    /// Walks the entries of `dir`, `depth` levels below the root. Excluded,
+    /// hidden and ignored directories are pruned so they're never descended
+    /// into. Returns false once the walk is over.
+    pub(crate) fn walk_dir(&mut self, dir: &Path, depth: usize) -> bool {
+        let Root { vfs, walk, .. } = *self.root;
+        if walk.max_depth.is_some_and(|max| depth >= max) {
+            return true;
+        }
+        // Following links could lead round in circles, which is reported
+        // rather than walked
+        let canonical = if walk.follow_links {
+            let canonical = match vfs.canonicalize(dir) {
+                Ok(canonical) => canonical,
+                Err(source) => return (self.emit)(Err(io_error(dir, source))),
+            };
+            if let Some(ancestor) = self.ancestors.iter().find(|a| **a == canonical) {
+                let e = GreprError::Loop {
+                    path: dir.to_path_buf(),
+                    ancestor: ancestor.clone(),
+                };
+                return (self.emit)(Err(e));
+            }
+            Some(canonical)
+        } else {
+            None
+        };
+        let entries = match vfs.read_dir(dir) {
+            Ok(entries) => entries,
+            Err(source) => return (self.emit)(Err(io_error(dir, source))),
+        };
+
+        self.ancestors.extend(canonical);
+        for entry in entries {
+            if is_cancelled(walk.cancel.as_deref()) {
+                (self.emit)(Err(GreprError::Cancelled));
+                return false;
+            }
+            let name = entry.file_name().unwrap_or_default();
+            if is_hidden(walk, name) {
+                debug!("{}: skipped as hidden", entry.display());
+                continue;
+            }
+            let metadata = if walk.follow_links {
+                vfs.metadata(&entry)
+            } else {
+                vfs.symlink_metadata(&entry)
+            };
+            let metadata = match metadata {
+                Ok(metadata) => metadata,
+                // The error names the entry that couldn't be read
+                Err(source) => {
+                    if !(self.emit)(Err(io_error(&entry, source))) {
+                        return false;
+                    }
+                    continue;
+                }
+            };
+
+            let more = if metadata.is_dir {
+                if walk.exclude_dir.is_match(name) {
+                    debug!("{}: skipped by --exclude-dir", entry.display());
+                    true
+                } else if self.root.is_ignored(&entry, true) {
+                    true
+                } else if walk.one_file_system && metadata.device != self.root.device {
+                    debug!("{}: skipped on another file system", entry.display());
+                    true
+                } else if let Some(defer) = &mut self.defer {
+                    defer(entry, depth + 1, self.ancestors.clone());
+                    true
+                } else {
+                    self.walk_dir(&entry, depth + 1)
+                }
+            } else if !metadata.is_file || self.root.is_ignored(&entry, false) {
+                true
+            } else if !is_included(walk, name) {
+                debug!("{}: skipped by file name", entry.display());
+                true
+            } else {
+                select(walk, &entry, &metadata, self.skipped, self.emit)
+            };
+            if !more {
+                return false;
+            }
+        }
+        if walk.follow_links {
+            self.ancestors.pop();
+        }
+        true
+    }
+}
+
+/// Emits a file to search. Unlike the globs, the size limit applies to
+/// files named directly as well.
+fn select(
+    walk: &WalkOptions,
+    path: &Path,
+    metadata: &Metadata,
+    skipped: &mut usize,
+    emit: &mut dyn FnMut(MyResult<PathBuf>) -> bool,
+) -> bool {
+    if walk.max_filesize.is_some_and(|max| metadata.len > max) {
+        debug!("{}: skipped by --max-filesize", path.display());
+        *skipped += 1;
+        return true;
+    }
+    trace!("{}: selected", path.display());
+    let path = if walk.implicit_cwd {
+        path.strip_prefix(".").unwrap_or(path)
+    } else {
+        path
+    };
+    emit(Ok(path.to_path_buf()))
+}
+
+fn io_error(path: &Path, source: io::Error) -> GreprError {
+    GreprError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// Orders the files found across every path. Errors, and files whose
+/// metadata can't be read, stay first in the order they were found.
+fn sort_files(
+    vfs: &dyn Vfs,
+    files: Vec<MyResult<PathBuf>>,
+    sort: SortBy,
+    reverse: bool,
+) -> Vec<MyResult<PathBuf>> {
+    let mut keyed: Vec<_> = files
+        .into_iter()
+        .map(|file| {
+            (
+                file.as_ref().ok().and_then(|path| sort.key(vfs, path)),
+                file,
+            )
+        })
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) if reverse => b.cmp(a),
+        _ => a.cmp(b),
+    });
+    keyed.into_iter().map(|(_, file)| file).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_globs, find_files, find_files_in, SortBy, WalkOptions};
+    use crate::{GreprError, MemoryFs};
+    use rand::{distributions::Alphanumeric, Rng};
+    use std::path::PathBuf;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_find_files() {
+        let recursive = WalkOptions {
+            recursive: true,
+            ..Default::default()
+        };
+
+        // Verify that the function finds a file known to exist
+        let files = find_files(&["./tests/inputs/fox.txt".into()], &WalkOptions::default()).0;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].as_ref().unwrap(), "./tests/inputs/fox.txt");
+
+        // The function should reject a directory without the recursive option
+        let files = find_files(&["./tests/inputs/".into()], &WalkOptions::default()).0;
+        assert_eq!(files.len(), 1);
+        if let Err(e) = &files[0] {
+            assert_eq!(e.to_string(), "./tests/inputs/ is a directory");
+        }
+
+        // Unless directories are to be skipped, which leaves the other paths
+        let skip = WalkOptions {
+            skip_dirs: true,
+            ..Default::default()
+        };
+        let paths = ["./tests/inputs/".into(), "./tests/inputs/fox.txt".into()];
+        let files = find_files(&paths, &skip).0;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].as_ref().unwrap(), "./tests/inputs/fox.txt");
+
+        // Verify the function recurses to find four files in the directory
+        let res = find_files(&["./tests/inputs/".into()], &recursive).0;
+        let files = res
+            .iter()
+            .map(|r| r.as_ref().unwrap().to_string_lossy().replace("\\", "/"))
+            .collect::<Vec<String>>();
+
+        assert_eq!(files.len(), 4);
+
+        // Generate a random string to represent a nonexistent file
+        let bad: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(7)
+            .map(char::from)
+            .collect();
+        // Verify that the function returns the bad file as an error
+        let files = find_files(&[bad.into()], &WalkOptions::default()).0;
+        assert_eq!(files.len(), 1);
+        assert!(files[0].is_err());
+    }
+
+    #[test]
+    fn test_find_files_include() {
+        // Only files whose name matches one of the globs are found
+        let walk = WalkOptions {
+            recursive: true,
+            include: build_globs(["f*", "*.md"]).unwrap(),
+            ..Default::default()
+        };
+        let files = find_files(&["./tests/inputs/".into()], &walk).0;
+        assert_eq!(files.len(), 1);
+        assert!(files[0].as_ref().unwrap().ends_with("fox.txt"));
+
+        // A file given explicitly is searched regardless of the globs
+        let files = find_files(&["./tests/inputs/bustle.txt".into()], &walk).0;
+        assert_eq!(files.len(), 1);
+        assert!(files[0].is_ok());
+
+        assert!(build_globs(["a[b"]).is_err());
+    }
+
+    #[test]
+    fn test_find_files_exclude() {
+        // Exclusions win over inclusions
+        let walk = WalkOptions {
+            recursive: true,
+            include: build_globs(["*.txt"]).unwrap(),
+            exclude: build_globs(["[be]*"]).unwrap(),
+            ..Default::default()
+        };
+        let files = find_files(&["./tests/inputs/".into()], &walk).0;
+        assert_eq!(files.len(), 2);
+
+        // Excluded directories are skipped entirely, but not when named directly
+        let walk = WalkOptions {
+            recursive: true,
+            exclude_dir: build_globs(["inputs"]).unwrap(),
+            ..Default::default()
+        };
+        let files = find_files(&["./tests".into()], &walk).0;
+        assert!(files
+            .iter()
+            .all(|f| !f.as_ref().unwrap().to_string_lossy().contains("inputs")));
+        let files = find_files(&["./tests/inputs".into()], &walk).0;
+        assert_eq!(files.len(), 4);
+    }
+
+    #[test]
+    fn test_find_files_max_depth() {
+        let mut walk = WalkOptions {
+            recursive: true,
+            max_depth: Some(1),
+            ..Default::default()
+        };
+
+        // The input files sit two levels below ./tests
+        assert!(find_files(&["./tests".into()], &walk).0.iter().all(|f| !f
+            .as_ref()
+            .unwrap()
+            .to_string_lossy()
+            .contains("inputs")));
+
+        walk.max_depth = Some(2);
+        let files = find_files(&["./tests".into()], &walk).0;
+        assert!(files
+            .iter()
+            .any(|f| f.as_ref().unwrap().ends_with("fox.txt")));
+    }
+
+    #[test]
+    fn test_find_files_sort() {
+        let names = |walk: &WalkOptions| -> Vec<String> {
+            find_files(&["./tests/inputs".into()], walk)
+                .0
+                .into_iter()
+                .map(|f| {
+                    f.unwrap()
+                        .to_string_lossy()
+                        .replace('\\', "/")
+                        .replace("./tests/inputs/", "")
+                })
+                .collect()
+        };
+
+        let mut walk = WalkOptions {
+            recursive: true,
+            sort: Some(SortBy::Path),
+            ..Default::default()
+        };
+        assert_eq!(
+            names(&walk),
+            ["bustle.txt", "empty.txt", "fox.txt", "nobody.txt"]
+        );
+
+        walk.sort = Some(SortBy::Size);
+        assert_eq!(
+            names(&walk),
+            ["empty.txt", "fox.txt", "bustle.txt", "nobody.txt"]
+        );
+
+        walk.sort_reverse = true;
+        assert_eq!(
+            names(&walk),
+            ["nobody.txt", "bustle.txt", "fox.txt", "empty.txt"]
+        );
+
+        // Files that can't be looked at come first
+        let files = find_files(&["./tests/inputs/fox.txt".into(), "-".into()], &walk).0;
+        assert_eq!(files[0].as_ref().unwrap(), "-");
+    }
+
+    #[test]
+    fn test_find_files_max_filesize() {
+        let walk = WalkOptions {
+            recursive: true,
+            max_filesize: Some(100),
+            ..Default::default()
+        };
+        let (files, skipped) = find_files(&["./tests/inputs".into()], &walk);
+        assert_eq!(skipped, 2);
+        let mut names: Vec<_> = files
+            .into_iter()
+            .map(|f| f.unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["./tests/inputs/empty.txt", "./tests/inputs/fox.txt"]
+        );
+
+        // The limit applies to files named directly too
+        let (files, skipped) = find_files(&["./tests/inputs/nobody.txt".into()], &walk);
+        assert!(files.is_empty());
+        assert_eq!(skipped, 1);
+    }
+
+    #[test]
+    fn test_find_files_hidden() {
+        let dir = std::env::temp_dir().join(format!("grepr-hidden-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::write(dir.join(".git/HEAD"), "ref\n").unwrap();
+        std::fs::write(dir.join(".env"), "KEY=1\n").unwrap();
+        std::fs::write(dir.join("main.rs"), "fn main() {}\n").unwrap();
+        let names = |paths: &[PathBuf], walk: &WalkOptions| -> Vec<String> {
+            let mut names: Vec<_> = find_files(paths, walk)
+                .0
+                .into_iter()
+                .map(|f| f.unwrap().to_string_lossy().replace('\\', "/"))
+                .map(|f| f.rsplit_once("/").unwrap().1.to_string())
+                .collect();
+            names.sort();
+            names
+        };
+        let tree = [dir.clone()];
+
+        let mut walk = WalkOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        assert_eq!(names(&tree, &walk), ["main.rs"]);
+
+        // Hidden paths named directly are searched all the same
+        let named = [dir.join(".env"), dir.join(".git")];
+        assert_eq!(names(&named, &walk), [".env", "HEAD"]);
+
+        walk.hidden = true;
+        let found = names(&tree, &walk);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(found, [".env", "HEAD", "main.rs"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_files_follow_links() {
+        use std::os::unix::fs::symlink;
+
+        let dir = std::env::temp_dir().join(format!("grepr-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("real")).unwrap();
+        std::fs::create_dir_all(dir.join("tree")).unwrap();
+        std::fs::write(dir.join("real/file.txt"), "needle\n").unwrap();
+        symlink(dir.join("real"), dir.join("tree/link")).unwrap();
+        symlink(dir.join("tree"), dir.join("tree/loop")).unwrap();
+        let tree = [dir.join("tree")];
+
+        // Links aren't followed by default
+        let mut walk = WalkOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        assert!(find_files(&tree, &walk).0.is_empty());
+
+        // Following them finds the linked file and reports the cycle once
+        walk.follow_links = true;
+        let files = find_files(&tree, &walk).0;
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files.len(), 2);
+        let found: Vec<_> = files.iter().filter_map(|f| f.as_ref().ok()).collect();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].ends_with("link/file.txt"));
+    }
+
+    #[test]
+    fn test_find_files_one_file_system() {
+        let mut vfs = MemoryFs::new();
+        for path in ["a.txt", "mnt/b.txt", "mnt/c/d.txt", "mnt/e/f.txt"] {
+            vfs.insert(path, path);
+        }
+        vfs.mount("mnt", 1);
+        vfs.mount("mnt/e", 2);
+        let mut walk = WalkOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        let find = |path: &str, walk: &WalkOptions| {
+            find_files_in(&vfs, &[path.into()], walk)
+                .0
+                .into_iter()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(find(".", &walk).len(), 4);
+
+        // Each path stays on its own file system, and a mount below it is
+        // left out whole
+        walk.one_file_system = true;
+        assert_eq!(find(".", &walk), [PathBuf::from("./a.txt")]);
+        assert_eq!(
+            find("mnt", &walk),
+            [PathBuf::from("mnt/b.txt"), PathBuf::from("mnt/c/d.txt")]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_files_error_path() {
+        use std::os::unix::fs::symlink;
+
+        // A link to nowhere can't be followed, and is named in the error
+        let dir = std::env::temp_dir().join(format!("grepr-dangling-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        symlink(dir.join("nowhere"), dir.join("link")).unwrap();
+        let walk = WalkOptions {
+            recursive: true,
+            follow_links: true,
+            ..Default::default()
+        };
+        let files = find_files(std::slice::from_ref(&dir), &walk).0;
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files.len(), 1);
+        let e = files[0].as_ref().unwrap_err();
+        assert!(matches!(e, GreprError::Io { path, .. } if *path == dir.join("link")));
+    }
+
+    #[test]
+    fn test_find_files_cancel() {
+        // A cancelled walk ends with the error
+        let walk = WalkOptions {
+            recursive: true,
+            cancel: Some(Arc::new(AtomicBool::new(true))),
+            ..Default::default()
+        };
+        let files = find_files(&["./tests/inputs".into()], &walk).0;
+        assert!(matches!(files.last(), Some(Err(GreprError::Cancelled))));
+    }
+
+    #[test]
+    fn test_find_files_in() {
+        let mut vfs = MemoryFs::new();
+        for path in [
+            "b.txt",
+            "a/x.txt",
+            "a/y.rs",
+            "a/.z.txt",
+            "a/c/d.txt",
+            "a-b/e.txt",
+        ] {
+            vfs.insert(path, path);
+        }
+        let walk = WalkOptions {
+            recursive: true,
+            implicit_cwd: true,
+            ..Default::default()
+        };
+        let find = |paths: &[PathBuf], walk: &WalkOptions| {
+            find_files_in(&vfs, paths, walk)
+                .0
+                .into_iter()
+                .map(|r| r.map_err(|e| e.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let ok = |paths: &[&str]| {
+            paths
+                .iter()
+                .map(|p| Ok(PathBuf::from(p)))
+                .collect::<Vec<_>>()
+        };
+
+        // Entries come in order of name, without hidden ones or the ./
+        assert_eq!(
+            find(&[".".into()], &walk),
+            ok(&["a/c/d.txt", "a/x.txt", "a/y.rs", "a-b/e.txt", "b.txt"])
+        );
+
+        let walk = WalkOptions {
+            recursive: true,
+            max_depth: Some(1),
+            include: build_globs(["*.txt"]).unwrap(),
+            exclude_dir: build_globs(["a-*"]).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            find(&["a".into(), "a-b".into()], &walk),
+            ok(&["a/x.txt", "a-b/e.txt"])
+        );
+
+        // Named paths are searched whatever their names, or reported
+        let paths = ["a/y.rs".into(), "a".into(), "nope".into(), "-".into()];
+        let res = find(&paths, &WalkOptions::default());
+        assert_eq!(res[0], Ok(PathBuf::from("a/y.rs")));
+        assert_eq!(res[1], Err("a is a directory".to_string()));
+        assert!(res[2].as_ref().unwrap_err().starts_with("nope: "));
+        assert_eq!(res[3], Ok(PathBuf::from("-")));
+
+        // Ignore files are read from the tree walked, as on disk
+        let mut repo = vfs.clone();
+        repo.insert(".git/HEAD", "ref\n");
+        repo.insert("a/.gitignore", "*.rs\nc/\n");
+        let walk = WalkOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        let files: Vec<_> = find_files_in(&repo, &["a".into()], &walk)
+            .0
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(files, [PathBuf::from("a/x.txt")]);
+
+        // Files too big are counted rather than found
+        let walk = WalkOptions {
+            recursive: true,
+            max_filesize: Some(7),
+            sort: Some(SortBy::Size),
+            sort_reverse: true,
+            ..Default::default()
+        };
+        let (files, skipped) = find_files_in(&vfs, &["a".into()], &walk);
+        let files: Vec<_> = files.into_iter().map(Result::unwrap).collect();
+        assert_eq!(files, [PathBuf::from("a/x.txt"), PathBuf::from("a/y.rs")]);
+        assert_eq!(skipped, 1);
+    }
+}
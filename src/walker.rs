@@ -0,0 +1,328 @@
+//! Walking the paths to search on threads of their own, so that searching
+//! can start on the first files found while the rest are still being found
+
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::walk::{walk_path, Root, Walker};
+use crate::{find_files_in, MyResult, RealFs, Vfs, WalkOptions};
+
+/// Finds the files to search as `find_files` does, sending each one over a
+/// channel as soon as it's found rather than collecting them all first
+#[derive(Debug)]
+pub struct FileWalker {
+    paths: Arc<Vec<PathBuf>>,
+    walk: Arc<WalkOptions>,
+    vfs: Arc<dyn Vfs>,
+    threads: usize,
+    counts: Arc<Counts>,
+}
+
+/// How far the walk has got, read while it goes on
+#[derive(Debug, Default)]
+struct Counts {
+    found: AtomicUsize,
+    skipped: AtomicUsize,
+}
+
+impl Counts {
+    /// Sends a file on, returning whether it's still wanted
+    fn send(&self, sender: &Sender<MyResult<PathBuf>>, file: MyResult<PathBuf>) -> bool {
+        if file.is_ok() {
+            self.found.fetch_add(1, Ordering::Relaxed);
+        }
+        sender.send(file).is_ok()
+    }
+}
+
+impl FileWalker {
+    pub fn new(paths: Vec<PathBuf>, walk: WalkOptions) -> FileWalker {
+        FileWalker {
+            paths: Arc::new(paths),
+            walk: Arc::new(walk),
+            vfs: Arc::new(RealFs),
+            threads: 1,
+            counts: Arc::default(),
+        }
+    }
+
+    /// Walks this many directories at once, which may be under the same path
+    pub fn threads(mut self, threads: usize) -> FileWalker {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Walks `vfs` rather than the disk
+    pub fn vfs(mut self, vfs: Arc<dyn Vfs>) -> FileWalker {
+        self.vfs = vfs;
+        self
+    }
+
+    /// Starts the walk, returning the channel the files come through, which
+    /// closes once it's over. One thread finds the files in the same order
+    /// as `find_files`, while more share out the directories as they're
+    /// found, so the files under a path come in any order. Sorted files can
+    /// only be sent once they're all found. Dropping the receiver ends the
+    /// walk.
+    pub fn spawn(&self) -> mpsc::Receiver<MyResult<PathBuf>> {
+        let (sender, receiver) = mpsc::channel();
+        let (paths, walk, vfs) = (self.paths.clone(), self.walk.clone(), self.vfs.clone());
+        let (threads, counts) = (self.threads, self.counts.clone());
+
+        thread::spawn(move || {
+            let vfs = &*vfs;
+            let mut emit = |file| counts.send(&sender, file);
+            if walk.sort.is_some() {
+                let (files, n) = find_files_in(vfs, &paths, &walk);
+                counts.skipped.fetch_add(n, Ordering::Relaxed);
+                for file in files {
+                    if !emit(file) {
+                        break;
+                    }
+                }
+            } else if threads == 1 {
+                for path in paths.iter() {
+                    let mut n = 0;
+                    let more = walk_path(vfs, path, &walk, &mut n, &mut emit);
+                    counts.skipped.fetch_add(n, Ordering::Relaxed);
+                    if !more {
+                        break;
+                    }
+                }
+            } else {
+                walk_parallel(vfs, &paths, &walk, threads, &sender, &counts);
+            }
+        });
+        receiver
+    }
+
+    /// How many files have been found to search so far
+    pub fn found(&self) -> usize {
+        self.counts.found.load(Ordering::Relaxed)
+    }
+
+    /// How many files have been left out for their size so far
+    pub fn skipped(&self) -> usize {
+        self.counts.skipped.load(Ordering::Relaxed)
+    }
+}
+
+/// A directory waiting for a thread to walk it
+struct Job<'a> {
+    root: Arc<Root<'a>>,
+    dir: PathBuf,
+    depth: usize,
+    ancestors: Vec<PathBuf>,
+}
+
+/// The directories shared out between the threads of a walk
+struct Queue<'a> {
+    state: Mutex<QueueState<'a>>,
+    changed: Condvar,
+}
+
+struct QueueState<'a> {
+    /// Taken from the end, so the walk goes deep before it goes wide
+    jobs: Vec<Job<'a>>,
+    /// How many threads are walking a directory, and so might add more
+    busy: usize,
+    stopped: bool,
+}
+
+impl<'a> Queue<'a> {
+    /// The next directory to walk, waiting while another thread could
+    /// still find one. `None` once the walk is over.
+    fn pop(&self) -> Option<Job<'a>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.stopped {
+                return None;
+            }
+            if let Some(job) = state.jobs.pop() {
+                state.busy += 1;
+                return Some(job);
+            }
+            if state.busy == 0 {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Adds the directories found walking one, in the order they were
+    /// found, or stops the walk unless there's `more` to do
+    fn done(&self, found: Vec<Job<'a>>, more: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.jobs.extend(found.into_iter().rev());
+        state.busy -= 1;
+        state.stopped |= !more;
+        self.changed.notify_all();
+    }
+}
+
+/// Walks the paths on `threads` threads, any of which may take any of the
+/// directories found. The paths themselves are looked at first, in order.
+fn walk_parallel(
+    vfs: &dyn Vfs,
+    paths: &[PathBuf],
+    walk: &WalkOptions,
+    threads: usize,
+    sender: &Sender<MyResult<PathBuf>>,
+    counts: &Counts,
+) {
+    let mut jobs = vec![];
+    let mut skipped = 0;
+    for path in paths {
+        let mut emit = |file| counts.send(sender, file);
+        match Root::new(vfs, path, walk, &mut skipped, &mut emit) {
+            ControlFlow::Continue(root) => jobs.push(Job {
+                root: Arc::new(root),
+                dir: path.clone(),
+                depth: 0,
+                ancestors: vec![],
+            }),
+            ControlFlow::Break(true) => {}
+            ControlFlow::Break(false) => break,
+        }
+    }
+    counts.skipped.fetch_add(skipped, Ordering::Relaxed);
+    jobs.reverse();
+    let queue = Queue {
+        state: Mutex::new(QueueState {
+            jobs,
+            busy: 0,
+            stopped: false,
+        }),
+        changed: Condvar::new(),
+    };
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let (queue, sender) = (&queue, sender.clone());
+            scope.spawn(move || {
+                let mut emit = |file| counts.send(&sender, file);
+                while let Some(job) = queue.pop() {
+                    let mut found = vec![];
+                    let mut defer = |dir, depth, ancestors| {
+                        found.push(Job {
+                            root: job.root.clone(),
+                            dir,
+                            depth,
+                            ancestors,
+                        })
+                    };
+                    let mut skipped = 0;
+                    let more = Walker {
+                        root: &job.root,
+                        ancestors: job.ancestors,
+                        skipped: &mut skipped,
+                        emit: &mut emit,
+                        defer: Some(&mut defer),
+                    }
+                    .walk_dir(&job.dir, job.depth);
+                    counts.skipped.fetch_add(skipped, Ordering::Relaxed);
+                    queue.done(found, more);
+                }
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileWalker;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use crate::{build_globs, find_files, find_files_in, MemoryFs, SortBy, WalkOptions};
+
+    #[test]
+    fn test_spawn() {
+        let paths: Vec<PathBuf> = [
+            "./tests/inputs",
+            "./tests/inputs/fox.txt",
+            "./tests/nonexistent",
+        ]
+        .map(PathBuf::from)
+        .into();
+        let walk = || WalkOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        let expected: Vec<_> = find_files(&paths, &walk())
+            .0
+            .into_iter()
+            .map(|file| file.map_err(|e| e.to_string()))
+            .collect();
+
+        // One thread finds the same files in the same order
+        let walker = FileWalker::new(paths.clone(), walk());
+        let found: Vec<_> = walker
+            .spawn()
+            .into_iter()
+            .map(|file| file.map_err(|e| e.to_string()))
+            .collect();
+        assert_eq!(found, expected);
+
+        // More only change the order
+        let walker = FileWalker::new(paths.clone(), walk()).threads(3);
+        let mut found: Vec<_> = walker
+            .spawn()
+            .into_iter()
+            .map(|file| file.map_err(|e| e.to_string()))
+            .collect();
+        let mut expected = expected;
+        found.sort();
+        expected.sort();
+        assert_eq!(found, expected);
+
+        // Sorting waits for every file
+        let sorted = WalkOptions {
+            sort: Some(SortBy::Path),
+            ..walk()
+        };
+        let walker = FileWalker::new(paths, sorted);
+        let found: Vec<_> = walker.spawn().into_iter().filter_map(Result::ok).collect();
+        let mut expected = found.clone();
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_spawn_shared_dirs() {
+        // One path, so the threads can only share its directories
+        let mut vfs = MemoryFs::new();
+        for a in 0..5 {
+            for b in 0..5 {
+                vfs.insert(format!("t/{a}/{b}/x.txt"), "fox\n");
+                vfs.insert(format!("t/{a}/{b}/y.rs"), "dog\n");
+            }
+        }
+        vfs.insert(".git/HEAD", "ref\n");
+        vfs.insert("t/.gitignore", "*.rs\n3/\n");
+        let walk = || WalkOptions {
+            recursive: true,
+            max_depth: Some(3),
+            exclude_dir: build_globs(["1"]).unwrap(),
+            ..Default::default()
+        };
+        let paths = vec![PathBuf::from("t")];
+        let mut expected: Vec<_> = find_files_in(&vfs, &paths, &walk())
+            .0
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(expected.len(), 9);
+
+        let walker = FileWalker::new(paths, walk()).vfs(Arc::new(vfs)).threads(4);
+        let mut found: Vec<_> = walker.spawn().into_iter().map(Result::unwrap).collect();
+        found.sort();
+        expected.sort();
+        assert_eq!(found, expected);
+        assert_eq!(walker.found(), 9);
+    }
+}
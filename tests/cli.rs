@@ -42,7 +42,7 @@ fn dies_no_args() -> TestResult {
 #[test]
 fn dies_bad_pattern() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&["*foo", FOX])
+        .args(["*foo", FOX])
         .assert()
         .failure()
         .stderr(predicate::str::contains("Invalid pattern \"*foo\""));
@@ -55,7 +55,7 @@ fn warns_bad_file() -> TestResult {
     let bad = gen_bad_file();
     let expected = format!("{}: .* [(]os error 2[)]", bad);
     Command::cargo_bin(PRG)?
-        .args(&["foo", &bad])
+        .args(["foo", &bad])
         .assert()
         .stderr(predicate::str::is_match(expected)?);
     Ok(())
@@ -72,7 +72,7 @@ fn run(args: &[&str], expected_file: &str) -> TestResult {
         expected_file
     };
 
-    let expected = fs::read_to_string(&expected_file)?;
+    let expected = fs::read_to_string(expected_file)?;
 
     Command::cargo_bin(PRG)?
         .args(args)
@@ -168,6 +168,353 @@ fn recursive_insensitive() -> TestResult {
     )
 }
 
+// --------------------------------------------------
+#[test]
+fn threads() -> TestResult {
+    // Output comes in the same order however many files are searched at once
+    let sequential = Command::cargo_bin(PRG)?
+        .args(["-j", "1", "-r", "-n", "-C", "1", "-i", "the", INPUTS_DIR])
+        .output()?;
+    let parallel = Command::cargo_bin(PRG)?
+        .args(["-j", "4", "-r", "-n", "-C", "1", "-i", "the", INPUTS_DIR])
+        .output()?;
+    assert!(!sequential.stdout.is_empty());
+    assert_eq!(sequential.stdout, parallel.stdout);
+
+    // Even with many more files than are searched ahead of the output
+    let files: Vec<_> = [FOX, BUSTLE, EMPTY].repeat(10);
+    let sequential = Command::cargo_bin(PRG)?
+        .args(["-j", "1", "-c", "the"])
+        .args(&files)
+        .output()?;
+    let parallel = Command::cargo_bin(PRG)?
+        .args(["-j", "2", "-c", "the"])
+        .args(&files)
+        .output()?;
+    assert_eq!(String::from_utf8_lossy(&sequential.stdout).lines().count(), 30);
+    assert_eq!(sequential.stdout, parallel.stdout);
+
+    // Errors are still reported for the files that can't be read
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["--threads", "3", "fox", FOX, &bad, BUSTLE])
+        .assert()
+        .failure()
+        .stdout("tests/inputs/fox.txt:The quick brown fox jumps over the lazy dog.\n")
+        .stderr(predicate::str::starts_with(format!("{}: ", bad)));
+
+    Command::cargo_bin(PRG)?
+        .args(["-j", "many", "fox", FOX])
+        .assert()
+        .failure()
+        .stderr("Invalid thread count \"many\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn mmap() -> TestResult {
+    // Only files of a megabyte or more are mapped
+    let path = std::env::temp_dir().join(gen_bad_file());
+    let mut text = "filler line\n".repeat(100_000);
+    text.push_str("needle\nlast line without a newline");
+    fs::write(&path, &text)?;
+    let path = path.display().to_string();
+
+    for args in [vec!["--mmap"], vec![]] {
+        Command::cargo_bin(PRG)?
+            .args(&args)
+            .args(["-n", "-A", "1", "needle", &path])
+            .assert()
+            .success()
+            .stdout("100001:needle\n100002-last line without a newline");
+    }
+
+    // Small files and stdin are read as usual
+    Command::cargo_bin(PRG)?
+        .args(["--mmap", "fox", FOX, "-"])
+        .write_stdin("a fox\n")
+        .assert()
+        .success()
+        .stdout(concat!(
+            "tests/inputs/fox.txt:The quick brown fox jumps over the lazy dog.\n",
+            "-:a fox\n",
+        ));
+
+    fs::remove_file(&path)?;
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_memory() -> TestResult {
+    let path = std::env::temp_dir().join(gen_bad_file());
+    let long = "x".repeat(100);
+    fs::write(&path, format!("a fox\n{0}\nfox {0} fox\nfox\n", long))?;
+    let path = path.display().to_string();
+    let notice = format!(
+        "{}: 2 lines cut short to fit the memory limit of 10 bytes\n",
+        path
+    );
+
+    // Lines too long to hold are searched as far as they fit, and the rest
+    // of the file as usual
+    Command::cargo_bin(PRG)?
+        .args(["--max-memory", "10", "fox", &path])
+        .assert()
+        .success()
+        .stdout("a fox\nfox xxxxxx\nfox\n")
+        .stderr(notice.clone());
+    Command::cargo_bin(PRG)?
+        .args(["--max-memory", "10", "-c", "fox", &path])
+        .assert()
+        .success()
+        .stdout("3\n")
+        .stderr(notice.clone());
+    Command::cargo_bin(PRG)?
+        .args(["--max-memory", "10", "--json", "fox", &path])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#"{"type":"end","#))
+        .stdout(predicate::str::contains(r#""searches_with_match":1,"#));
+
+    // As is input searched whole
+    Command::cargo_bin(PRG)?
+        .args(["--max-memory", "10", "-U", "fox", &path])
+        .assert()
+        .success()
+        .stdout("a fox\n")
+        .stderr(format!(
+            "{}: input cut short to fit the memory limit of 10 bytes\n",
+            path
+        ));
+
+    // Context and --unique keep what there's room for
+    Command::cargo_bin(PRG)?
+        .args(["--max-memory", "20", "-B", "2", "fox"])
+        .write_stdin("aaaa\nbbbb\nfox\n")
+        .assert()
+        .success()
+        .stdout("bbbb\nfox\n");
+    Command::cargo_bin(PRG)?
+        .args(["--max-memory", "40", "--unique-count", "fox"])
+        .write_stdin("fox\nfox\na fox\nfox\n")
+        .assert()
+        .success()
+        .stdout("      2 fox\n      1 a fox\n      1 fox\n");
+
+    // Output that can't be held for the files before it is printed as it's
+    // found once its turn comes
+    let lines: String = (0..1000).map(|i| format!("{} fox\n", i)).collect();
+    fs::write(&path, &lines)?;
+    let expected = format!(
+        "{0}:The quick brown fox jumps over the lazy dog.\n{1}",
+        FOX,
+        lines
+            .lines()
+            .map(|line| format!("{}:{}\n", path, line))
+            .collect::<String>()
+    );
+    for threads in ["1", "2"] {
+        Command::cargo_bin(PRG)?
+            .args(["-j", threads, "--max-memory", "1K", "fox", FOX, &path, FOX])
+            .assert()
+            .success()
+            .stdout(format!(
+                "{}{}:The quick brown fox jumps over the lazy dog.\n",
+                expected, FOX
+            ));
+    }
+
+    Command::cargo_bin(PRG)?
+        .args(["--max-memory", "lots", "fox", FOX])
+        .assert()
+        .failure()
+        .stderr("Invalid memory limit \"lots\"\n");
+
+    fs::remove_file(&path)?;
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn sort() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-r", "-c", "--sort", "size", "the", INPUTS_DIR])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "tests/inputs/empty.txt:0\n",
+            "tests/inputs/fox.txt:1\n",
+            "tests/inputs/bustle.txt:1\n",
+            "tests/inputs/nobody.txt:3\n",
+        ));
+
+    Command::cargo_bin(PRG)?
+        .args(["-r", "-l", "--sortr", "path", "the", INPUTS_DIR])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "tests/inputs/nobody.txt\n",
+            "tests/inputs/fox.txt\n",
+            "tests/inputs/bustle.txt\n",
+        ));
+
+    Command::cargo_bin(PRG)?
+        .args(["--sort", "path", "--sortr", "size", "the", FOX])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_include() -> TestResult {
+    run(
+        &["-ri", "--include", "n*", "then", INPUTS_DIR],
+        "tests/expected/the.recursive.insensitive.include",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_include_no_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "-r",
+            "--include",
+            "b*",
+            "--include",
+            "n*",
+            "dog",
+            INPUTS_DIR,
+        ])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_exclude() -> TestResult {
+    run(
+        &["-ri", "--exclude", "[bef]*", "then", INPUTS_DIR],
+        "tests/expected/the.recursive.insensitive.include",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_exclude_dir() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--exclude-dir", "exp*", "dog", "tests"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fox.txt:The quick brown fox"))
+        .stdout(predicate::str::is_match("(?m)^tests.expected")?.not());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_include() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--include", "[", "dog", INPUTS_DIR])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid glob \"[\""));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(unix)]
+#[test]
+fn dereference_recursive() -> TestResult {
+    use std::os::unix::fs::symlink;
+
+    let dir = std::env::temp_dir().join(gen_bad_file());
+    fs::create_dir_all(dir.join("tree"))?;
+    symlink(fs::canonicalize(INPUTS_DIR)?, dir.join("tree/inputs"))?;
+    symlink(dir.join("tree"), dir.join("tree/loop"))?;
+    let tree = dir.join("tree").display().to_string();
+
+    // -r leaves the links alone
+    Command::cargo_bin(PRG)?
+        .args(["-r", "dog", &tree])
+        .assert()
+        .code(1)
+        .stdout("");
+
+    // -R follows them, and the cycle is reported rather than followed forever
+    Command::cargo_bin(PRG)?
+        .args(["-R", "-h", "dog", &tree])
+        .assert()
+        .failure()
+        .stdout("The quick brown fox jumps over the lazy dog.\n")
+        .stderr(predicate::str::contains("loop"));
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_depth() -> TestResult {
+    // The inputs are two levels below ./tests
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--max-depth", "1", "-l", "lazy dog", "tests"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fox.txt").not());
+
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--max-depth", "2", "-l", "lazy dog", "tests"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fox.txt"));
+
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--max-depth", "deep", "dog", "tests"])
+        .assert()
+        .failure()
+        .stderr("Invalid max depth \"deep\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn list_files() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "--files",
+            "-r",
+            "--include",
+            "*.txt",
+            "--exclude",
+            "e*",
+            INPUTS_DIR,
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fox.txt"))
+        .stdout(predicate::str::contains("empty.txt").not());
+
+    // Every positional argument is a path, and -Z separates them with NUL
+    Command::cargo_bin(PRG)?
+        .args(["--files", "-Z", FOX, NOBODY])
+        .assert()
+        .success()
+        .stdout("tests/inputs/fox.txt\0tests/inputs/nobody.txt\0");
+
+    Command::cargo_bin(PRG)?
+        .args(["--grep-compat", "--files", INPUTS_DIR])
+        .assert()
+        .failure()
+        .stderr("tests/inputs is a directory\n");
+    Ok(())
+}
+
 // --------------------------------------------------
 #[test]
 fn sensitive_count_capital() -> TestResult {
@@ -230,50 +577,2243 @@ fn insensitive_count_multiple() -> TestResult {
 
 // --------------------------------------------------
 #[test]
-fn warns_dir_not_recursive() -> TestResult {
-    let stdout = "tests/inputs/fox.txt:\
-        The quick brown fox jumps over the lazy dog.";
+fn line_number() -> TestResult {
+    run(
+        &["-n", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase.line_number",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn line_number_multiple() -> TestResult {
+    run(
+        &["--line-number", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.line_number",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_context() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&["fox", INPUTS_DIR, FOX])
+        .args(["-A", "x", "foo", FOX])
         .assert()
-        .stderr(predicate::str::contains("tests/inputs is a directory"))
-        .stdout(predicate::str::contains(stdout));
+        .failure()
+        .stderr(predicate::str::contains("Invalid context length \"x\""));
     Ok(())
 }
 
 // --------------------------------------------------
 #[test]
-fn stdin() -> TestResult {
-    let input = fs::read_to_string(BUSTLE)?;
-    let expected =
-        fs::read_to_string("tests/expected/bustle.txt.the.capitalized")?;
+fn context() -> TestResult {
+    run(
+        &["-C", "1", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase.context",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn after_context_multiple() -> TestResult {
+    run(
+        &["-n", "-A", "1", "The", BUSTLE, FOX],
+        "tests/expected/bustle.fox.the.capitalized.after_context",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn group_separator() -> TestResult {
+    let args = ["-A", "1", "-e", "morning", "-e", "heart", BUSTLE, FOX];
+    Command::cargo_bin(PRG)?
+        .args(["--group-separator", "~~"])
+        .args(args)
+        .assert()
+        .success()
+        .stdout(concat!(
+            "tests/inputs/bustle.txt:The morning after death\n",
+            "tests/inputs/bustle.txt-Is solemnest of industries\n",
+            "~~\n",
+            "tests/inputs/bustle.txt:The sweeping up the heart,\n",
+            "tests/inputs/bustle.txt-And putting love away\n",
+        ));
 
+    // Given last, --no-group-separator leaves out the line altogether
     Command::cargo_bin(PRG)?
-        .arg("The")
-        .write_stdin(input)
+        .args(["--group-separator", "~~", "--no-group-separator"])
+        .args(args)
         .assert()
-        .stdout(expected);
+        .success()
+        .stdout(concat!(
+            "tests/inputs/bustle.txt:The morning after death\n",
+            "tests/inputs/bustle.txt-Is solemnest of industries\n",
+            "tests/inputs/bustle.txt:The sweeping up the heart,\n",
+            "tests/inputs/bustle.txt-And putting love away\n",
+        ));
+
+    // An empty separator still leaves a blank line
+    Command::cargo_bin(PRG)?
+        .args(["--group-separator="])
+        .args(args)
+        .assert()
+        .success()
+        .stdout(concat!(
+            "tests/inputs/bustle.txt:The morning after death\n",
+            "tests/inputs/bustle.txt-Is solemnest of industries\n",
+            "\n",
+            "tests/inputs/bustle.txt:The sweeping up the heart,\n",
+            "tests/inputs/bustle.txt-And putting love away\n",
+        ));
     Ok(())
 }
 
 // --------------------------------------------------
 #[test]
-fn stdin_insensitive_count() -> TestResult {
-    let files = &[BUSTLE, EMPTY, FOX, NOBODY];
+fn before_context() -> TestResult {
+    run(
+        &["-n", "--before-context", "1", "ow", NOBODY],
+        "tests/expected/nobody.txt.ow.before_context",
+    )
+}
 
-    let mut input = String::new();
-    for file in files {
-        input += &fs::read_to_string(file)?;
-    }
+// --------------------------------------------------
+#[test]
+fn files_with_matches() -> TestResult {
+    run(
+        &["-l", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.files_with_matches",
+    )
+}
 
-    let expected_file =
-        "tests/expected/the.recursive.insensitive.count.stdin";
-    let expected = fs::read_to_string(expected_file)?;
+// --------------------------------------------------
+#[test]
+fn files_with_matches_recursive() -> TestResult {
+    run(
+        &["-ri", "--files-with-matches", "dog", INPUTS_DIR],
+        "tests/expected/dog.recursive.files_with_matches",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn files_without_match() -> TestResult {
+    run(
+        &["-L", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.files_without_match",
+    )
+}
 
+// --------------------------------------------------
+#[test]
+fn dies_files_with_and_without_match() -> TestResult {
     Command::cargo_bin(PRG)?
-        .args(&["-ci", "the", "-"])
-        .write_stdin(input)
+        .args(["-l", "-L", "The", BUSTLE])
         .assert()
-        .stdout(expected);
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn only_matching() -> TestResult {
+    run(
+        &["-o", "-i", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase.insensitive.only_matching",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn only_matching_line_number() -> TestResult {
+    run(
+        &["--only-matching", "-n", "N[a-z]+", FOX, NOBODY],
+        "tests/expected/fox.nobody.capitalized_word.only_matching",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn word_regexp() -> TestResult {
+    run(
+        &["-w", "-i", "you", NOBODY],
+        "tests/expected/nobody.txt.you.word_regexp",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn word_regexp_invert_count() -> TestResult {
+    run(
+        &["--word-regexp", "-v", "-c", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.word_regexp.invert.count",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn line_regexp_alternation() -> TestResult {
+    run(
+        &[
+            "-xi",
+            "the sweeping up the heart,|and putting love away",
+            BUSTLE,
+        ],
+        "tests/expected/bustle.txt.alternation.line_regexp",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn line_regexp_empty() -> TestResult {
+    run(
+        &["--line-regexp", "-n", "", BUSTLE],
+        "tests/expected/bustle.txt.empty.line_regexp",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn fixed_strings() -> TestResult {
+    run(
+        &["-F", "?", NOBODY, FOX],
+        "tests/expected/nobody.fox.question_mark.fixed_strings",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn fixed_strings_bad_regex() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--fixed-strings", "-c", "*foo", FOX])
+        .assert()
+        .code(1)
+        .stdout("0\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_patterns() -> TestResult {
+    run(
+        &["-e", "fox", "--regexp", "Nobody", FOX, NOBODY],
+        "tests/expected/fox.nobody.multiple_patterns",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_patterns_stdin() -> TestResult {
+    let input = fs::read_to_string(BUSTLE)?;
+    let expected =
+        fs::read_to_string("tests/expected/bustle.txt.the.lowercase.count")?;
+
+    Command::cargo_bin(PRG)?
+        .args(["-c", "-e", "sweeping", "-e", "up the"])
+        .write_stdin(input)
+        .assert()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_pattern_multiple() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-e", "fox", "-e", "*foo", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid pattern \"*foo\""));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn patterns_from_file() -> TestResult {
+    let expected =
+        fs::read_to_string("tests/expected/fox.nobody.multiple_patterns")?;
+
+    Command::cargo_bin(PRG)?
+        .args(["-f", "-", FOX, NOBODY])
+        .write_stdin("fox\nNobody\n")
+        .assert()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn many_fixed_strings() -> TestResult {
+    // A blocklist far too big to compile as one regex
+    let mut patterns: Vec<String> = (0..50_000).map(|i| format!("word{}", i)).collect();
+    patterns.push("lazy".to_string());
+    patterns.push("a.b".to_string());
+    Command::cargo_bin(PRG)?
+        .args(["-F", "-o", "-f", "-", FOX])
+        .write_stdin(patterns.join("\n"))
+        .assert()
+        .success()
+        .stdout("lazy\n");
+
+    // Metacharacters are still taken literally
+    Command::cargo_bin(PRG)?
+        .args(["-F", "-c", "-e", "a.b", "-e", "word1"])
+        .write_stdin("a.b\naxb\nword123\n")
+        .assert()
+        .success()
+        .stdout("2\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_pattern_file() -> TestResult {
+    let bad = gen_bad_file();
+    let expected = format!("{}: .* [(]os error 2[)]", bad);
+    Command::cargo_bin(PRG)?
+        .args(["-f", &bad, FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::is_match(expected)?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count() -> TestResult {
+    run(
+        &["-m", "1", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.max_count",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--max-count", "2", "-ci", "the", BUSTLE])
+        .assert()
+        .success()
+        .stdout("2\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_max_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-m", "many", "foo", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid max count \"many\""));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn quiet() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-q", "The", BUSTLE, FOX])
+        .assert()
+        .success()
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn quiet_stops_at_first_match() -> TestResult {
+    // The bad file is never opened, so nothing is reported for it
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["--quiet", "The", BUSTLE, &bad])
+        .assert()
+        .success()
+        .stdout("")
+        .stderr("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_messages() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["-s", "fox", &bad, FOX])
+        .assert()
+        .failure()
+        .stdout(format!(
+            "{}:The quick brown fox jumps over the lazy dog.\n",
+            FOX
+        ))
+        .stderr("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_messages_directory() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--grep-compat", "--no-messages", "fox", INPUTS_DIR])
+        .assert()
+        .failure()
+        .stderr("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn color_always() -> TestResult {
+    run(
+        &["--color=always", "-n", "the", BUSTLE, FOX],
+        "tests/expected/bustle.fox.the.lowercase.color",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn color_always_ignores_no_color() -> TestResult {
+    let expected =
+        fs::read_to_string("tests/expected/bustle.fox.the.lowercase.color")?;
+
+    Command::cargo_bin(PRG)?
+        .args(["--colour=always", "-n", "the", BUSTLE, FOX])
+        .env("NO_COLOR", "1")
+        .assert()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn color_grep_colors() -> TestResult {
+    let expected = fs::read_to_string(
+        "tests/expected/bustle.txt.the.lowercase.grep_colors",
+    )?;
+
+    Command::cargo_bin(PRG)?
+        .args(["--color=always", "-n", "-C", "1", "the", BUSTLE])
+        .env("GREP_COLORS", "ms=04:fn=:ln=33")
+        .assert()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn color_auto_not_a_tty() -> TestResult {
+    run(
+        &["--color", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn color_never() -> TestResult {
+    run(
+        &["--color=never", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn color_every_match() -> TestResult {
+    // Each occurrence on the line is highlighted, not only the first
+    let hl = |text: &str| format!("\x1b[01;31m\x1b[K{}\x1b[m\x1b[K", text);
+    Command::cargo_bin(PRG)?
+        .args(["--color=always", "fox|o", FOX])
+        .assert()
+        .success()
+        .stdout(format!(
+            "The quick br{}wn {} jumps {}ver the lazy d{}g.\n",
+            hl("o"),
+            hl("fox"),
+            hl("o"),
+            hl("o")
+        ));
+
+    // A match across lines is highlighted on each of them
+    Command::cargo_bin(PRG)?
+        .args(["--color=always", "-U", r"heart,\nAnd", BUSTLE])
+        .assert()
+        .success()
+        .stdout(format!(
+            "The sweeping up the {}\n{} putting love away\n",
+            hl("heart,"),
+            hl("And")
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn with_filename() -> TestResult {
+    run(
+        &["-H", "-c", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.count.with_filename",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn no_filename() -> TestResult {
+    run(
+        &["--no-filename", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.no_filename",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn filename_last_flag_wins() -> TestResult {
+    run(
+        &["-H", "-h", "The", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.capitalized.no_filename",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn null_files_with_matches() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-lZ", "The", BUSTLE, EMPTY, FOX])
+        .assert()
+        .success()
+        .stdout(format!("{}\0{}\0", BUSTLE, FOX));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn null_prefix() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--null", "-n", "fox", FOX, EMPTY])
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}\01:The quick brown fox jumps over the lazy dog.\n",
+            FOX
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn null_data() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-z", "^t"])
+        .write_stdin("one\0two\nlines\0three")
+        .assert()
+        .success()
+        .stdout("two\nlines\0three");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn crlf() -> TestResult {
+    // Without --crlf the CR stands between the match and the end of the line
+    Command::cargo_bin(PRG)?
+        .args(["-n", "foo$"])
+        .write_stdin("foo\r\nbar\r\nfoo\n")
+        .assert()
+        .success()
+        .stdout("3:foo\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--crlf", "-n", "foo$"])
+        .write_stdin("foo\r\nbar\r\nfoo\n")
+        .assert()
+        .success()
+        .stdout("1:foo\n3:foo\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--crlf", "-x", "-c", "bar"])
+        .write_stdin("foo\r\nbar\r\nfoo\n")
+        .assert()
+        .success()
+        .stdout("1\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn encoding() -> TestResult {
+    // UTF-16 is recognized by its BOM rather than taken for binary
+    let mut utf16 = vec![0xff, 0xfe];
+    utf16.extend("hello fox\nbye\n".encode_utf16().flat_map(u16::to_le_bytes));
+    Command::cargo_bin(PRG)?
+        .args(["fox"])
+        .write_stdin(utf16)
+        .assert()
+        .success()
+        .stdout("hello fox\n");
+
+    let utf16be: Vec<u8> = "hello fox\n".encode_utf16().flat_map(u16::to_be_bytes).collect();
+    Command::cargo_bin(PRG)?
+        .args(["--encoding", "utf-16be", "-o", "f.x"])
+        .write_stdin(utf16be)
+        .assert()
+        .success()
+        .stdout("fox\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--encoding", "latin1", "caf."])
+        .write_stdin(b"caf\xe9 au lait\n".to_vec())
+        .assert()
+        .success()
+        .stdout("café au lait\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--encoding", "klingon", "fox", FOX])
+        .assert()
+        .failure()
+        .stderr("Invalid encoding \"klingon\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn null_data_anchor() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--null-data", "-o", "-n", "[a-z]+$"])
+        .write_stdin("one\0two\nlines\0three")
+        .assert()
+        .success()
+        .stdout("1:one\x002:lines\x003:three\x00");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_matches() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .arg("foo")
+        .write_stdin("hello\0world\nfoo\n")
+        .assert()
+        .success()
+        .stdout("Binary file - matches\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_no_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .arg("bar")
+        .write_stdin("hello\0world\nfoo\n")
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_text() -> TestResult {
+    for flag in ["-a", "--binary-files=text"] {
+        Command::cargo_bin(PRG)?
+            .args([flag, "foo"])
+            .write_stdin("hello\0world\nfoo\n")
+            .assert()
+            .success()
+            .stdout("foo\n");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_without_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--binary-files", "without-match", "-l", "foo"])
+        .write_stdin("hello\0world\nfoo\n")
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn byte_offset() -> TestResult {
+    run(
+        &["-b", "The", BUSTLE, FOX],
+        "tests/expected/bustle.fox.the.capitalized.byte_offset",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn byte_offset_only_matching() -> TestResult {
+    run(
+        &["-n", "--byte-offset", "-o", "-i", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase.insensitive.byte_offset.only_matching",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn column() -> TestResult {
+    run(
+        &["-n", "--column", "the", BUSTLE, FOX],
+        "tests/expected/bustle.fox.the.lowercase.column",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn column_only_matching() -> TestResult {
+    run(
+        &["--column", "-o", "-i", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.lowercase.insensitive.column.only_matching",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn basic_regexp() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-G", r"^\(The\) \(quick\|lazy\)\+", FOX])
+        .assert()
+        .success()
+        .stdout("The quick brown fox jumps over the lazy dog.\n");
+
+    // Unescaped groups are literal in a BRE
+    Command::cargo_bin(PRG)?
+        .args(["-G", "(quick|lazy)", FOX])
+        .assert()
+        .code(1)
+        .stdout("");
+
+    Command::cargo_bin(PRG)?
+        .args(["-G", r"\(The\) \1", FOX])
+        .assert()
+        .failure()
+        .stderr("Invalid pattern \"\\(The\\) \\1\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn extended_regexp() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-E", "^(The) (quick|lazy)+ [[:alpha:]]", FOX])
+        .assert()
+        .success()
+        .stdout("The quick brown fox jumps over the lazy dog.\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["-E", "-G", "fox", FOX])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "perl")]
+#[test]
+fn perl_regexp() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-P", r"(?<=lazy )dog|(\w)\1(?=!)", FOX, NOBODY])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "tests/inputs/fox.txt:The quick brown fox jumps over the lazy dog.\n",
+            "tests/inputs/nobody.txt:Don't tell! they'd advertise—you know!\r\n",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "perl")]
+#[test]
+fn engine() -> TestResult {
+    // Only the pattern the default engine rejects goes to the fancy one
+    Command::cargo_bin(PRG)?
+        .args(["--engine", "auto", r"(\w)\1(?=!)", NOBODY])
+        .assert()
+        .success()
+        .stdout("Don't tell! they'd advertise—you know!\r\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--engine", "default", r"(\w)\1(?=!)", NOBODY])
+        .assert()
+        .code(2)
+        .stderr("Invalid pattern \"(\\w)\\1(?=!)\"\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--engine", "fancy", r"(?<=lazy )dog", FOX])
+        .assert()
+        .success();
+
+    // A pattern neither engine understands is reported as it was given
+    Command::cargo_bin(PRG)?
+        .args(["--engine", "auto", "(fox", FOX])
+        .assert()
+        .code(2)
+        .stderr("Invalid pattern \"(fox\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(not(feature = "perl"))]
+#[test]
+fn perl_regexp_unsupported() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-P", "fox", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("\"perl\" feature"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiline() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "-U", r"house\nThe \w+", BUSTLE])
+        .assert()
+        .success()
+        .stdout("1:The bustle in a house\n2:The morning after death\n");
+
+    // Each line prints its own part of the match
+    Command::cargo_bin(PRG)?
+        .args(["-o", "-U", r"earth,\W+The", BUSTLE])
+        .assert()
+        .success()
+        .stdout("earth,—\nThe\n");
+
+    // Without -U no single line can match
+    Command::cargo_bin(PRG)?
+        .args(["-c", r"house\nThe", BUSTLE])
+        .assert()
+        .code(1)
+        .stdout("0\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn smart_case() -> TestResult {
+    // All lowercase, so case is ignored
+    Command::cargo_bin(PRG)?
+        .args(["-S", "-c", "the", BUSTLE])
+        .assert()
+        .success()
+        .stdout("3\n");
+
+    // An uppercase letter makes the search case-sensitive again
+    Command::cargo_bin(PRG)?
+        .args(["-S", "-c", "T", BUSTLE])
+        .assert()
+        .success()
+        .stdout("3\n");
+
+    // Explicit flags win, and the last of -i and --case-sensitive counts
+    Command::cargo_bin(PRG)?
+        .args(["-S", "-i", "-c", "T", BUSTLE])
+        .assert()
+        .success()
+        .stdout("8\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["-S", "-i", "--case-sensitive", "-c", "the", BUSTLE])
+        .assert()
+        .success()
+        .stdout("1\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn count_matches() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--count-matches", "-i", "the", BUSTLE, FOX])
+        .assert()
+        .success()
+        .stdout("tests/inputs/bustle.txt:4\ntests/inputs/fox.txt:2\n");
+
+    // Inverted lines count once each
+    Command::cargo_bin(PRG)?
+        .args(["--count-matches", "-v", "-i", "the", BUSTLE])
+        .assert()
+        .success()
+        .stdout("6\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn count_total() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "--total", "-i", "the", BUSTLE, FOX, EMPTY])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "tests/inputs/bustle.txt:3\n",
+            "tests/inputs/fox.txt:1\n",
+            "tests/inputs/empty.txt:0\n",
+            "total:4\n",
+        ));
+
+    Command::cargo_bin(PRG)?
+        .args(["--count-matches", "--total", "-i", "the", BUSTLE, FOX])
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with("total:6\n"));
+
+    // Without -c there is nothing to add up
+    Command::cargo_bin(PRG)?
+        .args(["--total", "fox", FOX])
+        .assert()
+        .success()
+        .stdout("The quick brown fox jumps over the lazy dog.\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stats() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--stats", "-i", "the", BUSTLE, FOX, EMPTY])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(concat!(
+            "tests/inputs/fox.txt:The quick brown fox jumps over the lazy dog.\n",
+            "\n",
+            "6 matches\n",
+            "4 matched lines\n",
+            "2 files contained matches\n",
+            "3 files searched\n",
+            "238 bytes searched\n",
+        )))
+        .stdout(predicate::str::is_match(r"\n\d+\.\d{6} seconds\n$")?);
+
+    // Files left out for their size are counted
+    Command::cargo_bin(PRG)?
+        .args(["--stats", "--max-filesize", "100", "the", BUSTLE, FOX])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "1 files searched\n1 files skipped for their size\n",
+        ));
+
+    // With -l only whether each file matched is known
+    Command::cargo_bin(PRG)?
+        .args(["--stats", "-l", "fox", FOX, BUSTLE])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "1 files contained matches\n2 files searched\n",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn json() -> TestResult {
+    let output = Command::cargo_bin(PRG)?
+        .args(["--json", "-A", "1", "fox", FOX, EMPTY, BUSTLE])
+        .output()?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    let lines: Vec<_> = stdout.lines().collect();
+
+    // Only the file with a match is reported, followed by the summary
+    assert_eq!(lines.len(), 4);
+    assert_eq!(
+        lines[0],
+        r#"{"type":"begin","data":{"path":{"text":"tests/inputs/fox.txt"}}}"#
+    );
+    assert_eq!(
+        lines[1],
+        concat!(
+            r#"{"type":"match","data":{"path":{"text":"tests/inputs/fox.txt"},"#,
+            r#""lines":{"text":"The quick brown fox jumps over the lazy dog.\n"},"#,
+            r#""line_number":1,"absolute_offset":0,"#,
+            r#""submatches":[{"match":{"text":"fox"},"start":16,"end":19}]}}"#
+        )
+    );
+    assert!(lines[2].starts_with(r#"{"type":"end","data":{"path":{"text":"tests/inputs/fox.txt"}"#));
+    assert!(lines[2].contains(r#""bytes_searched":45,"#));
+    assert!(lines[3].starts_with(r#"{"type":"summary","#));
+    assert!(lines[3].contains(r#""searches":3,"searches_with_match":1,"#));
+
+    // Context lines are their own message type
+    Command::cargo_bin(PRG)?
+        .args(["--json", "-A", "1", "morning", BUSTLE])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            r#"{"type":"context","data":{"path":{"text":"tests/inputs/bustle.txt"},"lines":{"text":"Is solemnest of industries\n"},"line_number":3,"#,
+        ));
+
+    Command::cargo_bin(PRG)?
+        .args(["--json", "-c", "fox", FOX])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn passthru() -> TestResult {
+    let expected = fs::read_to_string(BUSTLE)?;
+    Command::cargo_bin(PRG)?
+        .args(["--passthru", "sweeping", BUSTLE])
+        .assert()
+        .success()
+        .stdout(expected.clone());
+
+    // Only the matches themselves are highlighted
+    Command::cargo_bin(PRG)?
+        .args(["--passthru", "--color=always", "sweeping", BUSTLE])
+        .assert()
+        .success()
+        .stdout(
+            expected
+                .replace("sweeping", "\x1b[01;31m\x1b[Ksweeping\x1b[m\x1b[K"),
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn replace() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--replace", "$2 $1", r"(\w+) (fox|dog)", FOX])
+        .assert()
+        .success()
+        .stdout("The quick fox brown jumps over the dog lazy.\n");
+
+    Command::cargo_bin(PRG)?
+        .args([
+            "-o",
+            "--replace",
+            "${first}!",
+            r"(?P<first>\w)\w+ \w+$",
+            BUSTLE,
+        ])
+        .assert()
+        .success()
+        .stdout("a!\no!\nl!\nu!\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn only_matching_group() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-o", "--group", "2", r"(\w+) (fox|dog)", FOX])
+        .assert()
+        .success()
+        .stdout("fox\ndog\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["-o", "--group", "adj", r"(?P<adj>\w+) (fox|dog)", FOX])
+        .assert()
+        .success()
+        .stdout("brown\nlazy\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["-o", "--group", "$1", "fox", FOX])
+        .assert()
+        .failure()
+        .stderr("Invalid group \"$1\"\n");
+
+    // A group the pattern doesn't have is a usage error
+    Command::cargo_bin(PRG)?
+        .args(["-o", "--group", "5", r"(\w+) (fox|dog)", FOX])
+        .assert()
+        .code(2)
+        .stdout("")
+        .stderr("No group \"5\" in the pattern\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["-o", "--group", "noun", r"(?P<adj>\w+) (fox|dog)", FOX])
+        .assert()
+        .code(2);
+
+    // The group is only printed on its own
+    Command::cargo_bin(PRG)?
+        .args(["--group", "1", "(fox)", FOX])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn vimgrep() -> TestResult {
+    // One line per match, each with its own column, and no context
+    Command::cargo_bin(PRG)?
+        .args(["--vimgrep", "-C", "1", "the", FOX, BUSTLE])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "tests/inputs/fox.txt:1:32:The quick brown fox jumps over the lazy dog.\n",
+            "tests/inputs/bustle.txt:6:17:The sweeping up the heart,\n",
+        ));
+
+    Command::cargo_bin(PRG)?
+        .args(["--vimgrep", "o", FOX])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "tests/inputs/fox.txt:1:13:The quick brown fox",
+        ))
+        .stdout(predicate::str::contains("\ntests/inputs/fox.txt:1:18:The"))
+        .stdout(predicate::function(|out: &str| out.lines().count() == 4));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn heading() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--heading", "-n", "-i", "the", FOX, EMPTY, BUSTLE])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "tests/inputs/fox.txt\n",
+            "1:The quick brown fox jumps over the lazy dog.\n",
+            "\n",
+            "tests/inputs/bustle.txt\n",
+            "1:The bustle in a house\n",
+            "2:The morning after death\n",
+            "6:The sweeping up the heart,\n",
+        ));
+
+    // A single file needs no heading, and --no-heading wins if given last
+    Command::cargo_bin(PRG)?
+        .args(["--heading", "fox", FOX])
+        .assert()
+        .success()
+        .stdout("The quick brown fox jumps over the lazy dog.\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--heading", "--no-heading", "fox", FOX, EMPTY])
+        .assert()
+        .success()
+        .stdout(
+            "tests/inputs/fox.txt:The quick brown fox jumps over the lazy dog.\n",
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn pretty() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-p", "fox", FOX, EMPTY])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "\x1b[35m\x1b[Ktests/inputs/fox.txt\x1b[m\x1b[K\n",
+            "\x1b[32m\x1b[K1\x1b[m\x1b[K\x1b[36m\x1b[K:\x1b[m\x1b[K",
+            "The quick brown \x1b[01;31m\x1b[Kfox\x1b[m\x1b[K jumps over the lazy dog.\n",
+        ));
+
+    // Each part can still be turned off on its own
+    Command::cargo_bin(PRG)?
+        .args(["-p", "--color=never", "--no-heading", "-N", "fox", FOX, EMPTY])
+        .assert()
+        .success()
+        .stdout(
+            "tests/inputs/fox.txt:The quick brown fox jumps over the lazy dog.\n",
+        );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn heading_context() -> TestResult {
+    // Groups within a file are still separated by "--"
+    Command::cargo_bin(PRG)?
+        .args(["--heading", "-A", "1", "morning|love", BUSTLE, FOX])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "tests/inputs/bustle.txt\n",
+            "The morning after death\n",
+            "Is solemnest of industries\n",
+            "--\n",
+            "And putting love away\n",
+            "We shall not want to use again\n",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn line_buffered() -> TestResult {
+    use std::io::{BufRead, BufReader, Write};
+    use std::process::Stdio;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    // Each line comes out while the input is still open
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args(["--line-buffered", "fox"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            sender.send(line.unwrap()).unwrap();
+        }
+    });
+
+    stdin.write_all(b"dog\nred fox\n")?;
+    stdin.flush()?;
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(10))?, "red fox");
+    drop(stdin);
+    child.wait()?;
+
+    // Block buffering changes nothing about what is printed
+    Command::cargo_bin(PRG)?
+        .args(["--line-buffered", "--block-buffered", "-c", "--stats", "the", BUSTLE])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("1\n\n1 matches\n"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn warns_dir_not_recursive() -> TestResult {
+    let stdout = "tests/inputs/fox.txt:\
+        The quick brown fox jumps over the lazy dog.";
+    Command::cargo_bin(PRG)?
+        .args(["--grep-compat", "fox", INPUTS_DIR, FOX])
+        .assert()
+        .stderr(predicate::str::contains("tests/inputs is a directory"))
+        .stdout(predicate::str::contains(stdout));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stdin() -> TestResult {
+    let input = fs::read_to_string(BUSTLE)?;
+    let expected =
+        fs::read_to_string("tests/expected/bustle.txt.the.capitalized")?;
+
+    Command::cargo_bin(PRG)?
+        .arg("The")
+        .write_stdin(input)
+        .assert()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stdin_insensitive_count() -> TestResult {
+    let files = &[BUSTLE, EMPTY, FOX, NOBODY];
+
+    let mut input = String::new();
+    for file in files {
+        input += &fs::read_to_string(file)?;
+    }
+
+    let expected_file =
+        "tests/expected/the.recursive.insensitive.count.stdin";
+    let expected = fs::read_to_string(expected_file)?;
+
+    Command::cargo_bin(PRG)?
+        .args(["-ci", "the", "-"])
+        .write_stdin(input)
+        .assert()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_filesize() -> TestResult {
+    // Only fox.txt and empty.txt are under 100 bytes
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--sort", "path", "--max-filesize", "100", "-c", "the", "tests/inputs"])
+        .assert()
+        .success()
+        .stdout("tests/inputs/empty.txt:0\ntests/inputs/fox.txt:1\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--max-filesize", "1K", "the", NOBODY])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("the"));
+
+    Command::cargo_bin(PRG)?
+        .args(["--max-filesize", "1x", "the", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(r#"Invalid file size "1x""#));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_columns() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-M", "30", "the", FOX, NOBODY])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "tests/inputs/fox.txt:[Omitted long line with 1 matches]\n",
+            "tests/inputs/nobody.txt:Then there's a pair of us!\r\n",
+            "tests/inputs/nobody.txt:[Omitted long line with 1 matches]\n",
+            "tests/inputs/nobody.txt:[Omitted long line with 1 matches]\n",
+        ));
+
+    Command::cargo_bin(PRG)?
+        .args(["--max-columns", "15", "--max-columns-preview", "fox", FOX])
+        .assert()
+        .success()
+        .stdout("The quick brown [... omitted]\n");
+
+    // The preview needs a limit
+    Command::cargo_bin(PRG)?
+        .args(["--max-columns-preview", "fox", FOX])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn trim() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--trim", "-n", "println"])
+        .write_stdin("fn main() {\n    \tprintln!(\"hi\");\n}\n")
+        .assert()
+        .success()
+        .stdout("2:println!(\"hi\");\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn hidden() -> TestResult {
+    let dir = std::env::temp_dir().join(gen_bad_file());
+    fs::create_dir_all(dir.join(".config"))?;
+    fs::write(dir.join(".config/settings"), "fox\n")?;
+    fs::write(dir.join("visible.txt"), "fox\n")?;
+    let dir_name = dir.display().to_string();
+
+    let skipped = Command::cargo_bin(PRG)?
+        .args(["-r", "-H", "-c", "fox", &dir_name])
+        .output()?;
+    let shown = Command::cargo_bin(PRG)?
+        .args(["-r", "-c", "--hidden", "--sort", "path", "fox", &dir_name])
+        .output()?;
+    fs::remove_dir_all(&dir)?;
+
+    assert_eq!(
+        String::from_utf8(skipped.stdout)?,
+        format!("{}/visible.txt:1\n", dir_name)
+    );
+    assert_eq!(
+        String::from_utf8(shown.stdout)?,
+        format!(
+            "{0}/.config/settings:1\n{0}/visible.txt:1\n",
+            dir_name
+        )
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn gitignore() -> TestResult {
+    let dir = std::env::temp_dir().join(gen_bad_file());
+    fs::create_dir_all(dir.join(".git"))?;
+    fs::create_dir_all(dir.join("build"))?;
+    fs::write(dir.join(".gitignore"), "*.log\nbuild/\n")?;
+    fs::write(dir.join("build/out.txt"), "fox\n")?;
+    fs::write(dir.join("debug.log"), "fox\n")?;
+    fs::write(dir.join("main.txt"), "fox\n")?;
+    fs::write(dir.join(".ignore"), "notes.txt\n")?;
+    fs::write(dir.join("notes.txt"), "fox\n")?;
+    let dir_name = dir.display().to_string();
+
+    let recursive = Command::cargo_bin(PRG)?
+        .args(["-r", "-H", "-c", "fox", &dir_name])
+        .output()?;
+    // Files named directly are searched regardless
+    let log = dir.join("debug.log").display().to_string();
+    let named = Command::cargo_bin(PRG)?.args(["-c", "fox", &log]).output()?;
+    let no_ignore = Command::cargo_bin(PRG)?
+        .args(["-r", "-c", "--sort", "path", "--no-ignore", "fox", &dir_name])
+        .output()?;
+    let no_ignore_dot = Command::cargo_bin(PRG)?
+        .args(["-r", "-c", "--sort", "path", "--no-ignore-dot", "fox", &dir_name])
+        .output()?;
+    fs::remove_dir_all(&dir)?;
+
+    assert_eq!(
+        String::from_utf8(recursive.stdout)?,
+        format!("{}/main.txt:1\n", dir_name)
+    );
+    assert_eq!(String::from_utf8(named.stdout)?, "1\n");
+    assert_eq!(
+        String::from_utf8(no_ignore.stdout)?,
+        format!(
+            "{0}/build/out.txt:1\n{0}/debug.log:1\n{0}/main.txt:1\n{0}/notes.txt:1\n",
+            dir_name
+        )
+    );
+    // Only turning off .ignore files leaves the .gitignore in force
+    assert_eq!(
+        String::from_utf8(no_ignore_dot.stdout)?,
+        format!("{0}/main.txt:1\n{0}/notes.txt:1\n", dir_name)
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn file_types() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-r", "-c", "--type-add", "fox:fox.*", "-t", "fox", "the"])
+        .arg(INPUTS_DIR)
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["-r", "-l", "--type-not", "txt", "the", INPUTS_DIR])
+        .assert()
+        .code(1)
+        .stdout("");
+
+    Command::cargo_bin(PRG)?
+        .args(["-t", "nope", "the", FOX])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(r#"Unknown file type "nope""#));
+
+    Command::cargo_bin(PRG)?
+        .args(["--type-list", "--type-add", "tf:*.tf"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("rust: *.rs\n"))
+        .stdout(predicate::str::contains("tf: *.tf\n"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn config_file() -> TestResult {
+    let config = std::env::temp_dir().join(gen_bad_file());
+    fs::write(&config, "# Always ignore case\n-i\n--max-count\n1\n")?;
+
+    let with_config = Command::cargo_bin(PRG)?
+        .env("GREPR_CONFIG_PATH", &config)
+        .args(["-c", "THE", NOBODY])
+        .output()?;
+    // The command line overrides the config file
+    let overridden = Command::cargo_bin(PRG)?
+        .env("GREPR_CONFIG_PATH", &config)
+        .args(["-c", "-m", "5", "THE", NOBODY])
+        .output()?;
+    let no_config = Command::cargo_bin(PRG)?
+        .env("GREPR_CONFIG_PATH", &config)
+        .args(["--no-config", "-c", "THE", NOBODY])
+        .output()?;
+    fs::remove_file(&config)?;
+
+    assert_eq!(String::from_utf8(with_config.stdout)?, "1\n");
+    assert_eq!(String::from_utf8(overridden.stdout)?, "3\n");
+    assert_eq!(String::from_utf8(no_config.stdout)?, "0\n");
+
+    Command::cargo_bin(PRG)?
+        .env("GREPR_CONFIG_PATH", gen_bad_file())
+        .args(["the", FOX])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn options_env() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .env("GREPR_OPTIONS", "-i --count")
+        .args(["THE", FOX])
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    // The command line comes last, so it wins
+    Command::cargo_bin(PRG)?
+        .env("GREPR_OPTIONS", "--max-count 1")
+        .args(["-c", "-m", "2", "the", NOBODY])
+        .assert()
+        .success()
+        .stdout("2\n");
+
+    Command::cargo_bin(PRG)?
+        .env("GREPR_OPTIONS", "-e 'open")
+        .args(["the", FOX])
+        .assert()
+        .failure()
+        .stderr("Unterminated quote in GREPR_OPTIONS\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn exit_codes() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?.args(["fox", FOX]).assert().code(0);
+    Command::cargo_bin(PRG)?.args(["cat", FOX]).assert().code(1);
+    Command::cargo_bin(PRG)?.args(["fox", &bad]).assert().code(2);
+    Command::cargo_bin(PRG)?.args(["fox", FOX, &bad]).assert().code(2);
+    Command::cargo_bin(PRG)?.args(["*", FOX]).assert().code(2);
+
+    // With -q a match is success despite the errors
+    Command::cargo_bin(PRG)?
+        .args(["-q", "fox", &bad, FOX])
+        .assert()
+        .code(0);
+
+    // With -L success is a file being listed
+    Command::cargo_bin(PRG)?
+        .args(["-L", "fox", FOX, EMPTY])
+        .assert()
+        .code(0);
+    Command::cargo_bin(PRG)?.args(["-L", "fox", FOX]).assert().code(1);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn field_and_path_separators() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--field-match-separator", " | ", "-n", "-A", "1", "morning", BUSTLE, FOX])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "tests/inputs/bustle.txt | 2 | The morning after death\n",
+            "tests/inputs/bustle.txt-3-Is solemnest of industries\n",
+        ));
+
+    Command::cargo_bin(PRG)?
+        .args(["--path-separator", "\\", "-c", "fox", FOX, EMPTY])
+        .assert()
+        .success()
+        .stdout("tests\\inputs\\fox.txt:1\ntests\\inputs\\empty.txt:0\n");
+
+    // Standard input keeps its name
+    Command::cargo_bin(PRG)?
+        .args(["--path-separator", "\\", "-l", "fox", FOX, "-"])
+        .write_stdin("a fox\n")
+        .assert()
+        .success()
+        .stdout("tests\\inputs\\fox.txt\n-\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn label() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--label", "piped", "-H", "-n", "fox", "-"])
+        .write_stdin("a fox\n")
+        .assert()
+        .success()
+        .stdout("piped:1:a fox\n");
+
+    // Other files keep their names
+    Command::cargo_bin(PRG)?
+        .args(["--label", "piped", "-c", "fox", FOX, "-"])
+        .write_stdin("no\n")
+        .assert()
+        .success()
+        .stdout("tests/inputs/fox.txt:1\npiped:0\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--label", "piped", "--json", "fox"])
+        .write_stdin("a fox\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(r#""path":{"text":"piped"}"#));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files_from() -> TestResult {
+    // The list comes from stdin, and its empty lines are passed over
+    Command::cargo_bin(PRG)?
+        .args(["--files-from", "-", "-c", "the"])
+        .write_stdin(format!("{}\n\n{}\n", FOX, BUSTLE))
+        .assert()
+        .success()
+        .stdout(format!("{}:1\n{}:1\n", FOX, BUSTLE));
+
+    // The listed files follow any named on the command line
+    let list = std::env::temp_dir().join(gen_bad_file());
+    fs::write(&list, format!("{}\n", BUSTLE))?;
+    let output = Command::cargo_bin(PRG)?
+        .args(["--files-from", &list.display().to_string(), "-l", "the", FOX])
+        .output()?;
+    fs::remove_file(&list)?;
+    assert_eq!(String::from_utf8(output.stdout)?, format!("{}\n{}\n", FOX, BUSTLE));
+
+    // An empty list is nothing to search, rather than stdin
+    Command::cargo_bin(PRG)?
+        .args(["--files-from", "-", "fox"])
+        .write_stdin("")
+        .assert()
+        .code(1)
+        .stdout("");
+
+    Command::cargo_bin(PRG)?
+        .args(["--files-from", "tests/inputs/nonexistent.txt", "fox"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::starts_with("tests/inputs/nonexistent.txt: "));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn files0_from() -> TestResult {
+    // Names are ended by NUL, so they may hold newlines
+    let dir = std::env::temp_dir().join(gen_bad_file());
+    fs::create_dir(&dir)?;
+    let odd = dir.join("a\nb.txt");
+    fs::write(&odd, "a fox\n")?;
+    let list = format!("{}\0{}\0", odd.display(), FOX);
+    let output = Command::cargo_bin(PRG)?
+        .args(["--files0-from", "-", "-c", "fox"])
+        .write_stdin(list)
+        .output()?;
+    fs::remove_dir_all(&dir)?;
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        format!("{}:1\n{}:1\n", odd.display(), FOX)
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(target_os = "linux")]
+fn read_error() -> TestResult {
+    // A file that opens but can't be read is warned about, and the other
+    // files still searched
+    Command::cargo_bin(PRG)?
+        .args(["fox", "/proc/self/mem", FOX])
+        .assert()
+        .code(2)
+        .stdout(format!("{}:The quick brown fox jumps over the lazy dog.\n", FOX))
+        .stderr(predicate::str::starts_with("/proc/self/mem: "));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn directories() -> TestResult {
+    // Directories are reported, but the other files still searched
+    Command::cargo_bin(PRG)?
+        .args(["-d", "read", "-c", "fox", INPUTS_DIR, FOX])
+        .assert()
+        .code(2)
+        .stdout("tests/inputs/fox.txt:1\n")
+        .stderr("tests/inputs is a directory\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["-d", "skip", "-c", "fox", INPUTS_DIR, FOX])
+        .assert()
+        .success()
+        .stdout("tests/inputs/fox.txt:1\n")
+        .stderr("");
+
+    Command::cargo_bin(PRG)?
+        .args(["--directories", "recurse", "-l", "fox", INPUTS_DIR])
+        .assert()
+        .success()
+        .stdout("tests/inputs/fox.txt\n");
+
+    // Directories named are recursed into without -r, unless --grep-compat
+    Command::cargo_bin(PRG)?
+        .args(["-l", "fox", INPUTS_DIR])
+        .assert()
+        .success()
+        .stdout("tests/inputs/fox.txt\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--grep-compat", "-l", "fox", INPUTS_DIR])
+        .assert()
+        .code(2)
+        .stderr("tests/inputs is a directory\n");
+
+    // Without a terminal to read, no files still means stdin
+    Command::cargo_bin(PRG)?
+        .args(["-c", "fox"])
+        .write_stdin("a fox\n")
+        .assert()
+        .success()
+        .stdout("1\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn exclude_from() -> TestResult {
+    let list = std::env::temp_dir().join(gen_bad_file());
+    fs::write(&list, "# Generated\n[bef]*\n\n")?;
+    let list_name = list.display().to_string();
+    let output = Command::cargo_bin(PRG)?
+        .args(["-ri", "--exclude-from", &list_name, "then", INPUTS_DIR])
+        .output()?;
+    fs::remove_file(&list)?;
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        fs::read_to_string("tests/expected/the.recursive.insensitive.include")?
+    );
+
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--exclude-from", &gen_bad_file(), "then", INPUTS_DIR])
+        .assert()
+        .code(2);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(feature = "search-zip")]
+#[test]
+fn search_zip() -> TestResult {
+    for ext in ["gz", "bz2", "xz", "zst"] {
+        let file = format!("tests/compressed/bustle.txt.{}", ext);
+        Command::cargo_bin(PRG)?
+            .args(["--search-zip", "-n", "The", &file])
+            .assert()
+            .success()
+            .stdout(concat!(
+                "1:The bustle in a house\n",
+                "2:The morning after death\n",
+                "6:The sweeping up the heart,\n",
+            ));
+    }
+
+    // Without the flag the compressed data is searched as it is
+    Command::cargo_bin(PRG)?
+        .args(["-c", "The", "tests/compressed/bustle.txt.gz"])
+        .assert()
+        .code(1)
+        .stdout("0\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(not(feature = "gzip"))]
+#[test]
+fn search_zip_unsupported() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--search-zip", "The", "tests/compressed/bustle.txt.gz"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("\"gzip\" feature"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(unix)]
+#[test]
+fn preprocessor() -> TestResult {
+    use std::os::unix::fs::PermissionsExt;
+
+    let script = std::env::temp_dir().join(gen_bad_file());
+    fs::write(&script, "#!/bin/sh\ntr a-z A-Z < \"$1\"\n")?;
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+    let pre = script.display().to_string();
+
+    let upper = Command::cargo_bin(PRG)?
+        .args(["--pre", &pre, "FOX", FOX])
+        .output()?;
+    // Files that don't match the glob are searched as they are
+    let globbed = Command::cargo_bin(PRG)?
+        .args(["--pre", &pre, "--pre-glob", "b*", "-c", "THE", FOX, BUSTLE])
+        .output()?;
+    fs::remove_file(&script)?;
+
+    assert_eq!(
+        String::from_utf8(upper.stdout)?,
+        "THE QUICK BROWN FOX JUMPS OVER THE LAZY DOG.\n"
+    );
+    assert_eq!(
+        String::from_utf8(globbed.stdout)?,
+        "tests/inputs/fox.txt:0\ntests/inputs/bustle.txt:3\n"
+    );
+
+    Command::cargo_bin(PRG)?
+        .args(["--pre", "false", "fox", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("tests/inputs/fox.txt: false failed"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn debug() -> TestResult {
+    let dir = std::env::temp_dir().join(gen_bad_file());
+    fs::create_dir_all(dir.join(".git"))?;
+    fs::write(dir.join(".gitignore"), "*.log\n")?;
+    fs::write(dir.join("debug.log"), "fox\n")?;
+    fs::write(dir.join("main.txt"), "fox\n")?;
+    let dir = fs::canonicalize(&dir)?;
+    let dir_name = dir.display().to_string();
+
+    let debug = Command::cargo_bin(PRG)?
+        .args(["-r", "-H", "--debug", "fox", &dir_name])
+        .output()?;
+    let trace = Command::cargo_bin(PRG)?
+        .args(["-r", "--trace", "fox", &dir_name])
+        .output()?;
+    let quiet = Command::cargo_bin(PRG)?
+        .args(["-r", "fox", &dir_name])
+        .output()?;
+    fs::remove_dir_all(&dir)?;
+
+    // The diagnostics go to stderr, leaving the results alone
+    let expected = format!("{}/main.txt:fox\n", dir_name);
+    assert_eq!(String::from_utf8(debug.stdout)?, expected);
+    let debug = String::from_utf8(debug.stderr)?;
+    assert!(debug.contains(&format!(
+        "{0}/debug.log: ignored by \"*.log\" in {0}/.gitignore",
+        dir_name
+    )));
+    assert!(debug.contains("compiled \"fox\""));
+    assert!(!debug.contains("main.txt: selected"));
+    assert!(String::from_utf8(trace.stderr)?.contains(&format!("{}/main.txt: selected", dir_name)));
+    assert!(quiet.stderr.is_empty());
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn no_unicode() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-o", r"\w+"])
+        .write_stdin("café 42\n")
+        .assert()
+        .success()
+        .stdout("café\n42\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["-o", "--no-unicode", r"\w+"])
+        .write_stdin("café 42\n")
+        .assert()
+        .success()
+        .stdout("caf\n42\n");
+
+    // Word boundaries and -w go by ASCII too, and --unicode given last wins
+    Command::cargo_bin(PRG)?
+        .args(["--no-unicode", "-w", "fé"])
+        .write_stdin("éfé\n")
+        .assert()
+        .success()
+        .stdout("éfé\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--no-unicode", "--unicode", "-w", "fé"])
+        .write_stdin("éfé\n")
+        .assert()
+        .code(1);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn regex_size_limit() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--regex-size-limit", "1K", r"\w{20}", FOX])
+        .assert()
+        .code(2)
+        .stderr(
+            "Pattern \"\\w{20}\" is too big to compile within 1024 bytes; \
+             raise --regex-size-limit\n",
+        );
+
+    // The limit can be raised as far as memory allows, and the DFA cache
+    // shrunk without changing the result
+    Command::cargo_bin(PRG)?
+        .args([
+            "--regex-size-limit",
+            "100M",
+            "--dfa-size-limit",
+            "64K",
+            r"\w{5}",
+            FOX,
+        ])
+        .assert()
+        .success()
+        .stdout("The quick brown fox jumps over the lazy dog.\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--dfa-size-limit", "lots", "fox", FOX])
+        .assert()
+        .code(2)
+        .stderr("Invalid size limit \"lots\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn hyperlink_format() -> TestResult {
+    let fox = fs::canonicalize(FOX)?.display().to_string();
+    let output = Command::cargo_bin(PRG)?
+        .args(["--color=always", "--hyperlink-format", "x://{path}#{line}:{column}"])
+        .args(["-l", "fox", FOX, EMPTY])
+        .env("GREP_COLORS", "fn=")
+        .output()?;
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        format!(
+            "\x1b]8;;x://{}#1:1\x1b\\tests/inputs/fox.txt\x1b]8;;\x1b\\\n",
+            fox
+        )
+    );
+
+    // Lines link to their first match
+    let output = Command::cargo_bin(PRG)?
+        .args(["--color=always", "--hyperlink-format", "x://{path}#{line}:{column}"])
+        .args(["-H", "jumps", FOX])
+        .env("GREP_COLORS", "fn=:ms=:se=")
+        .output()?;
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        format!(
+            "\x1b]8;;x://{}#1:21\x1b\\tests/inputs/fox.txt\x1b]8;;\x1b\\:{}\n",
+            fox, "The quick brown fox jumps over the lazy dog."
+        )
+    );
+
+    // Without color there are no links
+    Command::cargo_bin(PRG)?
+        .args(["--hyperlink-format", "vscode", "-l", "fox", FOX])
+        .assert()
+        .success()
+        .stdout("tests/inputs/fox.txt\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--hyperlink-format", "x://{file}", "fox", FOX])
+        .assert()
+        .code(2)
+        .stderr("Invalid hyperlink format \"x://{file}\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn pager_not_a_tty() -> TestResult {
+    // Output that isn't going to a terminal is never paged
+    Command::cargo_bin(PRG)?
+        .args(["--pager", "fox", FOX])
+        .env("PAGER", "false")
+        .assert()
+        .success()
+        .stdout("The quick brown fox jumps over the lazy dog.\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn unique() -> TestResult {
+    let input = "ERROR disk full\nok\nERROR timeout\nERROR disk full\nERROR disk full\n";
+    Command::cargo_bin(PRG)?
+        .args(["-n", "--unique", "ERROR"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("1:ERROR disk full\n3:ERROR timeout\n");
+
+    // Counts come with the first line of each kind, once the file is done
+    Command::cargo_bin(PRG)?
+        .args(["--unique-count", "ERROR"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("      3 ERROR disk full\n      1 ERROR timeout\n");
+
+    // With -o it's the matches that have to differ
+    Command::cargo_bin(PRG)?
+        .args(["-o", "--unique-count", r"ERROR \w+"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("      3 ERROR disk\n      1 ERROR timeout\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn format() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([
+            "--format",
+            "{path}:{line}:{column} [{match}] {word}",
+            r"(?P<word>f\w+)",
+            FOX,
+            BUSTLE,
+        ])
+        .assert()
+        .success()
+        .stdout(format!("{}:1:17 [fox] fox\n{}:2:14 [fter] fter\n", FOX, BUSTLE));
+
+    // Braces are doubled to be printed
+    Command::cargo_bin(PRG)?
+        .args(["--format", "{{{text}}}", "fox", FOX])
+        .assert()
+        .success()
+        .stdout("{The quick brown fox jumps over the lazy dog.}\n");
+
+    Command::cargo_bin(PRG)?
+        .args(["--format", "{path", "fox", FOX])
+        .assert()
+        .code(2)
+        .stderr("Invalid format \"{path\"\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn initial_tab() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--initial-tab", "-n", "-H", "fox", FOX])
+        .assert()
+        .success()
+        .stdout("tests/inputs/fox.txt:1:\tThe quick brown fox jumps over the lazy dog.\n");
+
+    // -T is the short form, as in GNU grep
+    Command::cargo_bin(PRG)?
+        .args(["-T", "-n", "the", FOX])
+        .assert()
+        .success()
+        .stdout("1:\tThe quick brown fox jumps over the lazy dog.\n");
+
+    // Without a prefix there's nothing to line up after
+    Command::cargo_bin(PRG)?
+        .args(["--initial-tab", "fox", FOX])
+        .assert()
+        .success()
+        .stdout("The quick brown fox jumps over the lazy dog.\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn in_process() -> TestResult {
+    let config = grepr::Config::builder()
+        .pattern("fox")
+        .file(FOX)
+        .line_number(true)
+        .build()?;
+    let mut out = vec![];
+    let summary = grepr::run_with_output(config, &mut out)?;
+    assert_eq!(summary.status.code(), 0);
+    assert_eq!(summary.stats.files_searched, 1);
+    assert_eq!(summary.stats.matched_lines, 1);
+    assert_eq!(
+        String::from_utf8(out)?,
+        "1:The quick brown fox jumps over the lazy dog.\n"
+    );
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn progress() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--progress", "fox", FOX, EMPTY])
+        .assert()
+        .success()
+        .stderr(predicate::str::ends_with(
+            "\r2/2 files searched, 45 bytes, 1 matched lines\n",
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn invalid_utf8() -> TestResult {
+    // A byte that isn't UTF-8 neither ends the search nor shifts offsets,
+    // and is printed as it was
+    Command::cargo_bin(PRG)?
+        .args(["-b", "foo"])
+        .write_stdin(b"abc\nfo\xffo foo\nfoo end\n".to_vec())
+        .assert()
+        .success()
+        .stdout(&b"4:fo\xffo foo\n13:foo end\n"[..]);
+
+    // Columns and offsets within the line count bytes
+    let line = b"a\xffb fox\n".to_vec();
+    Command::cargo_bin(PRG)?
+        .args(["-o", "-b", "fox"])
+        .write_stdin(line.clone())
+        .assert()
+        .success()
+        .stdout("4:fox\n");
+    Command::cargo_bin(PRG)?
+        .args(["--column", "fox"])
+        .write_stdin(line.clone())
+        .assert()
+        .success()
+        .stdout(&b"5:a\xffb fox\n"[..]);
+    // JSON gives such lines as base64 so the offsets still line up
+    Command::cargo_bin(PRG)?
+        .args(["--json", "fox"])
+        .write_stdin(line.clone())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(concat!(
+            r#""lines":{"bytes":"Yf9iIGZveAo="},"line_number":1,"absolute_offset":0,"#,
+            r#""submatches":[{"match":{"text":"fox"},"start":4,"end":7}]"#,
+        )));
+
+    // A pattern can match the byte itself
+    Command::cargo_bin(PRG)?
+        .args(["-c", r"(?-u:\xff)b"])
+        .write_stdin(line)
+        .assert()
+        .success()
+        .stdout("1\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(unix)]
+#[test]
+fn non_utf8_filename() -> TestResult {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    // Such names are valid on Unix, and only their display is lossy
+    let dir = std::env::temp_dir().join(gen_bad_file());
+    fs::create_dir_all(&dir)?;
+    let file = dir.join(OsStr::from_bytes(b"caf\xe9.txt"));
+    fs::write(&file, "fox\n")?;
+    let expected = format!("{}/caf\u{fffd}.txt:fox\n", dir.display());
+
+    Command::cargo_bin(PRG)?
+        .args(["-rH", "fox"])
+        .arg(&dir)
+        .assert()
+        .success()
+        .stdout(expected.clone());
+
+    // Named directly, the file is still opened by its real name
+    Command::cargo_bin(PRG)?
+        .args(["-H", "fox"])
+        .arg(&file)
+        .assert()
+        .success()
+        .stdout(expected);
+
+    // JSON keeps the name as it is, in base64
+    Command::cargo_bin(PRG)?
+        .args(["--json", "fox"])
+        .arg(&file)
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains(r#"{"type":"begin","data":{"path":{"bytes":"#)
+                .and(predicate::str::contains("\u{fffd}").not()),
+        );
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+// --------------------------------------------------
+#[cfg(unix)]
+#[test]
+fn broken_pipe() -> TestResult {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    // More than a pipe holds, so the reader closing it is noticed
+    let file = std::env::temp_dir().join(gen_bad_file());
+    let lines: String = (0..100_000).map(|i| format!("{} fox\n", i)).collect();
+    fs::write(&file, lines)?;
+
+    for args in [&["fox"][..], &["-j2", "fox", "-"]] {
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+            .args(args)
+            .arg(&file)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut first = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut first)?;
+        assert!(first.ends_with("0 fox\n"));
+        let output = child.wait_with_output()?;
+        assert_eq!(String::from_utf8(output.stderr)?, "");
+        assert_eq!(output.status.code(), Some(0));
+    }
+
+    fs::remove_file(&file)?;
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn broken_pipe_parallel() -> TestResult {
+    use std::io::Read;
+    use std::process::Stdio;
+    use std::time::{Duration, Instant};
+
+    // Enough files, each more than a pipe holds, to fill the channel to
+    // the printing thread before the reader goes away
+    let dir = std::env::temp_dir().join(gen_bad_file());
+    fs::create_dir(&dir)?;
+    let lines: String = (0..20_000).map(|i| format!("{} needle\n", i)).collect();
+    for i in 0..64 {
+        fs::write(dir.join(format!("{}.txt", i)), &lines)?;
+    }
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+        .args(["-j", "8", "needle"])
+        .arg(&dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Reading slowly lets the rest fill the channel while the first file
+    // is printed
+    let mut stdout = child.stdout.take().unwrap();
+    stdout.read_exact(&mut [0; 10])?;
+    std::thread::sleep(Duration::from_secs(1));
+    drop(stdout);
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() > Duration::from_secs(60) {
+            child.kill()?;
+            panic!("still running after stdout closed");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    assert_eq!(status.code(), Some(0));
+
+    fs::remove_dir_all(&dir)?;
     Ok(())
 }